@@ -0,0 +1,158 @@
+//! Throughput of a 10 MB burst through the socket buffers.
+//!
+//! The send side offers the burst to a [`SendQueue`] in OCX-sized chunks and, when a full
+//! queue refuses one, waits for the writer to catch up and offers it again, as the OCX does
+//! on `OnWrite`. Meanwhile a writer task drains the queue into a loopback connection. The receive side fills a bounded `BytesMut` the way the reader task does
+//! and empties it with the copy-and-advance reads of `receive_socket`.
+//!
+//! Run with `cargo bench --bench burst`.
+
+// Only part of each shared module is used here, and their test modules are built without
+// the tests themselves.
+#![allow(dead_code, unused_imports)]
+
+#[path = "../src/config.rs"]
+mod config;
+#[path = "../src/subscribers.rs"]
+mod subscribers;
+
+#[path = "../src/network"]
+mod network {
+    #![allow(clippy::collapsible_if)]
+
+    pub mod framing;
+    pub mod recorder;
+    pub mod send_queue;
+}
+
+#[path = "../src/protocol"]
+mod protocol {
+    pub mod message;
+}
+
+use bytes::{Buf, BytesMut};
+use std::io::Read;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt;
+use tokio::runtime::Runtime;
+
+use network::send_queue::{Refused, SendQueue};
+
+const BURST_LEN: usize = 10 * 1024 * 1024;
+/// Bytes per `send_socket` call and per reader task read.
+const CHUNK_LEN: usize = 4096;
+/// Bytes per `receive_socket` call.
+const RECEIVE_LEN: usize = 2048;
+/// The default `[network.buffers]` limits.
+const LIMIT: usize = 1024 * 1024;
+const RUNS: usize = 5;
+
+/// 10 MB of channel messages, as a large paste would produce.
+fn burst() -> Vec<u8> {
+    let line = format!("PRIVMSG #room :{}\r\n", "x".repeat(400));
+    line.as_bytes()
+        .iter()
+        .copied()
+        .cycle()
+        .take(BURST_LEN / line.len() * line.len())
+        .collect()
+}
+
+fn send_burst(rt: &Runtime, data: &[u8]) -> Duration {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).unwrap();
+        received.len()
+    });
+
+    let mut stream = rt.block_on(tokio::net::TcpStream::connect(addr)).unwrap();
+    let queue = Arc::new(SendQueue::new(LIMIT));
+    let start = Instant::now();
+    let writer = {
+        let queue = queue.clone();
+        rt.spawn(async move {
+            while let Some(line) = queue.next(&mut None).await {
+                stream.write_all(&line).await.unwrap();
+            }
+            stream.shutdown().await.unwrap();
+        })
+    };
+
+    for chunk in data.chunks(CHUNK_LEN) {
+        while let Err(refused) = queue.try_push(chunk) {
+            assert_eq!(refused, Refused::Full);
+            // Stands in for the OnWrite the writer task would post.
+            while !queue.take_unblocked() {
+                std::thread::sleep(Duration::from_micros(50));
+            }
+        }
+    }
+    queue.close();
+    rt.block_on(writer).unwrap();
+    assert_eq!(server.join().unwrap(), data.len());
+    start.elapsed()
+}
+
+fn receive_burst(data: &[u8]) -> Duration {
+    let mut rx_buffer = BytesMut::new();
+    let mut out = [0u8; RECEIVE_LEN];
+    let mut chunks = data.chunks(CHUNK_LEN);
+    let mut received = 0;
+    let start = Instant::now();
+    loop {
+        // The reader task stops once the buffer holds the limit.
+        while rx_buffer.len() < LIMIT {
+            match chunks.next() {
+                Some(chunk) => rx_buffer.extend_from_slice(chunk),
+                None => break,
+            }
+        }
+        if rx_buffer.is_empty() {
+            break;
+        }
+        while !rx_buffer.is_empty() {
+            let n = out.len().min(rx_buffer.len());
+            out[..n].copy_from_slice(&rx_buffer[..n]);
+            rx_buffer.advance(n);
+            received += n;
+        }
+    }
+    assert_eq!(received, data.len());
+    start.elapsed()
+}
+
+fn report(name: &str, len: usize, mut times: Vec<Duration>) {
+    times.sort();
+    let best = times[0];
+    let median = times[times.len() / 2];
+    let mib = len as f64 / (1024.0 * 1024.0);
+    println!(
+        "{:<8} {:.1} MiB: best {:?} ({:.0} MiB/s), median {:?} ({:.0} MiB/s)",
+        name,
+        mib,
+        best,
+        mib / best.as_secs_f64(),
+        median,
+        mib / median.as_secs_f64()
+    );
+}
+
+fn main() {
+    let rt = Runtime::new().unwrap();
+    let data = burst();
+    report(
+        "send",
+        data.len(),
+        (0..RUNS).map(|_| send_burst(&rt, &data)).collect(),
+    );
+    report(
+        "receive",
+        data.len(),
+        (0..RUNS).map(|_| receive_burst(&data)).collect(),
+    );
+}
@@ -168,6 +168,295 @@ pub struct LicensingConfig {
     pub hash: String, // Hex string of {5954F421-4768-46bc-B331-3DC37B1E7048}
 }
 
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyKind {
+    #[default]
+    Socks5,
+    /// HTTP `CONNECT` tunnel.
+    Http,
+}
+
+/// Upstream proxy used for every OCX socket connection (`[network.proxy]`).
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct ProxyConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub kind: ProxyKind,
+    #[serde(default)]
+    pub host: String,
+    #[serde(default)]
+    pub port: u16,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Seconds allowed for reaching the proxy and completing the handshake (default 30).
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    /// Raw IRC over TCP (optionally TLS, see `[network.tls]`).
+    #[default]
+    Tcp,
+    /// One IRC line per WebSocket message, over `ws://` or `wss://`.
+    WebSocket,
+}
+
+/// A per-host transport rule (`[[network.transports]]`). `host` accepts `*` wildcards.
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct TransportRule {
+    pub host: String,
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub kind: TransportKind,
+    /// WebSocket URL to connect to. Defaults to `ws://<host>:<port>/`, or `wss://` when a
+    /// TLS rule matches the host.
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Value sent in `Sec-WebSocket-Protocol`, e.g. `text.ircv3.net`.
+    #[serde(default)]
+    pub subprotocol: Option<String>,
+    /// Send lines as binary frames instead of text frames.
+    #[serde(default)]
+    pub binary: bool,
+}
+
+/// A per-host TLS rule (`[[network.tls.rules]]`). `host` accepts `*` wildcards.
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct TlsRule {
+    pub host: String,
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// SNI / certificate name to verify against, if it differs from `host`.
+    #[serde(default)]
+    pub server_name: Option<String>,
+    #[serde(default)]
+    pub accept_invalid_certs: bool,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct TlsConfig {
+    #[serde(default)]
+    pub rules: Vec<TlsRule>,
+    /// Extra PEM files of trusted CA certificates, added to the bundled web PKI roots.
+    #[serde(default)]
+    pub ca_bundles: Vec<PathBuf>,
+    /// Development switch: skip certificate verification for every TLS connection.
+    #[serde(default)]
+    pub accept_invalid_certs: bool,
+}
+
+/// Rewrites OCX connection targets before resolution (`[[network.rewrite]]`).
+///
+/// Both sides are `host:port`; `from` accepts `*` wildcards in either part and may omit
+/// the port, while a `to` without a port keeps the original one.
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct RewriteRule {
+    pub from: String,
+    pub to: String,
+}
+
+/// Opt-in reconnect supervisor (`[network.reconnect]`).
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct ReconnectConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Attempts before giving up and reporting the disconnect (default 10, 0 = unlimited).
+    #[serde(default)]
+    pub max_attempts: Option<u32>,
+    /// Delay before the first attempt in milliseconds (default 1000), doubled per attempt.
+    #[serde(default)]
+    pub initial_delay_ms: Option<u64>,
+    /// Upper bound on the backoff delay in milliseconds (default 60000).
+    #[serde(default)]
+    pub max_delay_ms: Option<u64>,
+}
+
+/// Wire-level session recorder (`[network.recorder]`).
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct RecorderConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Directory for `session-<timestamp>.jsonl` transcripts (default `recordings`).
+    #[serde(default)]
+    pub directory: Option<PathBuf>,
+}
+
+/// Direct TCP connect behaviour (`[network.connect]`).
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct ConnectConfig {
+    /// Seconds allowed for resolving and connecting, across all addresses (default 30).
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+    /// Milliseconds before racing the next address while an attempt is pending (default 250).
+    #[serde(default)]
+    pub attempt_delay_ms: Option<u64>,
+    /// Seconds a DNS answer is reused for; 0 disables the cache (default 300).
+    #[serde(default)]
+    pub dns_ttl_secs: Option<u64>,
+}
+
+/// Ordered directory servers to fail over between (`[network.directory]`).
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct DirectoryConfig {
+    /// `host` or `host:port` entries, most preferred first.
+    #[serde(default)]
+    pub servers: Vec<String>,
+    /// Entry from `servers` that last accepted a connection, as saved by older versions.
+    /// Now kept in `directory_state.json` instead, and only read when that file is missing.
+    #[serde(default)]
+    pub last_good: Option<String>,
+}
+
+/// Messages held while a channel socket reconnects (`[network.outbox]`).
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct OutboxConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Seconds after which a held message is discarded instead of sent (default 300).
+    #[serde(default)]
+    pub expiry_secs: Option<u64>,
+    /// File the queue is mirrored to (default `outbox.json`).
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+}
+
+/// Local session bouncer (`[network.bouncer]`).
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct BouncerConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Loopback port the bouncer listens on (default 6680).
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// Room messages kept per detached session (default 500).
+    #[serde(default)]
+    pub backlog_lines: Option<usize>,
+    /// Seconds a detached session is kept waiting for a client (default 3600).
+    #[serde(default)]
+    pub detach_timeout_secs: Option<u64>,
+    /// Start `--bouncer` automatically when nothing is listening (default true).
+    #[serde(default)]
+    pub spawn: Option<bool>,
+    /// Secret every client must present to the bouncer; generated on first use.
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+/// Stalled-connection watchdog in the socket reader (`[network.watchdog]`).
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct WatchdogConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Seconds without inbound traffic before a PING is sent (default 120).
+    #[serde(default)]
+    pub idle_secs: Option<u64>,
+    /// Seconds to wait for any reply to that PING before giving up (default 30).
+    #[serde(default)]
+    pub pong_timeout_secs: Option<u64>,
+}
+
+/// Per-socket buffer limits (`[network.buffers]`).
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct BufferConfig {
+    /// Bytes received but not yet read by the OCX before the reader stops reading (default 1 MiB).
+    #[serde(default)]
+    pub rx_limit: Option<usize>,
+    /// Bytes queued for sending before `send_socket` refuses more until the writer catches
+    /// up and `OnWrite` fires (default 1 MiB).
+    #[serde(default)]
+    pub tx_limit: Option<usize>,
+}
+
+/// Outgoing token bucket, counted in lines (`[network.rate_limit]`).
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct RateLimitConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Lines that may be sent back-to-back before throttling starts (default 5).
+    #[serde(default)]
+    pub burst: Option<u32>,
+    /// Sustained lines per second once the burst is spent (default 1.0).
+    #[serde(default)]
+    pub lines_per_sec: Option<f64>,
+    /// Let PING/PONG/NICK and registration lines skip the limiter (default true).
+    #[serde(default)]
+    pub control_bypass: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplayPacing {
+    /// Deliver inbound chunks with their original spacing.
+    #[default]
+    Timed,
+    /// Deliver one inbound chunk per `/replay` command.
+    Manual,
+}
+
+/// Offline replay of a recorded transcript in place of real connections (`[network.replay]`).
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct ReplayConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub path: PathBuf,
+    #[serde(default)]
+    pub pacing: ReplayPacing,
+    /// Close the connection once the last inbound chunk has been delivered.
+    #[serde(default)]
+    pub close_at_end: bool,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct NetworkConfig {
+    #[serde(default)]
+    pub connect: ConnectConfig,
+    #[serde(default)]
+    pub proxy: ProxyConfig,
+    #[serde(default)]
+    pub tls: TlsConfig,
+    #[serde(default)]
+    pub rewrite: Vec<RewriteRule>,
+    #[serde(default)]
+    pub transports: Vec<TransportRule>,
+    #[serde(default)]
+    pub directory: DirectoryConfig,
+    #[serde(default)]
+    pub reconnect: ReconnectConfig,
+    #[serde(default)]
+    pub outbox: OutboxConfig,
+    #[serde(default)]
+    pub bouncer: BouncerConfig,
+    #[serde(default)]
+    pub recorder: RecorderConfig,
+    #[serde(default)]
+    pub replay: ReplayConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub watchdog: WatchdogConfig,
+    #[serde(default)]
+    pub buffers: BufferConfig,
+    /// Milliseconds `close_socket` waits for queued data to be sent (default 2000, 0 drops it).
+    #[serde(default)]
+    pub linger_ms: Option<u64>,
+}
+
+/// Outbound command middlewares (`[middleware]`).
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct MiddlewareConfig {
+    /// Names of middlewares to skip, e.g. `["split"]`.
+    #[serde(default)]
+    pub disabled: Vec<String>,
+}
+
 #[derive(Serialize, Deserialize, Default, Debug, Clone)]
 pub struct MSNConfig {
     pub session: SessionConfig,
@@ -175,6 +464,10 @@ pub struct MSNConfig {
     pub licensing: LicensingConfig,
     #[serde(default)]
     pub settings: SettingsConfig,
+    #[serde(default)]
+    pub network: NetworkConfig,
+    #[serde(default)]
+    pub middleware: MiddlewareConfig,
 }
 
 pub struct MSNConfigManager {
@@ -235,6 +528,20 @@ impl MSNConfigManager {
         }
     }
 
+    /// Returns the bouncer token, generating and saving one if there is none yet
+    pub fn bouncer_token(&self) -> io::Result<String> {
+        let mut config = self.load()?;
+        match config.network.bouncer.token {
+            Some(ref token) if !token.is_empty() => Ok(token.clone()),
+            _ => {
+                let token = Uuid::new_v4().simple().to_string();
+                config.network.bouncer.token = Some(token.clone());
+                self.save(&config)?;
+                Ok(token)
+            }
+        }
+    }
+
     /// Registers a resource DLL path to track for clean uninstallation
     pub fn register_res_dll(&self, dll_path: &Path) -> io::Result<()> {
         let mut config = self.load()?;
@@ -1070,6 +1070,11 @@ impl OcxWindow {
         lparam: LPARAM,
     ) -> LRESULT {
         unsafe {
+            if message == crate::network::dispatch::socket_event_message() {
+                crate::network::dispatch::drain();
+                return LRESULT(0);
+            }
+
             let user_data = windows::Win32::UI::WindowsAndMessaging::GetWindowLongW(
                 window,
                 windows::Win32::UI::WindowsAndMessaging::GWLP_USERDATA,
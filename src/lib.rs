@@ -0,0 +1,19 @@
+#![allow(non_snake_case)]
+#![allow(clippy::missing_safety_doc)]
+#![allow(unused_unsafe)]
+
+//! The OCX host, its hooks and the Rust network backend.
+//!
+//! `audio`, `host` and `patch` drive the 32-bit OCX and only build for 32-bit Windows; the
+//! rest builds anywhere, so the network and protocol layers can be tested off Windows.
+
+#[cfg(all(windows, target_arch = "x86"))]
+pub mod audio;
+pub mod config;
+#[cfg(all(windows, target_arch = "x86"))]
+pub mod host;
+pub mod network;
+#[cfg(all(windows, target_arch = "x86"))]
+pub mod patch;
+pub mod protocol;
+pub mod subscribers;
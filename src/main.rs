@@ -1,21 +1,47 @@
-#![allow(non_snake_case)]
-#![allow(clippy::missing_safety_doc)]
 #![allow(unused_unsafe)]
 
+#[cfg(all(windows, target_arch = "x86"))]
+use msnchat_rs::host::window::OcxWindow;
+#[cfg(all(windows, target_arch = "x86"))]
+use msnchat_rs::{config, network, patch, protocol};
+#[cfg(all(windows, target_arch = "x86"))]
 use windows::Win32::System::Ole::OleInitialize;
+#[cfg(all(windows, target_arch = "x86"))]
 use windows::core::{GUID, Result};
 
-pub mod audio;
-pub mod config;
-pub mod host;
-pub mod network;
-pub mod patch;
-
-use host::window::OcxWindow;
+#[cfg(not(all(windows, target_arch = "x86")))]
+fn main() {
+    eprintln!("msnchat-rs hosts a 32-bit OCX and only runs on 32-bit Windows");
+    std::process::exit(1);
+}
 
+#[cfg(all(windows, target_arch = "x86"))]
 fn main() -> Result<()> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
+    // Loaded once; the network and middleware modules keep what they need
+    let config = config::MSNConfigManager::new(std::path::Path::new("config.toml"))
+        .load()
+        .unwrap_or_else(|e| {
+            log::warn!("Failed to load config.toml, using defaults: {}", e);
+            config::MSNConfig::default()
+        });
+
+    // Headless bouncer process, started by the first client that finds none running
+    if std::env::args().any(|arg| arg == "--bouncer") {
+        return network::bouncer::run(config.network).map_err(|e| {
+            windows::core::Error::new(
+                windows::core::HRESULT(0x80004005u32 as i32),
+                format!("Bouncer failed: {}", e),
+            )
+        });
+    }
+
+    protocol::middleware::init(&config.middleware);
+    let directory_server = network::failover::preferred_server(&config.network.directory)
+        .unwrap_or_else(|| "dir.irc7.com".to_string());
+    network::init(config.network);
+
     if let Err(e) = unsafe { patch::loader_hook::init_dll_hooks() } {
         log::error!("Failed to init hooks: {}", e);
     }
@@ -26,6 +52,8 @@ fn main() -> Result<()> {
 
     // Create the main window
     let mut main_window = OcxWindow::new()?;
+    network::dispatch::set_notice_sink(patch::command_patch::notify_user);
+    network::dispatch::set_target_window(main_window.hwnd());
 
     // Attach the MSN Chat OCX
     let clsid = GUID::from_values(
@@ -77,7 +105,7 @@ fn main() -> Result<()> {
         let _ = host.put_property("MessageOfTheDay", "Welcome to MSN Chat. Important: MSN does not control or endorse the content, messages or information found in chat. MSN specifically disclaims any liability with regard to these areas. To review the guidelines for use of MSN Chat, go to http://chat.msn.com/conduct.asp.");
         let _ = host.put_property("NickName", &nickname);
         let _ = host.put_property("RoomName", "The Lobby");
-        let _ = host.put_property("Server", "dir.irc7.com");
+        let _ = host.put_property("Server", &directory_server);
         let _ = host.put_property("WhisperContent", "http://test.example.com/whisper");
     }) {
         Ok(_) => {
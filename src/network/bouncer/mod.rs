@@ -0,0 +1,469 @@
+//! Local bouncer that keeps server sessions alive across OCX restarts.
+//!
+//! With `[network.bouncer]` enabled, every OCX socket connects to a `--bouncer` process on
+//! loopback instead of the server, and the bouncer holds the upstream connection. When the
+//! OCX goes away (window closed, crash) a session that has registered and joined a room
+//! stays connected: the bouncer answers PINGs and keeps room messages in a backlog. When a
+//! new OCX connects to the same server, its registration is answered from the original
+//! replies and its JOINs from the tracked room state (topic, NAMES), followed by the
+//! backlog, so the room shows up as if it had never been left. The bare QUIT the OCX sends
+//! as it closes is swallowed; a QUIT with a message goes upstream and ends the session.
+//!
+//! Each loopback connection starts with `BOUNCER <token> <host> <port>`, answered by
+//! `BOUNCER OK` or `BOUNCER ERROR <reason>`. After that the connection carries plain IRC.
+//! The token is `[network.bouncer] token`, generated into config.toml the first time it is
+//! needed, so other local users cannot take over a session; a wrong one gets
+//! `BOUNCER ERROR unauthorized`.
+
+mod state;
+
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, WriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+use crate::config::{BouncerConfig, MSNConfigManager, NetworkConfig};
+use crate::network::dial::DialReport;
+use crate::network::framing::LineFramer;
+use crate::network::manager::{get_rt, open_transport};
+use crate::network::transport::BoxedTransport;
+use state::SessionState;
+
+const DEFAULT_PORT: u16 = 6680;
+const DEFAULT_BACKLOG_LINES: usize = 500;
+const DEFAULT_DETACH_TIMEOUT_SECS: u64 = 3600;
+/// Attempts to reach a bouncer process that is still starting up.
+const ATTACH_RETRIES: u32 = 10;
+const ATTACH_RETRY_DELAY: Duration = Duration::from_millis(300);
+const MAX_PREAMBLE_LEN: usize = 1024;
+
+/// Set in the `--bouncer` process so its own upstream connects are not routed to itself.
+static IS_BOUNCER: AtomicBool = AtomicBool::new(false);
+
+pub fn is_bouncer() -> bool {
+    IS_BOUNCER.load(Ordering::SeqCst)
+}
+
+fn listen_addr(config: &BouncerConfig) -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::LOCALHOST, config.port.unwrap_or(DEFAULT_PORT)))
+}
+
+/// The configured token, or a new one saved to config.toml.
+pub(crate) fn token(config: &BouncerConfig) -> io::Result<String> {
+    match config.token {
+        Some(ref token) if !token.is_empty() => Ok(token.clone()),
+        _ => MSNConfigManager::new(std::path::Path::new("config.toml")).bouncer_token(),
+    }
+}
+
+/// Compares tokens without stopping at the first differing byte.
+fn token_matches(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Reads one CRLF-terminated line byte by byte, so nothing after it is consumed.
+async fn read_preamble<S: AsyncReadExt + Unpin>(stream: &mut S) -> io::Result<String> {
+    let mut line = Vec::new();
+    loop {
+        let mut byte = [0u8; 1];
+        if stream.read(&mut byte).await? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "bouncer connection closed during handshake",
+            ));
+        }
+        if byte[0] == b'\n' {
+            break;
+        }
+        line.push(byte[0]);
+        if line.len() > MAX_PREAMBLE_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "bouncer handshake line too long",
+            ));
+        }
+    }
+    Ok(String::from_utf8_lossy(&line).trim_end().to_string())
+}
+
+// ---------------------------------------------------------------------------------------
+// Client side (the OCX host process)
+// ---------------------------------------------------------------------------------------
+
+/// Starts a detached `--bouncer` process from the current executable.
+fn spawn_bouncer_process() -> io::Result<()> {
+    let exe = std::env::current_exe()?;
+    log::info!("Starting bouncer process {} --bouncer", exe.display());
+    let mut command = std::process::Command::new(exe);
+    command.arg("--bouncer");
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+        const DETACHED_PROCESS: u32 = 0x0000_0008;
+        command.creation_flags(CREATE_NO_WINDOW | DETACHED_PROCESS);
+    }
+    command.spawn().map(|_| ())
+}
+
+/// Connects to the bouncer (starting it if needed) and asks it for a session to `host:port`.
+pub async fn attach(config: &NetworkConfig, host: &str, port: u16) -> io::Result<BoxedTransport> {
+    // Saved before a bouncer is started, so the new process reads the same token.
+    let token = token(&config.bouncer)?;
+    let addr = listen_addr(&config.bouncer);
+    let mut spawned = false;
+    let mut attempt = 0;
+    let mut stream = loop {
+        match TcpStream::connect(addr).await {
+            Ok(stream) => break stream,
+            Err(e) if attempt < ATTACH_RETRIES && config.bouncer.spawn.unwrap_or(true) => {
+                if !spawned {
+                    log::info!("No bouncer listening on {} ({}), starting one", addr, e);
+                    spawn_bouncer_process()?;
+                    spawned = true;
+                }
+                attempt += 1;
+                tokio::time::sleep(ATTACH_RETRY_DELAY).await;
+            }
+            Err(e) => return Err(e),
+        }
+    };
+
+    stream
+        .write_all(format!("BOUNCER {} {} {}\r\n", token, host, port).as_bytes())
+        .await?;
+    let reply = read_preamble(&mut stream).await?;
+    match reply.strip_prefix("BOUNCER ") {
+        Some("OK") => {
+            log::info!("Attached to bouncer session for {}:{}", host, port);
+            Ok(Box::new(stream))
+        }
+        Some(error) => Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("bouncer: {}", error.trim_start_matches("ERROR ")),
+        )),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected bouncer reply: {:?}", reply),
+        )),
+    }
+}
+
+// ---------------------------------------------------------------------------------------
+// Bouncer side (the `--bouncer` process)
+// ---------------------------------------------------------------------------------------
+
+struct Session {
+    host: String,
+    port: u16,
+    state: SessionState,
+    /// Lines for the upstream writer task.
+    upstream: UnboundedSender<Vec<u8>>,
+    /// Lines for the attached client's writer task, if a client is attached.
+    client: Option<UnboundedSender<Vec<u8>>>,
+    /// Bumped on every attach so a stale detach timer does not end a reattached session.
+    generation: u64,
+    closed: bool,
+}
+
+type SessionRef = Arc<Mutex<Session>>;
+
+static SESSIONS: Mutex<Vec<SessionRef>> = Mutex::new(Vec::new());
+
+fn crlf(line: &str) -> Vec<u8> {
+    format!("{}\r\n", line).into_bytes()
+}
+
+/// Runs the bouncer until the process is killed. Called from `main` for `--bouncer`.
+pub fn run(config: NetworkConfig) -> io::Result<()> {
+    IS_BOUNCER.store(true, Ordering::SeqCst);
+    let config = Arc::new(config);
+    let token: Arc<str> = token(&config.bouncer)?.into();
+    get_rt().block_on(serve(config, token))
+}
+
+async fn serve(config: Arc<NetworkConfig>, token: Arc<str>) -> io::Result<()> {
+    let addr = listen_addr(&config.bouncer);
+    let listener = TcpListener::bind(addr).await?;
+    log::info!("Bouncer listening on {}", addr);
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        log::info!("Bouncer client connected from {}", peer);
+        let config = config.clone();
+        let token = token.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_client(stream, config, &token).await {
+                log::warn!("Bouncer client {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+/// Finds a live detached session for `host:port`.
+fn find_detached(host: &str, port: u16) -> Option<SessionRef> {
+    let sessions = SESSIONS.lock().ok()?;
+    sessions
+        .iter()
+        .find(|s| {
+            s.lock().is_ok_and(|s| {
+                !s.closed
+                    && s.client.is_none()
+                    && s.port == port
+                    && s.host.eq_ignore_ascii_case(host)
+            })
+        })
+        .cloned()
+}
+
+fn remove_session(session: &SessionRef) {
+    if let Ok(mut sessions) = SESSIONS.lock() {
+        sessions.retain(|s| !Arc::ptr_eq(s, session));
+    }
+}
+
+/// Dials `host:port` and starts the upstream reader and writer tasks for a new session.
+async fn open_session(
+    config: &Arc<NetworkConfig>,
+    host: &str,
+    port: u16,
+) -> io::Result<SessionRef> {
+    let mut report = DialReport::default();
+    let transport = open_transport(config, host, port, &mut report).await?;
+    log::info!("Bouncer connected upstream to {}:{}", host, port);
+
+    let (mut read_half, mut write_half) = tokio::io::split(transport);
+    let (upstream_tx, mut upstream_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+    let session = Arc::new(Mutex::new(Session {
+        host: host.to_string(),
+        port,
+        state: SessionState::new(
+            config
+                .bouncer
+                .backlog_lines
+                .unwrap_or(DEFAULT_BACKLOG_LINES),
+        ),
+        upstream: upstream_tx,
+        client: None,
+        generation: 0,
+        closed: false,
+    }));
+    if let Ok(mut sessions) = SESSIONS.lock() {
+        sessions.push(session.clone());
+    }
+
+    tokio::spawn(async move {
+        while let Some(data) = upstream_rx.recv().await {
+            if write_half.write_all(&data).await.is_err() {
+                break;
+            }
+        }
+        let _ = write_half.shutdown().await;
+    });
+
+    let reader_session = session.clone();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 4096];
+        let mut framer = LineFramer::new();
+        loop {
+            let n = match read_half.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            let Ok(mut session) = reader_session.lock() else {
+                break;
+            };
+            for line in framer.push(&buf[..n]) {
+                let line = String::from_utf8_lossy(&line).into_owned();
+                let keep = session.state.observe_inbound(&line);
+                match session.client {
+                    Some(ref client) => {
+                        let _ = client.send(crlf(&line));
+                    }
+                    None if line.starts_with("PING") => {
+                        let _ = session
+                            .upstream
+                            .send(crlf(&line.replacen("PING", "PONG", 1)));
+                    }
+                    None if keep => session.state.push_backlog(&line),
+                    None => {}
+                }
+            }
+        }
+
+        log::info!("Bouncer upstream connection closed");
+        if let Ok(mut session) = reader_session.lock() {
+            session.closed = true;
+            // Dropping the sender ends the client writer, which closes the loopback socket.
+            session.client = None;
+        }
+        remove_session(&reader_session);
+    });
+
+    Ok(session)
+}
+
+async fn handle_client(
+    mut stream: TcpStream,
+    config: Arc<NetworkConfig>,
+    token: &str,
+) -> io::Result<()> {
+    let preamble = read_preamble(&mut stream).await?;
+    let mut words = preamble.split(' ');
+    let (host, port) = match (words.next(), words.next(), words.next(), words.next()) {
+        (Some("BOUNCER"), Some(given), _, _) if !token_matches(given, token) => {
+            log::warn!("Bouncer client presented a wrong token, refusing it");
+            stream.write_all(b"BOUNCER ERROR unauthorized\r\n").await?;
+            return Ok(());
+        }
+        (Some("BOUNCER"), Some(_), Some(host), Some(port)) => match port.parse::<u16>() {
+            Ok(port) => (host.to_string(), port),
+            Err(_) => {
+                stream.write_all(b"BOUNCER ERROR bad port\r\n").await?;
+                return Ok(());
+            }
+        },
+        _ => {
+            stream.write_all(b"BOUNCER ERROR bad handshake\r\n").await?;
+            return Ok(());
+        }
+    };
+
+    let (session, reattached) = match find_detached(&host, port) {
+        Some(session) => (session, true),
+        None => match open_session(&config, &host, port).await {
+            Ok(session) => (session, false),
+            Err(e) => {
+                stream
+                    .write_all(format!("BOUNCER ERROR {}\r\n", e).as_bytes())
+                    .await?;
+                return Ok(());
+            }
+        },
+    };
+    stream.write_all(b"BOUNCER OK\r\n").await?;
+
+    let (mut read_half, write_half) = tokio::io::split(stream);
+    let (client_tx, client_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+    let generation = {
+        let Ok(mut s) = session.lock() else {
+            return Ok(());
+        };
+        s.generation += 1;
+        s.client = Some(client_tx.clone());
+        if reattached {
+            log::info!(
+                "Reattaching client to {}:{} ({} room(s))",
+                host,
+                port,
+                s.state.rooms()
+            );
+            for line in s.state.begin_replay() {
+                let _ = client_tx.send(crlf(&line));
+            }
+        }
+        s.generation
+    };
+    tokio::spawn(client_writer(write_half, client_rx));
+
+    let mut buf = [0u8; 4096];
+    let mut framer = LineFramer::new();
+    loop {
+        let n = match read_half.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        let Ok(mut s) = session.lock() else {
+            break;
+        };
+        if s.closed {
+            break;
+        }
+        for line in framer.push(&buf[..n]) {
+            let line = String::from_utf8_lossy(&line).into_owned();
+            match s.state.intercept(&line) {
+                Some(replies) => {
+                    log::debug!("Bouncer answered {:?} locally", line);
+                    for reply in replies {
+                        let _ = client_tx.send(crlf(&reply));
+                    }
+                }
+                None => {
+                    s.state.observe_outbound(&line);
+                    let _ = s.upstream.send(crlf(&line));
+                }
+            }
+        }
+    }
+
+    detach(&session, generation, &config);
+    Ok(())
+}
+
+async fn client_writer(mut writer: WriteHalf<TcpStream>, mut rx: mpsc::UnboundedReceiver<Vec<u8>>) {
+    while let Some(data) = rx.recv().await {
+        if writer.write_all(&data).await.is_err() {
+            break;
+        }
+    }
+    let _ = writer.shutdown().await;
+}
+
+/// Keeps a resumable session for `detach_timeout_secs` after its client leaves, and ends
+/// any other session right away.
+fn detach(session: &SessionRef, generation: u64, config: &NetworkConfig) {
+    let resumable = {
+        let Ok(mut s) = session.lock() else {
+            return;
+        };
+        if s.generation != generation {
+            return;
+        }
+        s.client = None;
+        s.state.resumable() && !s.closed
+    };
+
+    if !resumable {
+        log::info!("Bouncer client left, closing its upstream connection");
+        end_session(session);
+        return;
+    }
+
+    let timeout = Duration::from_secs(
+        config
+            .bouncer
+            .detach_timeout_secs
+            .unwrap_or(DEFAULT_DETACH_TIMEOUT_SECS),
+    );
+    log::info!("Bouncer client left, holding session for {:?}", timeout);
+    let session = session.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(timeout).await;
+        let expired = session
+            .lock()
+            .is_ok_and(|s| s.generation == generation && s.client.is_none());
+        if expired {
+            log::info!("No client reattached within {:?}, ending session", timeout);
+            end_session(&session);
+        }
+    });
+}
+
+fn end_session(session: &SessionRef) {
+    if let Ok(mut s) = session.lock() {
+        let _ = s.upstream.send(b"QUIT\r\n".to_vec());
+        s.closed = true;
+        // Replacing the sender drops the old one, letting the upstream writer finish.
+        s.upstream = mpsc::unbounded_channel().0;
+    }
+    remove_session(session);
+}
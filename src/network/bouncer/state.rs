@@ -0,0 +1,537 @@
+//! What the bouncer remembers about an upstream session so it can be replayed on reattach.
+
+use std::collections::VecDeque;
+
+use crate::protocol::message::Message;
+
+/// Commands the OCX sends to register; swallowed when reattaching to a live session.
+const REGISTRATION_COMMANDS: &[&str] = &["IRCVERS", "AUTH", "PASS", "NICK", "USER"];
+
+/// Server lines kept for a detached client.
+const BACKLOG_COMMANDS: &[&str] = &["PRIVMSG", "NOTICE", "WHISPER", "TOPIC"];
+
+/// Longest synthesized 353 line, leaving room under the 512-byte IRC limit.
+const NAMES_LINE_LEN: usize = 400;
+
+/// A room member as listed in NAMES: an optional IRCX profile (`H,U,GX`), mode flags and nick.
+#[derive(Debug, Clone)]
+struct Member {
+    profile: Option<String>,
+    owner: bool,
+    host: bool,
+    voice: bool,
+    nick: String,
+}
+
+impl Member {
+    fn parse(token: &str) -> Self {
+        let (profile, rest) = match token.rsplit_once(',') {
+            Some((profile, rest)) => (Some(profile.to_string()), rest),
+            None => (None, token),
+        };
+        let nick = rest.trim_start_matches(['.', '@', '+']);
+        let flags = &rest[..rest.len() - nick.len()];
+        Self {
+            profile,
+            owner: flags.contains('.'),
+            host: flags.contains('@'),
+            voice: flags.contains('+'),
+            nick: nick.to_string(),
+        }
+    }
+
+    fn token(&self) -> String {
+        let prefix = if self.owner {
+            "."
+        } else if self.host {
+            "@"
+        } else if self.voice {
+            "+"
+        } else {
+            ""
+        };
+        match self.profile {
+            Some(ref profile) => format!("{},{}{}", profile, prefix, self.nick),
+            None => format!("{}{}", prefix, self.nick),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Room {
+    name: String,
+    /// The server's echo of our own JOIN.
+    join_line: String,
+    /// 332/333 topic replies.
+    topic_lines: Vec<String>,
+    members: Vec<Member>,
+    /// Set while a 353 burst is being collected, until 366.
+    collecting_names: bool,
+}
+
+impl Room {
+    fn member_mut(&mut self, nick: &str) -> Option<&mut Member> {
+        self.members
+            .iter_mut()
+            .find(|m| m.nick.eq_ignore_ascii_case(nick))
+    }
+
+    fn remove_member(&mut self, nick: &str) {
+        self.members.retain(|m| !m.nick.eq_ignore_ascii_case(nick));
+    }
+}
+
+/// Registration replies recorded after one client registration line.
+#[derive(Debug, Clone)]
+struct RegistrationStep {
+    command: String,
+    replies: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct SessionState {
+    /// Server name from the 001 prefix.
+    server: String,
+    nick: String,
+    registered: bool,
+    /// Server lines seen during the first registration, grouped by the client line they
+    /// answered. The first step (empty command) holds anything sent before the client spoke.
+    registration: Vec<RegistrationStep>,
+    recording_registration: bool,
+    rooms: Vec<Room>,
+    backlog: VecDeque<String>,
+    backlog_limit: usize,
+    /// Steps already replayed to the current client.
+    replay_cursor: usize,
+    /// Rooms the current client has not rejoined yet.
+    pending_rooms: Vec<String>,
+    replaying: bool,
+    /// Set once a client QUIT has been passed upstream.
+    quit: bool,
+}
+
+impl SessionState {
+    pub fn new(backlog_limit: usize) -> Self {
+        Self {
+            registration: vec![RegistrationStep {
+                command: String::new(),
+                replies: Vec::new(),
+            }],
+            recording_registration: true,
+            backlog_limit,
+            ..Default::default()
+        }
+    }
+
+    /// Whether the session is worth keeping when the client goes away.
+    pub fn resumable(&self) -> bool {
+        self.registered && !self.rooms.is_empty() && !self.quit
+    }
+
+    pub fn rooms(&self) -> usize {
+        self.rooms.len()
+    }
+
+    fn room_mut(&mut self, name: &str) -> Option<&mut Room> {
+        self.rooms
+            .iter_mut()
+            .find(|r| r.name.eq_ignore_ascii_case(name))
+    }
+
+    fn is_self(&self, nick: Option<&str>) -> bool {
+        nick.is_some_and(|n| n.eq_ignore_ascii_case(&self.nick))
+    }
+
+    /// Tracks a line the client sent upstream.
+    pub fn observe_outbound(&mut self, line: &str) {
+        if !self.recording_registration {
+            return;
+        }
+        let command = Message::parse(line)
+            .map(|m| m.command.to_string())
+            .unwrap_or_default();
+        if REGISTRATION_COMMANDS.contains(&command.as_str()) {
+            self.registration.push(RegistrationStep {
+                command,
+                replies: Vec::new(),
+            });
+        } else if command == "JOIN" {
+            self.recording_registration = false;
+        }
+    }
+
+    /// Tracks a line received from upstream. Returns true if it should be kept in the
+    /// backlog while no client is attached.
+    pub fn observe_inbound(&mut self, line: &str) -> bool {
+        let Some(message) = Message::parse(line) else {
+            return false;
+        };
+        let command = message.command.to_string();
+        let nick = message.source();
+        // Middle parameters followed by the trailing one.
+        let params: Vec<&str> = message
+            .params
+            .iter()
+            .map(String::as_str)
+            .chain(message.trailing.as_deref())
+            .collect();
+
+        if self.recording_registration {
+            if let Some(step) = self.registration.last_mut() {
+                step.replies.push(line.to_string());
+            }
+            if command == "376" || command == "422" {
+                self.recording_registration = false;
+            }
+        }
+
+        match command.as_str() {
+            "001" => {
+                self.registered = true;
+                self.server = nick.unwrap_or_default().to_string();
+                if let Some(nick) = params.first() {
+                    self.nick = nick.to_string();
+                }
+            }
+            "JOIN" => {
+                let Some(room) = params.last().map(|r| r.to_string()) else {
+                    return false;
+                };
+                if self.is_self(nick) {
+                    self.rooms.retain(|r| !r.name.eq_ignore_ascii_case(&room));
+                    self.rooms.push(Room {
+                        name: room,
+                        join_line: line.to_string(),
+                        topic_lines: Vec::new(),
+                        members: Vec::new(),
+                        collecting_names: false,
+                    });
+                } else if let Some(nick) = nick {
+                    // IRCX puts the joiner's profile before the room name.
+                    let profile = (params.len() > 1).then(|| params[0].to_string());
+                    if let Some(room) = self.room_mut(&room) {
+                        room.remove_member(nick);
+                        room.members.push(Member {
+                            profile,
+                            owner: false,
+                            host: false,
+                            voice: false,
+                            nick: nick.to_string(),
+                        });
+                    }
+                }
+            }
+            "PART" => {
+                if let (Some(nick), Some(room)) = (nick, params.first()) {
+                    if self.is_self(Some(nick)) {
+                        self.rooms.retain(|r| !r.name.eq_ignore_ascii_case(room));
+                    } else if let Some(room) = self.room_mut(room) {
+                        room.remove_member(nick);
+                    }
+                }
+            }
+            "KICK" => {
+                if let (Some(room), Some(target)) = (params.first(), params.get(1)) {
+                    if self.is_self(Some(target)) {
+                        self.rooms.retain(|r| !r.name.eq_ignore_ascii_case(room));
+                    } else if let Some(room) = self.room_mut(room) {
+                        room.remove_member(target);
+                    }
+                }
+            }
+            "QUIT" => {
+                if let Some(nick) = nick {
+                    for room in &mut self.rooms {
+                        room.remove_member(nick);
+                    }
+                }
+            }
+            "NICK" => {
+                if let (Some(old), Some(new)) = (nick, params.first()) {
+                    if self.is_self(Some(old)) {
+                        self.nick = new.to_string();
+                    }
+                    for room in &mut self.rooms {
+                        if let Some(member) = room.member_mut(old) {
+                            member.nick = new.to_string();
+                        }
+                    }
+                }
+            }
+            "MODE" => {
+                if let Some(room_name) = params.first().map(|r| r.to_string()) {
+                    let modes = params.get(1).copied().unwrap_or_default();
+                    let mut args = params.iter().skip(2);
+                    if let Some(room) = self.room_mut(&room_name) {
+                        let mut adding = true;
+                        for mode in modes.chars() {
+                            match mode {
+                                '+' => adding = true,
+                                '-' => adding = false,
+                                'q' | 'o' | 'v' => {
+                                    let Some(target) = args.next() else {
+                                        break;
+                                    };
+                                    if let Some(member) = room.member_mut(target) {
+                                        match mode {
+                                            'q' => member.owner = adding,
+                                            'o' => member.host = adding,
+                                            _ => member.voice = adding,
+                                        }
+                                    }
+                                }
+                                // Other modes with an argument.
+                                'k' | 'l' | 'b' if adding || mode == 'b' => {
+                                    args.next();
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+                }
+            }
+            "332" | "333" => {
+                if let Some(room_name) = params.get(1).map(|r| r.to_string()) {
+                    if let Some(room) = self.room_mut(&room_name) {
+                        if command == "332" {
+                            room.topic_lines.clear();
+                        }
+                        room.topic_lines.push(line.to_string());
+                    }
+                }
+            }
+            "353" => {
+                // `353 <nick> <type> <room> :<names>`
+                if let (Some(room_name), Some(names)) =
+                    (params.get(params.len().saturating_sub(2)), params.last())
+                {
+                    let room_name = room_name.to_string();
+                    if let Some(room) = self.room_mut(&room_name) {
+                        if !room.collecting_names {
+                            room.members.clear();
+                            room.collecting_names = true;
+                        }
+                        room.members.extend(
+                            names
+                                .split(' ')
+                                .filter(|t| !t.is_empty())
+                                .map(Member::parse),
+                        );
+                    }
+                }
+            }
+            "366" => {
+                if let Some(room_name) = params.get(1).map(|r| r.to_string()) {
+                    if let Some(room) = self.room_mut(&room_name) {
+                        room.collecting_names = false;
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        BACKLOG_COMMANDS.contains(&command.as_str())
+    }
+
+    /// Keeps a server line for the next client, dropping the oldest past the limit.
+    pub fn push_backlog(&mut self, line: &str) {
+        if self.backlog.len() >= self.backlog_limit {
+            self.backlog.pop_front();
+        }
+        self.backlog.push_back(line.to_string());
+    }
+
+    /// Prepares to replay the session to a newly attached client. Returns the lines sent
+    /// before the client spoke during the original registration.
+    ///
+    /// The replay lasts until the client has rejoined every room the session is in, or
+    /// joins one it is not in.
+    pub fn begin_replay(&mut self) -> Vec<String> {
+        self.replaying = self.registered;
+        self.replay_cursor = 1;
+        self.pending_rooms = self.rooms.iter().map(|r| r.name.clone()).collect();
+        if self.replaying {
+            self.registration[0].replies.clone()
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Handles a line from the client. Returns `Some(lines to send back)` if the line was
+    /// answered locally and must not go upstream.
+    pub fn intercept(&mut self, line: &str) -> Option<Vec<String>> {
+        let message = Message::parse(line)?;
+        let command = message.command.to_string();
+
+        // The bare QUIT the OCX sends as it closes only detaches it, and the bouncer decides
+        // when the session ends. A QUIT with a message is the user leaving on purpose, and
+        // a session not worth keeping has nothing to hold on to.
+        if command == "QUIT" {
+            if message.text().is_some_and(|t| !t.is_empty()) || !self.resumable() {
+                self.quit = true;
+                return None;
+            }
+            return Some(Vec::new());
+        }
+        if !self.replaying {
+            return None;
+        }
+
+        if REGISTRATION_COMMANDS.contains(&command.as_str()) {
+            let replies = match self.registration[self.replay_cursor..]
+                .iter()
+                .position(|s| s.command == command)
+            {
+                Some(offset) => {
+                    let index = self.replay_cursor + offset;
+                    self.replay_cursor = index + 1;
+                    self.registration[index].replies.clone()
+                }
+                None => Vec::new(),
+            };
+            return Some(replies);
+        }
+
+        if command == "JOIN" {
+            let room_name = message.arg(0)?.to_string();
+            let Some(lines) = self.synthesize_join(&room_name) else {
+                // A room the session is not in: the client is past rejoining.
+                self.replaying = false;
+                return None;
+            };
+            self.pending_rooms
+                .retain(|r| !r.eq_ignore_ascii_case(&room_name));
+            if self.pending_rooms.is_empty() {
+                self.replaying = false;
+            }
+            return Some(lines);
+        }
+
+        None
+    }
+
+    /// Builds the JOIN burst for a room the session is already in, followed by whatever was
+    /// said there while the client was away.
+    fn synthesize_join(&mut self, room_name: &str) -> Option<Vec<String>> {
+        let server = self.server.clone();
+        let nick = self.nick.clone();
+        let room = self
+            .rooms
+            .iter()
+            .find(|r| r.name.eq_ignore_ascii_case(room_name))?
+            .clone();
+
+        let mut lines = vec![room.join_line.clone()];
+        lines.extend(room.topic_lines.iter().cloned());
+
+        let prefix = format!(":{} 353 {} = {} :", server, nick, room.name);
+        let mut current = String::new();
+        for member in &room.members {
+            let token = member.token();
+            if !current.is_empty() && prefix.len() + current.len() + token.len() >= NAMES_LINE_LEN {
+                lines.push(format!("{}{}", prefix, current));
+                current.clear();
+            }
+            if !current.is_empty() {
+                current.push(' ');
+            }
+            current.push_str(&token);
+        }
+        if !current.is_empty() {
+            lines.push(format!("{}{}", prefix, current));
+        }
+        lines.push(format!(
+            ":{} 366 {} {} :End of /NAMES list.",
+            server, nick, room.name
+        ));
+
+        // Backlog for this room, plus private messages, which have no room to wait for.
+        let mut kept = VecDeque::new();
+        for line in self.backlog.drain(..) {
+            let target = Message::parse(&line).and_then(|m| m.arg(0).map(str::to_string));
+            let for_room = target
+                .as_deref()
+                .is_some_and(|t| t.eq_ignore_ascii_case(&room.name));
+            let private = target
+                .as_deref()
+                .is_some_and(|t| t.eq_ignore_ascii_case(&nick));
+            if for_room || private {
+                lines.push(line);
+            } else {
+                kept.push_back(line);
+            }
+        }
+        self.backlog = kept;
+        Some(lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A session registered as `me` and sitting in `#room`.
+    fn joined_session() -> SessionState {
+        let mut state = SessionState::new(10);
+        state.observe_outbound("NICK me");
+        state.observe_inbound(":srv 001 me :Welcome");
+        state.observe_inbound(":srv 376 me :End of /MOTD command.");
+        state.observe_outbound("JOIN #room");
+        state.observe_inbound(":me!u@h JOIN :#room");
+        state.observe_inbound(":srv 353 me = #room :.me other");
+        state.observe_inbound(":srv 366 me #room :End of /NAMES list.");
+        state
+    }
+
+    #[test]
+    fn bare_quit_is_swallowed_on_first_attach() {
+        let mut state = joined_session();
+        assert_eq!(state.intercept("QUIT"), Some(Vec::new()));
+        assert_eq!(state.intercept("QUIT :"), Some(Vec::new()));
+        assert!(state.resumable());
+        assert_eq!(state.intercept("PRIVMSG #room :hi"), None);
+    }
+
+    #[test]
+    fn quit_with_a_message_is_forwarded_and_ends_the_session() {
+        let mut state = joined_session();
+        assert_eq!(state.intercept("QUIT :bye"), None);
+        assert!(!state.resumable());
+    }
+
+    #[test]
+    fn quit_is_forwarded_when_there_is_no_session_to_keep() {
+        let mut state = SessionState::new(10);
+        state.observe_outbound("NICK me");
+        state.observe_inbound(":srv 001 me :Welcome");
+        assert!(!state.resumable());
+        assert_eq!(state.intercept("QUIT"), None);
+    }
+
+    #[test]
+    fn replay_ends_once_every_room_is_rejoined() {
+        let mut state = joined_session();
+        assert!(state.resumable());
+        state.begin_replay();
+
+        let replies = state.intercept("NICK me").unwrap();
+        assert!(replies.iter().any(|l| l.contains(" 001 ")));
+        let burst = state.intercept("JOIN #room").unwrap();
+        assert_eq!(burst[0], ":me!u@h JOIN :#room");
+        assert!(burst.iter().any(|l| l.ends_with(":.me other")));
+
+        // Past the replay everything goes upstream again.
+        assert_eq!(state.intercept("NICK newnick"), None);
+        assert_eq!(state.intercept("JOIN #room"), None);
+        assert_eq!(state.intercept("QUIT"), Some(Vec::new()));
+        assert_eq!(state.intercept("QUIT :closing"), None);
+    }
+
+    #[test]
+    fn joining_another_room_ends_the_replay() {
+        let mut state = joined_session();
+        state.begin_replay();
+        assert_eq!(state.intercept("JOIN #elsewhere"), None);
+        assert_eq!(state.intercept("NICK other"), None);
+    }
+}
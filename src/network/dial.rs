@@ -0,0 +1,275 @@
+//! Dual-stack TCP connect with a small resolution cache.
+//!
+//! Addresses are resolved once per `dns_ttl_secs` and tried in the RFC 8305 ("Happy
+//! Eyeballs v2") order: IPv6 and IPv4 interleaved, starting with IPv6. A new attempt starts
+//! every `attempt_delay_ms`, or as soon as the previous one fails, and the first connection
+//! to complete wins. The whole race is bounded by `timeout_secs`.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+
+use crate::config::ConnectConfig;
+
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_ATTEMPT_DELAY_MS: u64 = 250;
+const DEFAULT_DNS_TTL_SECS: u64 = 300;
+
+struct CacheEntry {
+    expires: Instant,
+    addrs: Vec<IpAddr>,
+}
+
+static DNS_CACHE: Mutex<Option<HashMap<String, CacheEntry>>> = Mutex::new(None);
+
+/// What happened while dialling, for the socket's stats.
+#[derive(Debug, Clone, Default)]
+pub struct DialReport {
+    /// Connection attempts started, including ones that lost the race or failed.
+    pub attempts: u32,
+    /// Address of the attempt that won.
+    pub addr: Option<SocketAddr>,
+}
+
+/// Resolves `host`, using the cache while its entry is fresh.
+async fn resolve(config: &ConnectConfig, host: &str, port: u16) -> io::Result<Vec<IpAddr>> {
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(vec![ip]);
+    }
+
+    let key = host.to_ascii_lowercase();
+    let ttl = Duration::from_secs(config.dns_ttl_secs.unwrap_or(DEFAULT_DNS_TTL_SECS));
+    if let Ok(cache) = DNS_CACHE.lock() {
+        if let Some(entry) = cache.as_ref().and_then(|c| c.get(&key)) {
+            if entry.expires > Instant::now() {
+                log::debug!("Resolved {} from cache: {:?}", host, entry.addrs);
+                return Ok(entry.addrs.clone());
+            }
+        }
+    }
+
+    let mut addrs: Vec<IpAddr> = Vec::new();
+    for addr in tokio::net::lookup_host((host, port)).await? {
+        if !addrs.contains(&addr.ip()) {
+            addrs.push(addr.ip());
+        }
+    }
+    if addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} did not resolve to any address", host),
+        ));
+    }
+    log::info!("Resolved {} to {:?}", host, addrs);
+
+    if !ttl.is_zero() {
+        if let Ok(mut cache) = DNS_CACHE.lock() {
+            cache.get_or_insert_with(HashMap::new).insert(
+                key,
+                CacheEntry {
+                    expires: Instant::now() + ttl,
+                    addrs: addrs.clone(),
+                },
+            );
+        }
+    }
+    Ok(addrs)
+}
+
+/// Orders addresses IPv6 first, alternating families.
+fn interleave(addrs: Vec<IpAddr>) -> Vec<IpAddr> {
+    let (v6, v4): (Vec<IpAddr>, Vec<IpAddr>) = addrs.into_iter().partition(IpAddr::is_ipv6);
+    let mut ordered = Vec::with_capacity(v6.len() + v4.len());
+    let (mut v6, mut v4) = (v6.into_iter(), v4.into_iter());
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => break,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+    ordered
+}
+
+/// Connects to `host:port`, racing the resolved addresses.
+pub async fn connect(
+    config: &ConnectConfig,
+    host: &str,
+    port: u16,
+    report: &mut DialReport,
+) -> io::Result<TcpStream> {
+    let timeout = Duration::from_secs(config.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS));
+    match tokio::time::timeout(timeout, race(config, host, port, report)).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!(
+                "connecting to {}:{} timed out after {:?}",
+                host, port, timeout
+            ),
+        )),
+    }
+}
+
+async fn race(
+    config: &ConnectConfig,
+    host: &str,
+    port: u16,
+    report: &mut DialReport,
+) -> io::Result<TcpStream> {
+    let addrs = interleave(resolve(config, host, port).await?);
+    let attempt_delay =
+        Duration::from_millis(config.attempt_delay_ms.unwrap_or(DEFAULT_ATTEMPT_DELAY_MS));
+
+    // Dropping the set aborts attempts that are still in flight once one has won.
+    let mut attempts = JoinSet::new();
+    let mut remaining = addrs.into_iter().map(|ip| SocketAddr::new(ip, port));
+    let mut last_error = None;
+    loop {
+        // Each pass starts the next address: first time in, after the attempt delay, or
+        // after an attempt failed.
+        if let Some(addr) = remaining.next() {
+            report.attempts += 1;
+            log::info!(
+                "Connecting to {} ({}) [attempt {}]",
+                host,
+                addr,
+                report.attempts
+            );
+            attempts.spawn(async move { (addr, TcpStream::connect(addr).await) });
+        }
+        if attempts.is_empty() {
+            break;
+        }
+
+        let finished = if remaining.len() > 0 {
+            match tokio::time::timeout(attempt_delay, attempts.join_next()).await {
+                Ok(finished) => finished,
+                // No answer yet: race the next address alongside.
+                Err(_) => continue,
+            }
+        } else {
+            attempts.join_next().await
+        };
+
+        match finished {
+            Some(Ok((addr, Ok(stream)))) => {
+                log::info!("Connected to {} via {}", host, addr);
+                report.addr = Some(addr);
+                return Ok(stream);
+            }
+            Some(Ok((addr, Err(e)))) => {
+                log::warn!("Connect to {} ({}) failed: {}", host, addr, e);
+                last_error = Some(e);
+            }
+            Some(Err(e)) => {
+                last_error = Some(io::Error::other(e));
+            }
+            None => break,
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no addresses to connect to for {}", host),
+        )
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::manager::get_rt;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use tokio::net::TcpListener;
+
+    const V4: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    const V6: IpAddr = IpAddr::V6(Ipv6Addr::LOCALHOST);
+
+    /// Caches `addrs` for `host`, which must not resolve for real, until `expires`.
+    fn cache(host: &str, addrs: Vec<IpAddr>, expires: Instant) {
+        DNS_CACHE
+            .lock()
+            .unwrap()
+            .get_or_insert_with(HashMap::new)
+            .insert(host.to_string(), CacheEntry { expires, addrs });
+    }
+
+    #[test]
+    fn families_alternate_starting_with_ipv6() {
+        let v4 = |n| IpAddr::V4(Ipv4Addr::new(10, 0, 0, n));
+        let v6 = |n| IpAddr::V6(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, n));
+        assert_eq!(
+            interleave(vec![v4(1), v4(2), v4(3), v6(1), v6(2)]),
+            [v6(1), v4(1), v6(2), v4(2), v4(3)]
+        );
+        assert_eq!(interleave(vec![v4(1), v4(2)]), [v4(1), v4(2)]);
+        assert_eq!(interleave(vec![v6(1), v6(2)]), [v6(1), v6(2)]);
+    }
+
+    #[test]
+    fn cached_answers_are_used_until_they_expire() {
+        get_rt().block_on(async {
+            let config = ConnectConfig::default();
+            cache(
+                "fresh.invalid",
+                vec![V4],
+                Instant::now() + Duration::from_secs(60),
+            );
+            assert_eq!(resolve(&config, "FRESH.invalid", 6667).await.unwrap(), [V4]);
+
+            // Expired: looked up again, and `.invalid` never resolves.
+            cache("stale.invalid", vec![V4], Instant::now());
+            assert!(resolve(&config, "stale.invalid", 6667).await.is_err());
+        });
+    }
+
+    #[test]
+    fn answers_are_cached_for_the_configured_ttl() {
+        get_rt().block_on(async {
+            let config = ConnectConfig {
+                dns_ttl_secs: Some(1234),
+                ..Default::default()
+            };
+            let before = Instant::now();
+            let addrs = resolve(&config, "localhost", 6667).await.unwrap();
+            let cache = DNS_CACHE.lock().unwrap();
+            let entry = &cache.as_ref().unwrap()["localhost"];
+            assert_eq!(entry.addrs, addrs);
+            assert!(entry.expires >= before + Duration::from_secs(1234));
+            assert!(entry.expires <= Instant::now() + Duration::from_secs(1234));
+        });
+    }
+
+    #[test]
+    fn ipv6_is_tried_before_falling_back_to_ipv4() {
+        get_rt().block_on(async {
+            // Only IPv4 is listening, so the IPv6 attempt is refused (or has no route).
+            let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            cache(
+                "dual.invalid",
+                vec![V4, V6],
+                Instant::now() + Duration::from_secs(60),
+            );
+            let config = ConnectConfig {
+                attempt_delay_ms: Some(10_000),
+                ..Default::default()
+            };
+
+            let mut report = DialReport::default();
+            let started = Instant::now();
+            connect(&config, "dual.invalid", port, &mut report)
+                .await
+                .unwrap();
+            assert_eq!(report.attempts, 2);
+            assert_eq!(report.addr, Some(SocketAddr::new(V4, port)));
+            // The failure, not the attempt delay, started the IPv4 attempt.
+            assert!(started.elapsed() < Duration::from_secs(5));
+        });
+    }
+}
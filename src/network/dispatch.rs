@@ -0,0 +1,158 @@
+//! Delivery of OCX socket events and user notices on the UI thread.
+//!
+//! The chat control is apartment-threaded, so its socket callbacks must not be called from
+//! Tokio worker threads. [`OcxEvents`](crate::network::events::OcxEvents) instead queues
+//! each event here and posts `WM_CHAT_SOCKETEVENT` to the host window; `OcxWindow::wndproc`
+//! calls [`drain`], which invokes the callbacks on the thread running the message pump.
+//! Events for sockets the OCX has closed in the meantime are dropped. [`notify_user`] does
+//! the same for status lines shown in the chat window.
+//!
+//! Anything queued before the host window exists waits until [`set_target_window`] is
+//! called.
+
+use std::collections::VecDeque;
+#[cfg(windows)]
+use std::ffi::c_void;
+#[cfg(all(windows, target_arch = "x86"))]
+use std::sync::Arc;
+#[cfg(windows)]
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+#[cfg(all(windows, target_arch = "x86"))]
+use crate::network::events::OcxCallback;
+use crate::network::events::SocketEvent;
+use crate::network::manager::get_registry;
+
+struct Pending {
+    /// The socket event this task delivers, or `None` for a notice.
+    event: Option<SocketEvent>,
+    task: Box<dyn FnOnce() + Send>,
+}
+
+#[cfg(windows)]
+static TARGET_WINDOW: AtomicPtr<c_void> = AtomicPtr::new(std::ptr::null_mut());
+static PENDING: Mutex<VecDeque<Pending>> = Mutex::new(VecDeque::new());
+static NOTICE_SINK: OnceLock<fn(&str)> = OnceLock::new();
+
+/// Registered window message that tells the host window to call [`drain`].
+#[cfg(windows)]
+pub fn socket_event_message() -> u32 {
+    use windows::Win32::UI::WindowsAndMessaging::RegisterWindowMessageW;
+    use windows::core::w;
+
+    static MESSAGE: OnceLock<u32> = OnceLock::new();
+    *MESSAGE.get_or_init(|| unsafe { RegisterWindowMessageW(w!("WM_CHAT_SOCKETEVENT")) })
+}
+
+/// Sets the window whose thread receives socket events, and delivers anything queued so
+/// far. Call once the host window exists.
+#[cfg(windows)]
+pub fn set_target_window(hwnd: windows::Win32::Foundation::HWND) {
+    TARGET_WINDOW.store(hwnd.0, Ordering::SeqCst);
+    if PENDING.lock().is_ok_and(|p| !p.is_empty()) {
+        wake();
+    }
+}
+
+/// Sets where [`notify_user`] lines end up. Until it is called they are only logged.
+pub fn set_notice_sink(sink: fn(&str)) {
+    let _ = NOTICE_SINK.set(sink);
+}
+
+/// Asks the UI thread to call [`drain`]. Does nothing until a target window is set.
+fn wake() {
+    #[cfg(windows)]
+    {
+        use windows::Win32::Foundation::{HWND, LPARAM, WPARAM};
+        use windows::Win32::UI::WindowsAndMessaging::PostMessageW;
+
+        let hwnd = TARGET_WINDOW.load(Ordering::SeqCst);
+        if hwnd.is_null() {
+            return;
+        }
+        let posted = unsafe {
+            PostMessageW(
+                Some(HWND(hwnd)),
+                socket_event_message(),
+                WPARAM(0),
+                LPARAM(0),
+            )
+        };
+        if let Err(e) = posted {
+            log::error!("Failed to post socket event to the UI thread: {}", e);
+        }
+    }
+}
+
+fn socket_id(event: SocketEvent) -> u32 {
+    match event {
+        SocketEvent::Write(id)
+        | SocketEvent::Error(id)
+        | SocketEvent::Read(id)
+        | SocketEvent::ReadReady(id) => id,
+    }
+}
+
+fn enqueue(pending: Pending) {
+    if let Ok(mut queue) = PENDING.lock() {
+        // One queued OnReadReady per socket is enough: the OCX drains the whole buffer.
+        if let Some(event @ SocketEvent::ReadReady(_)) = pending.event {
+            if queue.iter().any(|p| p.event == Some(event)) {
+                return;
+            }
+        }
+        queue.push_back(pending);
+    }
+    wake();
+}
+
+/// Queues `event` for delivery to `sink` on the UI thread.
+#[cfg(all(windows, target_arch = "x86"))]
+pub fn post(event: SocketEvent, sink: Arc<OcxCallback>) {
+    enqueue(Pending {
+        event: Some(event),
+        task: Box::new(move || sink.deliver(event)),
+    });
+}
+
+/// Queues `text` to be shown as a system line in the chat window, from the UI thread.
+pub fn notify_user(text: String) {
+    enqueue(Pending {
+        event: None,
+        task: Box::new(move || match NOTICE_SINK.get() {
+            Some(sink) => sink(&text),
+            None => log::info!("{}", text),
+        }),
+    });
+}
+
+/// Runs every queued task. Must be called on the thread that owns the target window.
+pub fn drain() {
+    loop {
+        // Pop one at a time so callbacks that queue new events (or close sockets) do not
+        // run while the queue lock is held.
+        let next = PENDING.lock().ok().and_then(|mut p| p.pop_front());
+        let Some(Pending { event, task }) = next else {
+            break;
+        };
+
+        if let Some(event) = event {
+            let id = socket_id(event);
+            let live = get_registry()
+                .lock()
+                .ok()
+                .and_then(|reg| reg.get(&id).cloned())
+                .is_some_and(|socket| socket.lock().is_ok_and(|s| !s.closed));
+            if !live {
+                log::debug!(
+                    "Dropping {:?} for socket {}, which is no longer open",
+                    event,
+                    id
+                );
+                continue;
+            }
+        }
+        task();
+    }
+}
@@ -0,0 +1,331 @@
+//! Socket event sinks.
+//!
+//! The manager reports connection progress through a [`SocketEvents`] implementation
+//! attached to each socket instead of calling C++ vtables directly. [`OcxEvents`] forwards to
+//! the OCX callback object registered by `register_socket` (via `dispatch`, so the calls
+//! happen on the UI thread), and [`ChannelEvents`] pushes [`SocketEvent`]s into a Tokio
+//! channel so the backend can be driven without the OCX.
+//!
+//! Only the trait and [`ChannelEvents`] build off 32-bit Windows; the OCX adapters need the
+//! `thiscall` ABI.
+
+#[cfg(all(windows, target_arch = "x86"))]
+use std::ffi::c_void;
+#[cfg(all(windows, target_arch = "x86"))]
+use std::sync::Arc;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+#[cfg(all(windows, target_arch = "x86"))]
+use crate::network::dispatch;
+
+/// Receiver of the four notifications the OCX socket callback understands.
+pub trait SocketEvents: Send + Sync {
+    /// The connection is established and the socket is writable.
+    fn on_write(&self, id: u32);
+    /// The connection attempt failed.
+    fn on_error(&self, id: u32);
+    /// The connection was closed. The OCX calls this slot `OnRead`.
+    fn on_read(&self, id: u32);
+    /// New bytes are waiting in the receive buffer.
+    fn on_read_ready(&self, id: u32) -> bool;
+}
+
+// C++ Callback function typings
+#[cfg(all(windows, target_arch = "x86"))]
+type FnOnWrite = unsafe extern "thiscall" fn(this: *mut c_void, context: *mut c_void);
+#[cfg(all(windows, target_arch = "x86"))]
+type FnOnRead = unsafe extern "thiscall" fn(this: *mut c_void, context: *mut c_void);
+#[cfg(all(windows, target_arch = "x86"))]
+type FnOnError = unsafe extern "thiscall" fn(this: *mut c_void, context: *mut c_void);
+#[cfg(all(windows, target_arch = "x86"))]
+type FnOnReadReady = unsafe extern "thiscall" fn(this: *mut c_void, context: *mut c_void) -> u8;
+
+/// Triggers the C++ `OnWrite` virtual callback (offset 0).
+///
+/// # Safety
+///
+/// This function is unsafe because it dereferences raw pointers and invokes external
+/// C++ `thiscall` functions. The `callback_ptr` must point to a valid object with a vtable.
+#[cfg(all(windows, target_arch = "x86"))]
+pub unsafe fn trigger_on_write(callback_ptr: *mut c_void, context_ptr: *mut c_void) {
+    if callback_ptr.is_null() {
+        return;
+    }
+    unsafe {
+        let vtable = *(callback_ptr as *mut *mut *mut c_void);
+        let func_ptr = *vtable.offset(0);
+        let func: FnOnWrite = std::mem::transmute(func_ptr);
+        func(callback_ptr, context_ptr);
+    }
+}
+
+/// Triggers the C++ `OnRead` virtual callback (offset 2 / 8 bytes).
+///
+/// # Safety
+///
+/// This function is unsafe because it dereferences raw pointers and invokes external
+/// C++ `thiscall` functions. The `callback_ptr` must point to a valid object with a vtable.
+#[cfg(all(windows, target_arch = "x86"))]
+pub unsafe fn trigger_on_read(callback_ptr: *mut c_void, context_ptr: *mut c_void) {
+    if callback_ptr.is_null() {
+        return;
+    }
+    unsafe {
+        let vtable = *(callback_ptr as *mut *mut *mut c_void);
+        let func_ptr = *vtable.offset(2); // Offset 2 is OnRead (offset 8 bytes)
+        let func: FnOnRead = std::mem::transmute(func_ptr);
+        func(callback_ptr, context_ptr);
+    }
+}
+
+/// Triggers the C++ `OnError` virtual callback (offset 1 / 4 bytes).
+///
+/// # Safety
+///
+/// This function is unsafe because it dereferences raw pointers and invokes external
+/// C++ `thiscall` functions. The `callback_ptr` must point to a valid object with a vtable.
+#[cfg(all(windows, target_arch = "x86"))]
+pub unsafe fn trigger_on_error(callback_ptr: *mut c_void, context_ptr: *mut c_void) {
+    if callback_ptr.is_null() {
+        return;
+    }
+    unsafe {
+        let vtable = *(callback_ptr as *mut *mut *mut c_void);
+        let func_ptr = *vtable.offset(1); // Offset 1 is OnError (offset 4 bytes)
+        let func: FnOnError = std::mem::transmute(func_ptr);
+        func(callback_ptr, context_ptr);
+    }
+}
+
+/// Triggers the C++ `OnReadReady` virtual callback (offset 3 / 12 bytes).
+///
+/// # Safety
+///
+/// This function is unsafe because it dereferences raw pointers and invokes external
+/// C++ `thiscall` functions. The `callback_ptr` must point to a valid object with a vtable.
+#[cfg(all(windows, target_arch = "x86"))]
+pub unsafe fn trigger_on_read_ready(callback_ptr: *mut c_void, context_ptr: *mut c_void) -> bool {
+    if callback_ptr.is_null() {
+        return false;
+    }
+    unsafe {
+        let vtable = *(callback_ptr as *mut *mut *mut c_void);
+        let func_ptr = *vtable.offset(3); // Offset 3 is OnReadReady (offset 12 bytes)
+        let func: FnOnReadReady = std::mem::transmute(func_ptr);
+        func(callback_ptr, context_ptr) != 0
+    }
+}
+
+/// The OCX callback object and context passed to `register_socket`.
+#[cfg(all(windows, target_arch = "x86"))]
+pub struct OcxCallback {
+    callback_ptr: *mut c_void,
+    context_ptr: *mut c_void,
+}
+
+// The OCX owns the callback object for the lifetime of the socket registration, and
+// `dispatch` only ever invokes it on the UI thread.
+#[cfg(all(windows, target_arch = "x86"))]
+unsafe impl Send for OcxCallback {}
+#[cfg(all(windows, target_arch = "x86"))]
+unsafe impl Sync for OcxCallback {}
+
+#[cfg(all(windows, target_arch = "x86"))]
+impl OcxCallback {
+    /// Invokes the matching vtable slot on the calling thread.
+    pub fn deliver(&self, event: SocketEvent) {
+        unsafe {
+            match event {
+                SocketEvent::Write(_) => trigger_on_write(self.callback_ptr, self.context_ptr),
+                SocketEvent::Error(_) => trigger_on_error(self.callback_ptr, self.context_ptr),
+                SocketEvent::Read(_) => trigger_on_read(self.callback_ptr, self.context_ptr),
+                SocketEvent::ReadReady(_) => {
+                    trigger_on_read_ready(self.callback_ptr, self.context_ptr);
+                }
+            }
+        }
+    }
+}
+
+/// Adapter that reports events to the OCX callback object, marshalled onto the UI thread.
+#[cfg(all(windows, target_arch = "x86"))]
+pub struct OcxEvents {
+    callback: Arc<OcxCallback>,
+}
+
+#[cfg(all(windows, target_arch = "x86"))]
+impl OcxEvents {
+    pub fn new(callback_ptr: *mut c_void, context_ptr: *mut c_void) -> Self {
+        Self {
+            callback: Arc::new(OcxCallback {
+                callback_ptr,
+                context_ptr,
+            }),
+        }
+    }
+}
+
+#[cfg(all(windows, target_arch = "x86"))]
+impl SocketEvents for OcxEvents {
+    fn on_write(&self, id: u32) {
+        dispatch::post(SocketEvent::Write(id), self.callback.clone());
+    }
+
+    fn on_error(&self, id: u32) {
+        dispatch::post(SocketEvent::Error(id), self.callback.clone());
+    }
+
+    fn on_read(&self, id: u32) {
+        dispatch::post(SocketEvent::Read(id), self.callback.clone());
+    }
+
+    /// Always reports success, since the OCX only sees the event once the UI thread runs it.
+    fn on_read_ready(&self, id: u32) -> bool {
+        dispatch::post(SocketEvent::ReadReady(id), self.callback.clone());
+        true
+    }
+}
+
+/// A socket event tagged with the socket ID, as queued by `dispatch` or sent by
+/// [`ChannelEvents`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketEvent {
+    Write(u32),
+    Error(u32),
+    Read(u32),
+    ReadReady(u32),
+}
+
+/// Sink that sends every event down an unbounded channel.
+pub struct ChannelEvents {
+    tx: UnboundedSender<SocketEvent>,
+}
+
+impl ChannelEvents {
+    /// Creates the sink together with the receiver its events arrive on.
+    pub fn new() -> (Self, UnboundedReceiver<SocketEvent>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (Self { tx }, rx)
+    }
+}
+
+impl SocketEvents for ChannelEvents {
+    fn on_write(&self, id: u32) {
+        let _ = self.tx.send(SocketEvent::Write(id));
+    }
+
+    fn on_error(&self, id: u32) {
+        let _ = self.tx.send(SocketEvent::Error(id));
+    }
+
+    fn on_read(&self, id: u32) {
+        let _ = self.tx.send(SocketEvent::Read(id));
+    }
+
+    fn on_read_ready(&self, id: u32) -> bool {
+        self.tx.send(SocketEvent::ReadReady(id)).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::config::NetworkConfig;
+    use crate::network::manager::{self, get_rt};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Creates a socket reporting to a [`ChannelEvents`] and connects it to `port`.
+    fn connect(port: u16) -> (u32, UnboundedReceiver<SocketEvent>) {
+        let id = manager::create_socket();
+        let (events, rx) = ChannelEvents::new();
+        assert!(manager::set_socket_events(id, Arc::new(events)));
+        assert!(manager::connect_socket_with_config(
+            id,
+            "127.0.0.1".to_string(),
+            port,
+            Arc::new(NetworkConfig::default()),
+        ));
+        (id, rx)
+    }
+
+    fn next_event(rx: &mut UnboundedReceiver<SocketEvent>) -> SocketEvent {
+        get_rt()
+            .block_on(async { tokio::time::timeout(TIMEOUT, rx.recv()).await })
+            .expect("timed out waiting for a socket event")
+            .expect("event channel closed")
+    }
+
+    #[test]
+    fn connect_read_and_eof() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"PING :irc7\r\n").unwrap();
+            let mut line = [0u8; 12];
+            stream.read_exact(&mut line).unwrap();
+            assert_eq!(&line, b"PONG :irc7\r\n");
+            // Dropping the stream closes the connection.
+        });
+
+        let (id, mut rx) = connect(port);
+        assert_eq!(next_event(&mut rx), SocketEvent::Write(id));
+        assert_eq!(next_event(&mut rx), SocketEvent::ReadReady(id));
+
+        let mut buf = [0u8; 64];
+        let n = manager::receive_socket(id, &mut buf);
+        assert_eq!(&buf[..n as usize], b"PING :irc7\r\n");
+        assert!(manager::send_socket(id, b"PONG :irc7\r\n"));
+
+        server.join().unwrap();
+        assert_eq!(next_event(&mut rx), SocketEvent::Read(id));
+        manager::close_socket(id);
+    }
+
+    #[test]
+    fn connect_refused_reports_error() {
+        // Bind and drop a listener to get a port nothing is listening on.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let (id, mut rx) = connect(port);
+        assert_eq!(next_event(&mut rx), SocketEvent::Error(id));
+        manager::close_socket(id);
+    }
+
+    #[test]
+    fn registering_after_connect_reports_write() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || listener.accept().map(|(stream, _)| stream));
+
+        let id = manager::create_socket();
+        assert!(manager::connect_socket_with_config(
+            id,
+            "127.0.0.1".to_string(),
+            port,
+            Arc::new(NetworkConfig::default()),
+        ));
+        let _stream = server.join().unwrap().unwrap();
+        // Wait for the connect task to attach the transport.
+        let deadline = std::time::Instant::now() + TIMEOUT;
+        while !manager::socket_stats(id).is_some_and(|s| s.connected) {
+            assert!(std::time::Instant::now() < deadline, "never connected");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let (events, mut rx) = ChannelEvents::new();
+        assert!(manager::set_socket_events(id, Arc::new(events)));
+        assert_eq!(next_event(&mut rx), SocketEvent::Write(id));
+        manager::close_socket(id);
+    }
+}
@@ -0,0 +1,208 @@
+//! Directory server failover.
+//!
+//! `[network.directory] servers` lists directory servers in order of preference. A connect
+//! to any of them walks the whole list, starting with the server that last worked, before
+//! the OCX is told the connection failed. The server that answers is saved to
+//! `directory_state.json` so the next start tries it first; `config.toml` is left alone.
+//! An older `last_good` in `config.toml` is still read when there is no state file yet.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+use crate::config::DirectoryConfig;
+use crate::network::manager::get_rt;
+use crate::network::rewrite::split_host_port;
+
+const STATE_PATH: &str = "directory_state.json";
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+struct DirectoryState {
+    #[serde(default)]
+    last_good: Option<String>,
+}
+
+static STATE: OnceLock<Mutex<DirectoryState>> = OnceLock::new();
+/// Held while the state file is written, so saves land in order.
+static SAVE_LOCK: Mutex<()> = Mutex::new(());
+
+impl DirectoryState {
+    /// Reads the state saved at `path`, or the default if there is none or it is unreadable.
+    fn load(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        fs::write(path, json)
+    }
+}
+
+fn state() -> &'static Mutex<DirectoryState> {
+    STATE.get_or_init(|| Mutex::new(DirectoryState::load(Path::new(STATE_PATH))))
+}
+
+/// The entry that last accepted a connection, from the state file or else `config`.
+fn last_good(config: &DirectoryConfig) -> Option<String> {
+    state()
+        .lock()
+        .ok()
+        .and_then(|s| s.last_good.clone())
+        .or_else(|| config.last_good.clone())
+}
+
+/// One directory server to try, as written in config and as a host/port pair.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub entry: String,
+    pub host: String,
+    pub port: u16,
+}
+
+fn parse_entry(entry: &str, default_port: u16) -> Option<Candidate> {
+    let (host, port) = split_host_port(entry);
+    if host.is_empty() {
+        return None;
+    }
+    let port = match port {
+        Some(p) => p.parse().ok()?,
+        None => default_port,
+    };
+    Some(Candidate {
+        entry: entry.trim().to_string(),
+        host: host.to_string(),
+        port,
+    })
+}
+
+/// Server the OCX should be pointed at on startup: the last one that worked if it is still
+/// listed, otherwise the first in the list.
+pub fn preferred_server(config: &DirectoryConfig) -> Option<String> {
+    last_good(config)
+        .filter(|last| config.servers.contains(last))
+        .as_ref()
+        .or_else(|| config.servers.first())
+        .map(|entry| split_host_port(entry).0.to_string())
+}
+
+/// Returns the servers to try for a connect to `host:port`, or `None` if `host` is not one
+/// of the configured directory servers.
+pub fn candidates(config: &DirectoryConfig, host: &str, port: u16) -> Option<Vec<Candidate>> {
+    ordered_candidates(config, host, port, last_good(config).as_deref())
+}
+
+fn ordered_candidates(
+    config: &DirectoryConfig,
+    host: &str,
+    port: u16,
+    last_good: Option<&str>,
+) -> Option<Vec<Candidate>> {
+    let mut listed: Vec<Candidate> = config
+        .servers
+        .iter()
+        .filter_map(|entry| parse_entry(entry, port))
+        .collect();
+    if !listed.iter().any(|c| c.host.eq_ignore_ascii_case(host)) {
+        return None;
+    }
+    // Stable sort: the last good server moves to the front, the rest keep their order.
+    listed.sort_by_key(|c| last_good != Some(c.entry.as_str()));
+    Some(listed)
+}
+
+/// Remembers `entry` as the last directory server that accepted a connection. The state
+/// file is written on a blocking thread, away from the socket tasks.
+pub fn record_success(entry: &str) {
+    let Ok(mut current) = state().lock() else {
+        return;
+    };
+    if current.last_good.as_deref() == Some(entry) {
+        return;
+    }
+    current.last_good = Some(entry.to_string());
+    drop(current);
+
+    get_rt().spawn_blocking(|| {
+        let _guard = SAVE_LOCK.lock();
+        // Whatever is current by now, so a slower earlier save cannot win.
+        let Some(state) = state().lock().ok().map(|s| s.clone()) else {
+            return;
+        };
+        if let Err(e) = state.save(Path::new(STATE_PATH)) {
+            log::warn!("Failed to save {}: {}", STATE_PATH, e);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> DirectoryConfig {
+        DirectoryConfig {
+            servers: vec![
+                "dir1.example".to_string(),
+                " dir2.example:7000 ".to_string(),
+                "dir3.example".to_string(),
+            ],
+            last_good: None,
+        }
+    }
+
+    fn order(last_good: Option<&str>) -> Vec<(String, u16)> {
+        ordered_candidates(&config(), "DIR3.example", 6667, last_good)
+            .unwrap()
+            .into_iter()
+            .map(|c| (c.host, c.port))
+            .collect()
+    }
+
+    #[test]
+    fn candidates_follow_the_list_with_the_last_good_first() {
+        let listed = [
+            ("dir1.example".to_string(), 6667),
+            ("dir2.example".to_string(), 7000),
+            ("dir3.example".to_string(), 6667),
+        ];
+        assert_eq!(order(None), listed);
+        assert_eq!(
+            order(Some("dir3.example")),
+            [2, 0, 1].map(|i| listed[i].clone())
+        );
+        assert_eq!(
+            order(Some("dir2.example:7000")),
+            [1, 0, 2].map(|i| listed[i].clone())
+        );
+        // No longer listed.
+        assert_eq!(order(Some("old.example")), listed);
+
+        assert!(ordered_candidates(&config(), "elsewhere.example", 6667, None).is_none());
+    }
+
+    #[test]
+    fn state_is_saved_and_read_back() {
+        let directory =
+            std::env::temp_dir().join(format!("msnchat-failover-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join(STATE_PATH);
+
+        assert_eq!(DirectoryState::load(&path).last_good, None);
+        let state = DirectoryState {
+            last_good: Some("dir2.example:7000".to_string()),
+        };
+        state.save(&path).unwrap();
+        assert_eq!(
+            DirectoryState::load(&path).last_good.as_deref(),
+            Some("dir2.example:7000")
+        );
+
+        fs::write(&path, "not json").unwrap();
+        assert_eq!(DirectoryState::load(&path).last_good, None);
+        let _ = fs::remove_dir_all(directory);
+    }
+}
@@ -0,0 +1,197 @@
+//! IRC line framing for socket traffic, with subscribers that observe every line.
+//!
+//! The reader task frames what arrives from the server and the writer task frames what
+//! goes out, independently of how the OCX later splits the same bytes. Each complete line
+//! is handed to every registered subscriber, so Rust-side features (logging, triggers,
+//! state tracking) can follow a session without hooking the OCX.
+
+use std::borrow::Cow;
+use std::sync::Arc;
+
+pub use crate::network::recorder::Direction;
+use crate::subscribers::Subscribers;
+
+/// Longest partial line kept while waiting for its terminator. Anything longer is handed
+/// to subscribers as-is so a misbehaving peer cannot grow the buffer without bound.
+pub const MAX_LINE_LEN: usize = 16 * 1024;
+
+/// Splits a byte stream into lines terminated by `\n` (with or without a preceding `\r`).
+#[derive(Default)]
+pub struct LineFramer {
+    pending: Vec<u8>,
+}
+
+impl LineFramer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends `data` and returns every line it completes, without the terminator.
+    pub fn push(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        let mut lines = self.push_inclusive(data);
+        for line in &mut lines {
+            if line.last() == Some(&b'\n') {
+                line.pop();
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+            }
+        }
+        lines
+    }
+
+    /// Like [`push`](Self::push), but keeps each line's terminator. Only a line passed on
+    /// unframed for being too long comes back without one.
+    pub fn push_inclusive(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        self.pending.extend_from_slice(data);
+        let mut lines = Vec::new();
+        while let Some(pos) = self.pending.iter().position(|&b| b == b'\n') {
+            lines.push(self.pending.drain(..=pos).collect());
+        }
+        if self.pending.len() > MAX_LINE_LEN {
+            log::warn!(
+                "Unterminated line exceeded {} bytes, passing it on unframed",
+                MAX_LINE_LEN
+            );
+            lines.push(std::mem::take(&mut self.pending));
+        }
+        lines
+    }
+
+    /// Removes and returns the partial line waiting for its terminator.
+    pub fn take_partial(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+/// One framed line, as seen by subscribers.
+pub struct LineEvent<'a> {
+    pub socket: u32,
+    pub dir: Direction,
+    /// The line without its CRLF terminator.
+    pub line: &'a [u8],
+}
+
+impl LineEvent<'_> {
+    pub fn text(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(self.line)
+    }
+}
+
+pub type LineSubscriber = dyn Fn(&LineEvent) + Send + Sync;
+
+static SUBSCRIBERS: Subscribers<LineSubscriber> = Subscribers::new();
+
+/// Registers `subscriber` for every inbound and outbound line of every socket. Returns an
+/// ID for [`unsubscribe`].
+///
+/// Subscribers run on Tokio worker threads and must not block. They may call back into
+/// `network` (e.g. `send_socket`), since no socket lock is held while they run.
+pub fn subscribe(subscriber: impl Fn(&LineEvent) + Send + Sync + 'static) -> u64 {
+    SUBSCRIBERS.add(Arc::new(subscriber))
+}
+
+/// Removes a subscriber registered with [`subscribe`].
+pub fn unsubscribe(id: u64) {
+    SUBSCRIBERS.remove(id);
+}
+
+/// Hands each line to every subscriber.
+pub(crate) fn publish(socket: u32, dir: Direction, lines: &[Vec<u8>]) {
+    if lines.is_empty() {
+        return;
+    }
+    let subscribers = SUBSCRIBERS.snapshot();
+    if subscribers.is_empty() {
+        return;
+    }
+    for line in lines {
+        let event = LineEvent { socket, dir, line };
+        for subscriber in &subscribers {
+            subscriber(&event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn lines_end_at_crlf_or_a_bare_lf() {
+        let mut framer = LineFramer::new();
+        assert_eq!(
+            framer.push(b"PING :a\r\nPING :b\n\r\nPING"),
+            [b"PING :a".to_vec(), b"PING :b".to_vec(), Vec::new()]
+        );
+        assert_eq!(framer.take_partial(), b"PING");
+    }
+
+    #[test]
+    fn a_line_split_across_pushes_comes_out_whole() {
+        let mut framer = LineFramer::new();
+        assert!(framer.push(b"PRIVMSG #a").is_empty());
+        assert!(framer.push(b" :hi\r").is_empty());
+        assert_eq!(framer.push(b"\nNICK"), [b"PRIVMSG #a :hi".to_vec()]);
+        assert_eq!(framer.push(b" me\n"), [b"NICK me".to_vec()]);
+        assert!(framer.take_partial().is_empty());
+    }
+
+    #[test]
+    fn push_inclusive_keeps_terminators() {
+        let mut framer = LineFramer::new();
+        assert_eq!(
+            framer.push_inclusive(b"PING :a\r\nPING :b\nPO"),
+            [b"PING :a\r\n".to_vec(), b"PING :b\n".to_vec()]
+        );
+        assert_eq!(framer.push_inclusive(b"NG\r\n"), [b"PONG\r\n".to_vec()]);
+    }
+
+    #[test]
+    fn an_overlong_line_is_passed_on_unframed() {
+        let mut framer = LineFramer::new();
+        let long = vec![b'x'; MAX_LINE_LEN];
+        assert!(framer.push(&long).is_empty());
+        let lines = framer.push(b"yz");
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].len(), MAX_LINE_LEN + 2);
+        // Framing starts over with the rest of it.
+        assert_eq!(
+            framer.push(b" tail\r\nPING\r\n"),
+            [b" tail".to_vec(), b"PING".to_vec()]
+        );
+    }
+
+    #[test]
+    fn subscribers_see_every_line_until_unsubscribed() {
+        const SOCKET: u32 = 0xF1A3;
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let id = subscribe({
+            let seen = seen.clone();
+            move |event| {
+                // Subscribers are process-wide: ignore other tests' sockets.
+                if event.socket == SOCKET {
+                    seen.lock()
+                        .unwrap()
+                        .push((event.dir, event.text().into_owned()));
+                }
+            }
+        });
+
+        let lines = [b"PING :a".to_vec(), b"PRIVMSG #a :\xff".to_vec()];
+        publish(SOCKET, Direction::In, &lines);
+        publish(SOCKET, Direction::Out, &lines[..1]);
+        unsubscribe(id);
+        publish(SOCKET, Direction::In, &lines);
+
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                (Direction::In, "PING :a".to_string()),
+                (Direction::In, "PRIVMSG #a :\u{fffd}".to_string()),
+                (Direction::Out, "PING :a".to_string()),
+            ]
+        );
+    }
+}
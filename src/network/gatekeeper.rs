@@ -0,0 +1,234 @@
+//! Fresh GateKeeper answers for resumed sessions.
+//!
+//! MSN Chat servers authenticate every connection with `AUTH GateKeeper` (or
+//! `GateKeeperPassport`): the server sends a token carrying a challenge and the client
+//! answers with a token carrying an HMAC-MD5 of that challenge, followed by its GUID. An
+//! answer is only good for its own challenge, so the reconnect supervisor cannot replay the
+//! OCX's. Instead [`Answerer::learn`] works out from the exchange the OCX had with the
+//! server how it built its answer, and is only used if that reproduces the OCX's answer
+//! byte for byte.
+//!
+//! Tokens follow the `:` of the AUTH line, IRCX-escaped (`\0`, `\b` for a space, `\c` for a
+//! comma, ...), and start with a 16-byte header: `GKSSP\0`, two bytes of padding, then the
+//! version and sequence number as little-endian 32-bit integers.
+
+use hmac::{Hmac, Mac};
+use md5::Md5;
+
+/// Key of the GateKeeper package's HMAC.
+const KEY: &[u8] = b"SRFMKSJANDRESKKC";
+const SIGNATURE: &[u8] = b"GKSSP\0";
+const HEADER_LEN: usize = 16;
+const DIGEST_LEN: usize = 16;
+
+/// Builds answers to GateKeeper challenges the way the OCX built its own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Answerer {
+    /// The OCX's answer line up to and including the `:` before its token.
+    prefix: Vec<u8>,
+    /// The OCX's answer token, unescaped: header, digest, then its GUID.
+    token: Vec<u8>,
+    /// Which of the server's addresses the OCX hashed after the challenge, if any.
+    suffix: Option<usize>,
+}
+
+impl Answerer {
+    /// Works out how `answer`, the OCX's AUTH line, answered `challenge`, the server's.
+    /// `addresses` are the forms of the server's address the OCX may have hashed along with
+    /// the challenge, in a fixed order. Returns `None` unless hashing the challenge alone,
+    /// or with one of them, reproduces the OCX's answer.
+    pub fn learn(challenge: &[u8], answer: &[u8], addresses: &[String]) -> Option<Self> {
+        let (_, challenge) = token(challenge)?;
+        let challenge = challenge.get(HEADER_LEN..)?;
+        let (prefix, token) = token(answer)?;
+        if token.len() < HEADER_LEN + DIGEST_LEN || escape(&token) != answer[prefix.len()..] {
+            return None;
+        }
+
+        let digest = &token[HEADER_LEN..HEADER_LEN + DIGEST_LEN];
+        let suffix = if self::digest(challenge, b"") == digest {
+            None
+        } else {
+            Some(
+                addresses
+                    .iter()
+                    .position(|a| self::digest(challenge, a.as_bytes()) == digest)?,
+            )
+        };
+        Some(Self {
+            prefix: prefix.to_vec(),
+            token,
+            suffix,
+        })
+    }
+
+    /// The answer line, without CRLF, to the server's `challenge` line. `addresses` are the
+    /// current connection's, in the same order as given to [`learn`](Self::learn).
+    pub fn answer(&self, challenge: &[u8], addresses: &[String]) -> Option<Vec<u8>> {
+        let (_, challenge) = token(challenge)?;
+        let challenge = challenge.get(HEADER_LEN..)?;
+        let suffix = match self.suffix {
+            Some(i) => addresses.get(i)?.as_bytes(),
+            None => b"",
+        };
+        let mut token = self.token.clone();
+        token[HEADER_LEN..HEADER_LEN + DIGEST_LEN].copy_from_slice(&digest(challenge, suffix));
+
+        let mut line = self.prefix.clone();
+        line.extend_from_slice(&escape(&token));
+        Some(line)
+    }
+}
+
+/// Whether `line` is an AUTH line carrying a GateKeeper token.
+pub fn is_gatekeeper(line: &[u8]) -> bool {
+    token(line).is_some()
+}
+
+/// Splits an AUTH line into everything up to its token, and the unescaped token, if that
+/// is a GateKeeper one.
+fn token(line: &[u8]) -> Option<(&[u8], Vec<u8>)> {
+    let start = line.windows(2).position(|w| w == b" :")? + 2;
+    let token = unescape(&line[start..]);
+    token
+        .starts_with(SIGNATURE)
+        .then(|| (&line[..start], token))
+}
+
+fn digest(challenge: &[u8], suffix: &[u8]) -> [u8; DIGEST_LEN] {
+    let mut mac = Hmac::<Md5>::new_from_slice(KEY).expect("HMAC accepts any key length");
+    mac.update(challenge);
+    mac.update(suffix);
+    mac.finalize().into_bytes().into()
+}
+
+/// IRCX escaping of a binary AUTH parameter.
+fn escape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / 4);
+    for &b in data {
+        let escaped: &[u8] = match b {
+            b'\\' => b"\\\\",
+            0 => b"\\0",
+            b'\t' => b"\\t",
+            b'\n' => b"\\n",
+            b'\r' => b"\\r",
+            b' ' => b"\\b",
+            b',' => b"\\c",
+            _ => {
+                out.push(b);
+                continue;
+            }
+        };
+        out.extend_from_slice(escaped);
+    }
+    out
+}
+
+fn unescape(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut bytes = data.iter().copied();
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            out.push(b);
+            continue;
+        }
+        match bytes.next() {
+            Some(b'\\') => out.push(b'\\'),
+            Some(b'0') => out.push(0),
+            Some(b't') => out.push(b'\t'),
+            Some(b'n') => out.push(b'\n'),
+            Some(b'r') => out.push(b'\r'),
+            Some(b'b') => out.push(b' '),
+            Some(b'c') => out.push(b','),
+            Some(other) => out.extend_from_slice(&[b'\\', other]),
+            None => out.push(b'\\'),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) const GUID: [u8; 16] = *b"0123456789\0 ,\\\r\n";
+
+    fn header(sequence: u32) -> Vec<u8> {
+        let mut header = SIGNATURE.to_vec();
+        header.extend_from_slice(b"JD");
+        header.extend_from_slice(&3u32.to_le_bytes());
+        header.extend_from_slice(&sequence.to_le_bytes());
+        header
+    }
+
+    /// The server's challenge line for `challenge`.
+    pub(crate) fn challenge_line(challenge: &[u8; 8]) -> Vec<u8> {
+        let mut token = header(2);
+        token.extend_from_slice(challenge);
+        let mut line = b"AUTH GateKeeper S :".to_vec();
+        line.extend_from_slice(&escape(&token));
+        line
+    }
+
+    /// The answer the OCX would send to `challenge`, hashing `suffix` after it.
+    pub(crate) fn answer_line(challenge: &[u8; 8], suffix: &[u8]) -> Vec<u8> {
+        let mut token = header(3);
+        token.extend_from_slice(&digest(challenge, suffix));
+        token.extend_from_slice(&GUID);
+        let mut line = b"AUTH GateKeeper S :".to_vec();
+        line.extend_from_slice(&escape(&token));
+        line
+    }
+
+    #[test]
+    fn escaping_round_trips_every_byte() {
+        let data: Vec<u8> = (0..=255).collect();
+        let escaped = escape(&data);
+        assert!(!escaped.iter().any(|b| b"\0\r\n ,".contains(b)));
+        assert_eq!(unescape(&escaped), data);
+        assert_eq!(unescape(b"a\\b\\cb\\x\\"), b"a ,b\\x\\");
+    }
+
+    #[test]
+    fn answers_are_rebuilt_for_new_challenges() {
+        let first = *b"\x01\x02 \x00\\,\r\n";
+        let second = *b"fresh!!!";
+        let before = ["irc.example:6667".to_string(), "10.0.0.1:6667".to_string()];
+        // Reconnected to the same name, but another address behind it.
+        let after = ["irc.example:6667".to_string(), "10.0.0.2:6667".to_string()];
+        for (hashed, expected) in [("", ""), (&*before[1], &*after[1])] {
+            let answerer = Answerer::learn(
+                &challenge_line(&first),
+                &answer_line(&first, hashed.as_bytes()),
+                &before,
+            )
+            .unwrap_or_else(|| panic!("could not learn an answer hashing {hashed:?}"));
+
+            assert_eq!(
+                answerer.answer(&challenge_line(&second), &after).unwrap(),
+                answer_line(&second, expected.as_bytes())
+            );
+        }
+    }
+
+    #[test]
+    fn answers_that_cannot_be_reproduced_are_not_learned() {
+        let challenge = *b"12345678";
+        let answer = answer_line(&challenge, b"somewhere-else:6667");
+        assert_eq!(
+            Answerer::learn(&challenge_line(&challenge), &answer, &[]),
+            None
+        );
+        // Not a GateKeeper exchange at all.
+        assert_eq!(
+            Answerer::learn(
+                b"AUTH GateKeeperPassport S :OK",
+                b"AUTH GateKeeperPassport S :ticket",
+                &[]
+            ),
+            None
+        );
+        assert!(!is_gatekeeper(b"AUTH GateKeeperPassport S :OK"));
+        assert!(is_gatekeeper(&challenge_line(&challenge)));
+    }
+}
@@ -1,17 +1,39 @@
+use bytes::Buf;
 use std::collections::HashMap;
+#[cfg(all(windows, target_arch = "x86"))]
 use std::ffi::c_void;
+use std::io;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::runtime::Runtime;
-use tokio::sync::mpsc::unbounded_channel;
 
-use crate::network::socket::RustSocket;
+use crate::config::NetworkConfig;
+use crate::network::dial::{self, DialReport};
+#[cfg(all(windows, target_arch = "x86"))]
+use crate::network::events::OcxEvents;
+use crate::network::events::SocketEvents;
+use crate::network::framing::{self, LineFramer};
+use crate::network::recorder::{self, Direction};
+use crate::network::send_queue::{QueueDepth, RateLimiter, Refused, SendQueue};
+use crate::network::socket::{RustSocket, SocketSnapshot};
+use crate::network::transport::BoxedTransport;
+use crate::network::{
+    bouncer, failover, outbox, proxy, reconnect, replay, rewrite, tls, websocket,
+};
 
 static TOKIO_RT: OnceLock<Runtime> = OnceLock::new();
 static SOCKET_REGISTRY: OnceLock<Mutex<HashMap<u32, Arc<Mutex<RustSocket>>>>> = OnceLock::new();
 static NEXT_SOCKET_ID: AtomicU32 = AtomicU32::new(1000);
+static NETWORK_CONFIG: OnceLock<Arc<NetworkConfig>> = OnceLock::new();
+
+const DEFAULT_RX_LIMIT: usize = 1024 * 1024;
+const DEFAULT_TX_LIMIT: usize = 1024 * 1024;
+const DEFAULT_LINGER_MS: u64 = 2000;
+const DEFAULT_WATCHDOG_IDLE_SECS: u64 = 120;
+const DEFAULT_WATCHDOG_PONG_TIMEOUT_SECS: u64 = 30;
 
 pub fn get_rt() -> &'static Runtime {
     TOKIO_RT.get_or_init(|| {
@@ -24,63 +46,84 @@ pub fn get_registry() -> &'static Mutex<HashMap<u32, Arc<Mutex<RustSocket>>>> {
     SOCKET_REGISTRY.get_or_init(|| Mutex::new(HashMap::new()))
 }
 
-// C++ Callback function typings
-type FnOnWrite = unsafe extern "thiscall" fn(this: *mut c_void, context: *mut c_void);
-type FnOnRead = unsafe extern "thiscall" fn(this: *mut c_void, context: *mut c_void);
+/// Reads the `[network]` section of `config.toml`, falling back to defaults.
+pub(crate) fn load_network_config() -> NetworkConfig {
+    let manager = crate::config::MSNConfigManager::new(std::path::Path::new("config.toml"));
+    manager.load().map(|c| c.network).unwrap_or_default()
+}
 
-/// Triggers the C++ `OnWrite` virtual callback (offset 0).
-///
-/// # Safety
-///
-/// This function is unsafe because it dereferences raw pointers and invokes external
-/// C++ `thiscall` functions. The `callback_ptr` must point to a valid object with a vtable.
-pub unsafe fn trigger_on_write(callback_ptr: *mut c_void, context_ptr: *mut c_void) {
-    if callback_ptr.is_null() {
-        return;
-    }
-    unsafe {
-        let vtable = *(callback_ptr as *mut *mut *mut c_void);
-        let func_ptr = *vtable.offset(0);
-        let func: FnOnWrite = std::mem::transmute(func_ptr);
-        func(callback_ptr, context_ptr);
+/// Sets the `[network]` configuration used by every [`connect_socket`] and applies its
+/// recorder and outbox settings. Call once at startup with the loaded config; later calls
+/// are ignored.
+pub fn init(config: NetworkConfig) {
+    NETWORK_CONFIG.get_or_init(|| apply(config));
+}
+
+/// The configuration set by [`init`], or the one in `config.toml` if `init` was never
+/// called.
+fn network_config() -> Arc<NetworkConfig> {
+    NETWORK_CONFIG
+        .get_or_init(|| apply(load_network_config()))
+        .clone()
+}
+
+fn apply(mut config: NetworkConfig) -> Arc<NetworkConfig> {
+    recorder::configure(&config.recorder);
+    outbox::configure(&config.outbox);
+    // Generated now so a bouncer started later reads the same token from config.toml.
+    if config.bouncer.enabled && !bouncer::is_bouncer() {
+        match bouncer::token(&config.bouncer) {
+            Ok(token) => config.bouncer.token = Some(token),
+            Err(e) => log::warn!("Failed to set up the bouncer token: {}", e),
+        }
     }
+    Arc::new(config)
 }
 
-/// Triggers the C++ `OnRead` virtual callback (offset 2 / 8 bytes).
+/// Opens a TCP stream to `host:port`, tunnelling through the configured proxy if enabled.
 ///
-/// # Safety
-///
-/// This function is unsafe because it dereferences raw pointers and invokes external
-/// C++ `thiscall` functions. The `callback_ptr` must point to a valid object with a vtable.
-pub unsafe fn trigger_on_read(callback_ptr: *mut c_void, context_ptr: *mut c_void) {
-    if callback_ptr.is_null() {
-        return;
-    }
-    unsafe {
-        let vtable = *(callback_ptr as *mut *mut *mut c_void);
-        let func_ptr = *vtable.offset(2); // Offset 2 is OnRead (offset 8 bytes)
-        let func: FnOnRead = std::mem::transmute(func_ptr);
-        func(callback_ptr, context_ptr);
+/// Proxy failures (rejected auth, `407`/`502` replies, handshake timeouts) come back as
+/// ordinary `io::Error`s and are reported to the OCX through the same `OnError` slot as a
+/// failed direct connect.
+pub(crate) async fn open_stream(
+    config: &NetworkConfig,
+    host: &str,
+    port: u16,
+    report: &mut DialReport,
+) -> io::Result<TcpStream> {
+    if config.proxy.enabled {
+        proxy::connect(&config.proxy, host, port).await
+    } else {
+        dial::connect(&config.connect, host, port, report).await
     }
 }
 
-type FnOnReadReady = unsafe extern "thiscall" fn(this: *mut c_void, context: *mut c_void) -> u8;
-
-/// Triggers the C++ `OnReadReady` virtual callback (offset 3 / 12 bytes).
-///
-/// # Safety
-///
-/// This function is unsafe because it dereferences raw pointers and invokes external
-/// C++ `thiscall` functions. The `callback_ptr` must point to a valid object with a vtable.
-pub unsafe fn trigger_on_read_ready(callback_ptr: *mut c_void, context_ptr: *mut c_void) -> bool {
-    if callback_ptr.is_null() {
-        return false;
+/// Opens the full transport stack for `host:port`: TCP or proxy tunnel, then TLS when a
+/// `[[network.tls.rules]]` entry matches, or a WebSocket bridge when a
+/// `[[network.transports]]` rule selects one. In replay mode the recorded session stands in
+/// for all of it, and in bouncer mode the loopback connection to the bouncer does.
+pub(crate) async fn open_transport(
+    config: &NetworkConfig,
+    host: &str,
+    port: u16,
+    report: &mut DialReport,
+) -> io::Result<BoxedTransport> {
+    if config.replay.enabled {
+        return replay::open(&config.replay, host, port).await;
+    }
+    if config.bouncer.enabled && !bouncer::is_bouncer() {
+        return bouncer::attach(config, host, port).await;
     }
-    unsafe {
-        let vtable = *(callback_ptr as *mut *mut *mut c_void);
-        let func_ptr = *vtable.offset(3); // Offset 3 is OnReadReady (offset 12 bytes)
-        let func: FnOnReadReady = std::mem::transmute(func_ptr);
-        func(callback_ptr, context_ptr) != 0
+    if let Some(rule) = websocket::rule_for(&config.transports, host, port) {
+        return websocket::connect(config, rule, host, port, report).await;
+    }
+
+    let stream = open_stream(config, host, port, report).await?;
+    match tls::rule_for(&config.tls, host, port) {
+        Some(rule) => Ok(Box::new(
+            tls::connect(&config.tls, rule, host, stream).await?,
+        )),
+        None => Ok(Box::new(stream)),
     }
 }
 
@@ -97,16 +140,47 @@ pub fn create_socket() -> u32 {
 }
 
 /// Closes the socket, terminating reader/writer tasks.
+///
+/// The reader is aborted immediately. Anything still queued gets up to `linger_ms` to reach
+/// the wire before the writer half-closes the connection; after that the writer is aborted.
 pub fn close_socket(id: u32) {
     log::info!("network::close_socket called for ID: {}", id);
 
-    if let Ok(mut reg) = get_registry().lock() {
-        if let Some(socket_arc) = reg.remove(&id) {
-            if let Ok(mut socket) = socket_arc.lock() {
-                socket.closed = true;
-                socket.tx = None;
+    let socket_arc = match get_registry().lock() {
+        Ok(mut reg) => reg.remove(&id),
+        Err(_) => None,
+    };
+    let Some(socket_arc) = socket_arc else {
+        return;
+    };
+    let Ok(mut socket) = socket_arc.lock() else {
+        return;
+    };
+
+    log::info!("Final socket stats: {}", socket.snapshot());
+    recorder::finish(id, &socket.host, socket.port);
+    reconnect::forget(id);
+    socket.closed = true;
+    socket.rx_space.notify_one();
+    if let Some(reader) = socket.reader_task.take() {
+        reader.abort();
+    }
+    if let Some(queue) = socket.send_queue.take() {
+        queue.close();
+    }
+    if let Some(writer) = socket.writer_task.take() {
+        let linger = socket.linger;
+        get_rt().spawn(async move {
+            tokio::time::sleep(linger).await;
+            if !writer.is_finished() {
+                log::info!(
+                    "Socket {} did not flush within {:?}, dropping queued data.",
+                    id,
+                    linger
+                );
+                writer.abort();
             }
-        }
+        });
     }
 }
 
@@ -118,7 +192,17 @@ pub fn connect_socket(id: u32, host: String, port: u16) -> bool {
         host,
         port
     );
+    connect_socket_with_config(id, host, port, network_config())
+}
 
+/// Same as [`connect_socket`], but with an explicit `[network]` configuration instead of
+/// the one passed to [`init`].
+pub fn connect_socket_with_config(
+    id: u32,
+    host: String,
+    port: u16,
+    config: Arc<NetworkConfig>,
+) -> bool {
     let socket_arc = if let Ok(reg) = get_registry().lock() {
         match reg.get(&id) {
             Some(arc) => arc.clone(),
@@ -128,131 +212,335 @@ pub fn connect_socket(id: u32, host: String, port: u16) -> bool {
         return false;
     };
 
+    // A connect to a listed directory server may fail over to the others; anything else
+    // has exactly one target.
+    let candidates = failover::candidates(&config.directory, &host, port);
+    let failover_enabled = candidates.as_ref().is_some_and(|c| c.len() > 1);
+    let candidates = candidates.unwrap_or_else(|| {
+        vec![failover::Candidate {
+            entry: host.clone(),
+            host,
+            port,
+        }]
+    });
+
     let rt = get_rt();
     let socket_arc_clone = socket_arc.clone();
 
     rt.spawn(async move {
-        log::info!("Tokio task attempting connection to {}:{}...", host, port);
-        match TcpStream::connect((host.as_str(), port)).await {
-            Ok(stream) => {
-                log::info!(
-                    "Tokio connection to {}:{} established successfully!",
-                    host,
-                    port
-                );
-                let (mut read_half, mut write_half) = stream.into_split();
-                let (tx, mut rx) = unbounded_channel::<Vec<u8>>();
-
-                // Update socket status
-                let mut callback_to_trigger = None;
-                let mut context_to_trigger = None;
-                {
-                    if let Ok(mut socket) = socket_arc_clone.lock() {
-                        socket.tx = Some(tx);
-                        socket.connected = true;
-                        if !socket.callback_ptr.is_null() {
-                            callback_to_trigger = Some(socket.callback_ptr);
-                            context_to_trigger = Some(socket.context_ptr);
-                        }
+        for candidate in &candidates {
+            // One report per candidate: `record_dial` adds its attempts to the socket's.
+            let mut report = DialReport::default();
+            let (host, port) = rewrite::apply(&config.rewrite, &candidate.host, candidate.port);
+            if let Ok(mut socket) = socket_arc_clone.lock() {
+                if socket.closed {
+                    return;
+                }
+                socket.host = host.clone();
+                socket.port = port;
+            }
+
+            log::info!("Tokio task attempting connection to {}:{}...", host, port);
+            let result = open_transport(&config, &host, port, &mut report).await;
+            if let Ok(mut socket) = socket_arc_clone.lock() {
+                socket.stats.record_dial(&report);
+            }
+            match result {
+                Ok(transport) => {
+                    log::info!(
+                        "Tokio connection to {}:{} established successfully!",
+                        host,
+                        port
+                    );
+                    if failover_enabled {
+                        failover::record_success(&candidate.entry);
                     }
+                    attach_transport(id, &socket_arc_clone, transport, config);
+
+                    // If callback is already registered, trigger OnWrite immediately
+                    if let Some(events) = registered_events(&socket_arc_clone) {
+                        log::info!("Triggering OnWrite for socket {}", id);
+                        events.on_write(id);
+                    }
+                    return;
+                }
+                Err(e) => {
+                    log::error!("Tokio connection failed to {}:{}: {:?}", host, port, e);
                 }
+            }
+        }
+
+        if failover_enabled {
+            log::error!(
+                "All {} directory servers failed for socket {}",
+                candidates.len(),
+                id
+            );
+        }
+        // Trigger error callback
+        if let Some(events) = registered_events(&socket_arc_clone) {
+            events.on_error(id);
+        }
+    });
+
+    true
+}
+
+/// Returns the event sink registered for the socket, if any.
+pub(crate) fn registered_events(
+    socket_arc: &Arc<Mutex<RustSocket>>,
+) -> Option<Arc<dyn SocketEvents>> {
+    socket_arc.lock().ok()?.events.clone()
+}
 
-                // If callback is already registered, trigger OnWrite immediately
-                if let (Some(cb), Some(ctx)) = (callback_to_trigger, context_to_trigger) {
-                    log::info!("Triggering OnWrite for socket {}", id);
-                    unsafe { trigger_on_write(cb, ctx) };
+/// Marks the socket connected over `transport` and spawns its writer and reader tasks.
+///
+/// Used both for the initial connect and by the reconnect supervisor, which swaps a fresh
+/// transport in underneath the same socket ID. While the socket has a
+/// [`Diversion`](reconnect::Diversion) set, what the server sends goes to the supervisor
+/// instead of `rx_buffer`.
+pub(crate) fn attach_transport(
+    id: u32,
+    socket_arc: &Arc<Mutex<RustSocket>>,
+    transport: BoxedTransport,
+    config: Arc<NetworkConfig>,
+) {
+    let (mut read_half, mut write_half) = tokio::io::split(transport);
+    let queue = Arc::new(SendQueue::new(
+        config.buffers.tx_limit.unwrap_or(DEFAULT_TX_LIMIT),
+    ));
+    let rx_limit = config.buffers.rx_limit.unwrap_or(DEFAULT_RX_LIMIT).max(1);
+
+    // Update socket status
+    if let Ok(mut socket) = socket_arc.lock() {
+        if socket.closed {
+            // The OCX closed the socket while the connection was being set up.
+            return;
+        }
+        socket.send_queue = Some(queue.clone());
+        socket.connected = true;
+        socket.stats.connected_at = Some(std::time::SystemTime::now());
+        socket.linger = Duration::from_millis(config.linger_ms.unwrap_or(DEFAULT_LINGER_MS));
+    }
+
+    // Spawn Writer task. It runs until the queue is closed and drained, then half-closes
+    // the connection so the server sees a FIN only after everything queued (e.g. QUIT).
+    let mut limiter = RateLimiter::from_config(&config.rate_limit);
+    let socket_arc_writer = socket_arc.clone();
+    let writer = tokio::spawn(async move {
+        let mut framer = LineFramer::new();
+        while let Some(data) = queue.next(&mut limiter).await {
+            if let Err(e) = write_half.write_all(&data).await {
+                log::error!("Writer task write_all error: {:?}", e);
+                return;
+            }
+            let lines = framer.push(&data);
+            if let Ok(mut socket) = socket_arc_writer.lock() {
+                socket.stats.record_lines_out(&lines);
+            }
+            framing::publish(id, Direction::Out, &lines);
+            // The OCX was turned away by a full queue: tell it there is room again.
+            if queue.take_unblocked() {
+                if let Some(events) = registered_events(&socket_arc_writer) {
+                    events.on_write(id);
                 }
+            }
+        }
+        if let Err(e) = write_half.shutdown().await {
+            log::debug!("Socket {} write shutdown failed: {:?}", id, e);
+        }
+    });
 
-                // Spawn Writer task
-                let socket_arc_writer = socket_arc_clone.clone();
-                tokio::spawn(async move {
-                    while let Some(data) = rx.recv().await {
-                        let is_closed = socket_arc_writer.lock().map(|s| s.closed).unwrap_or(false);
-                        if is_closed {
-                            break;
-                        }
-                        if let Err(e) = write_half.write_all(&data).await {
-                            log::error!("Writer task write_all error: {:?}", e);
-                            break;
+    // Spawn Reader task
+    let socket_arc_reader = socket_arc.clone();
+    let watchdog = config.watchdog.enabled.then(|| {
+        (
+            Duration::from_secs(
+                config
+                    .watchdog
+                    .idle_secs
+                    .unwrap_or(DEFAULT_WATCHDOG_IDLE_SECS),
+            ),
+            Duration::from_secs(
+                config
+                    .watchdog
+                    .pong_timeout_secs
+                    .unwrap_or(DEFAULT_WATCHDOG_PONG_TIMEOUT_SECS),
+            ),
+        )
+    });
+    let rx_space = socket_arc
+        .lock()
+        .map(|s| s.rx_space.clone())
+        .unwrap_or_default();
+    let reader = tokio::spawn(async move {
+        let mut buf = [0u8; 4096];
+        let mut framer = LineFramer::new();
+        let mut awaiting_pong = false;
+        loop {
+            // Stop reading while the OCX has a full buffer to drain, leaving the rest in the
+            // kernel so TCP flow control pushes back on the server.
+            let room = loop {
+                let space = rx_space.notified();
+                match socket_arc_reader.lock() {
+                    Ok(socket) if !socket.closed => {
+                        let room = rx_limit.saturating_sub(socket.rx_buffer.len());
+                        if room > 0 {
+                            break room.min(buf.len());
                         }
                     }
-                });
-
-                // Spawn Reader task
-                let socket_arc_reader = socket_arc_clone.clone();
-                tokio::spawn(async move {
-                    let mut buf = [0u8; 4096];
-                    loop {
-                        let is_closed = socket_arc_reader.lock().map(|s| s.closed).unwrap_or(false);
-                        if is_closed {
+                    _ => break 0,
+                }
+                space.await;
+            };
+            if room == 0 {
+                break;
+            }
+            let buf = &mut buf[..room];
+
+            let result = match watchdog {
+                Some((idle, pong_timeout)) => {
+                    let limit = if awaiting_pong { pong_timeout } else { idle };
+                    match tokio::time::timeout(limit, read_half.read(buf)).await {
+                        Ok(result) => result,
+                        Err(_) if !awaiting_pong => {
+                            log::info!(
+                                "Socket {} idle for {:?}, sending PING to check the connection.",
+                                id,
+                                idle
+                            );
+                            if let Ok(mut socket) = socket_arc_reader.lock() {
+                                let ping = format!("PING {}\r\n", socket.host);
+                                enqueue(&mut socket, ping.as_bytes());
+                            }
+                            awaiting_pong = true;
+                            continue;
+                        }
+                        Err(_) => {
+                            log::warn!(
+                                "Socket {} got no reply to PING within {:?}, treating it as disconnected.",
+                                id,
+                                pong_timeout
+                            );
+                            handle_disconnect(id, socket_arc_reader, config, true);
                             break;
                         }
-
-                        match read_half.read(&mut buf).await {
-                            Ok(0) => {
-                                log::info!("Socket {} closed by remote.", id);
-                                let mut callback = None;
-                                let mut context = None;
-                                {
-                                    if let Ok(socket) = socket_arc_reader.lock() {
-                                        if !socket.callback_ptr.is_null() {
-                                            callback = Some(socket.callback_ptr);
-                                            context = Some(socket.context_ptr);
-                                        }
+                    }
+                }
+                None => read_half.read(buf).await,
+            };
+
+            match result {
+                Ok(0) => {
+                    log::info!("Socket {} closed by remote.", id);
+                    handle_disconnect(id, socket_arc_reader, config, true);
+                    break;
+                }
+                Ok(n) => {
+                    // Any traffic proves the connection is alive, not just the PONG itself.
+                    awaiting_pong = false;
+                    let lines = framer.push(&buf[..n]);
+                    let mut events = None;
+                    {
+                        if let Ok(mut socket) = socket_arc_reader.lock() {
+                            recorder::record(
+                                id,
+                                Direction::In,
+                                &socket.host,
+                                socket.port,
+                                &buf[..n],
+                            );
+                            socket.stats.record_in(&buf[..n], &lines);
+                            reconnect::capture_incoming(&mut socket, &lines);
+                            let data = match socket.resume.as_mut() {
+                                Some(diversion) => {
+                                    let rest = diversion.divert(&buf[..n]);
+                                    if rest.is_some() {
+                                        socket.resume = None;
                                     }
+                                    rest
                                 }
-                                if let (Some(cb), Some(ctx)) = (callback, context) {
-                                    unsafe { trigger_on_read(cb, ctx) };
-                                }
-                                break;
-                            }
-                            Ok(n) => {
-                                let mut callback = None;
-                                let mut context = None;
-                                {
-                                    if let Ok(mut socket) = socket_arc_reader.lock() {
-                                        socket.rx_buffer.extend_from_slice(&buf[..n]);
-                                        if !socket.callback_ptr.is_null() {
-                                            callback = Some(socket.callback_ptr);
-                                            context = Some(socket.context_ptr);
-                                        }
-                                    }
-                                }
-                                if let (Some(cb), Some(ctx)) = (callback, context) {
-                                    unsafe { trigger_on_read_ready(cb, ctx) };
-                                }
-                            }
-                            Err(e) => {
-                                log::error!("Reader task read error: {:?}", e);
-                                break;
+                                None => Some(&buf[..n]),
+                            };
+                            if let Some(data) = data.filter(|d| !d.is_empty()) {
+                                socket.rx_buffer.extend_from_slice(data);
+                                events = socket.events.clone();
                             }
                         }
                     }
-                });
-            }
-            Err(e) => {
-                log::error!("Tokio connection failed to {}:{}: {:?}", host, port, e);
-                // Trigger error callback
-                let mut callback = None;
-                let mut context = None;
-                if let Ok(socket) = socket_arc_clone.lock() {
-                    if !socket.callback_ptr.is_null() {
-                        callback = Some(socket.callback_ptr);
-                        context = Some(socket.context_ptr);
+                    framing::publish(id, Direction::In, &lines);
+                    if let Some(events) = events {
+                        events.on_read_ready(id);
                     }
                 }
-                if let (Some(cb), Some(ctx)) = (callback, context) {
-                    unsafe {
-                        let vtable = *(cb as *mut *mut *mut c_void);
-                        let error_func: FnOnWrite = std::mem::transmute(*vtable.offset(1)); // Offset 1 is OnError
-                        error_func(cb, ctx);
-                    }
+                Err(e) => {
+                    log::error!("Reader task read error: {:?}", e);
+                    handle_disconnect(id, socket_arc_reader, config, false);
+                    break;
                 }
             }
         }
     });
 
-    true
+    if let Ok(mut socket) = socket_arc.lock() {
+        socket.reader_task = Some(reader.abort_handle());
+        socket.writer_task = Some(writer.abort_handle());
+    }
+}
+
+/// Decides what happens once a connected socket's reader stops.
+///
+/// With `[network.reconnect]` enabled, a channel session (one that has sent JOIN) that can
+/// register again on its own (see [`Registration::is_resumable`](reconnect::Registration))
+/// is handed to the reconnect supervisor and the OCX is not told anything. A channel
+/// session that cannot, such as one whose AUTH answer could not be reproduced, is reported
+/// closed so that the OCX reconnects and authenticates itself. Otherwise a remote close, or
+/// a stall detected by the watchdog, is reported through `OnRead`, as before. A resumed
+/// connection dropping while it registers is left to the supervisor.
+fn handle_disconnect(
+    id: u32,
+    socket_arc: Arc<Mutex<RustSocket>>,
+    config: Arc<NetworkConfig>,
+    mut notify_close: bool,
+) {
+    let resumable = if let Ok(mut socket) = socket_arc.lock() {
+        if socket.closed {
+            return;
+        }
+        if socket.reconnecting {
+            // Dropping the diversion tells the supervisor the attempt failed.
+            socket.resume = None;
+            socket.connected = false;
+            return;
+        }
+        let session = config.reconnect.enabled && socket.joined;
+        let resumable = session && socket.registration.is_resumable();
+        if session && !resumable {
+            log::info!(
+                "Socket {} cannot register again by itself, leaving the reconnect to the OCX.",
+                id
+            );
+            notify_close = true;
+        }
+        if resumable {
+            if let Some(queue) = socket.send_queue.take() {
+                queue.close();
+            }
+            socket.connected = false;
+            socket.reconnecting = true;
+        }
+        resumable
+    } else {
+        false
+    };
+
+    if resumable {
+        tokio::spawn(reconnect::supervise(id, socket_arc, config));
+    } else if notify_close {
+        if let Some(events) = registered_events(&socket_arc) {
+            events.on_read(id);
+        }
+    }
 }
 
 /// Shuts down writing half of the connection.
@@ -262,13 +550,20 @@ pub fn shutdown_socket(id: u32) {
     if let Ok(reg) = get_registry().lock() {
         if let Some(socket_arc) = reg.get(&id) {
             if let Ok(mut socket) = socket_arc.lock() {
-                socket.tx = None;
+                // Closing the queue lets the writer drain what is already queued, then send
+                // FIN. The reader keeps running until the server closes its side.
+                if let Some(queue) = socket.send_queue.take() {
+                    queue.close();
+                }
             }
         }
     }
 }
 
 /// Copies buffered incoming bytes to target buffer. Returns number of bytes read.
+///
+/// The bytes are copied once, straight into `buf`; `advance` then releases them from the
+/// front of `rx_buffer` without moving what is left.
 pub fn receive_socket(id: u32, buf: &mut [u8]) -> i32 {
     if let Ok(reg) = get_registry().lock() {
         if let Some(socket_arc) = reg.get(&id) {
@@ -276,7 +571,8 @@ pub fn receive_socket(id: u32, buf: &mut [u8]) -> i32 {
                 let to_copy = std::cmp::min(buf.len(), socket.rx_buffer.len());
                 if to_copy > 0 {
                     buf[..to_copy].copy_from_slice(&socket.rx_buffer[..to_copy]);
-                    socket.rx_buffer.drain(0..to_copy);
+                    socket.rx_buffer.advance(to_copy);
+                    socket.rx_space.notify_one();
                     return to_copy as i32;
                 }
             }
@@ -285,23 +581,98 @@ pub fn receive_socket(id: u32, buf: &mut [u8]) -> i32 {
     0
 }
 
+/// Queues `data` for the writer task, recording it and counting it in the socket's stats.
+pub(crate) fn enqueue(socket: &mut RustSocket, data: &[u8]) -> bool {
+    let sent = match socket.send_queue {
+        Some(ref queue) => queue.push(data),
+        None => false,
+    };
+    if sent {
+        record_sent(socket, data);
+    }
+    sent
+}
+
+fn record_sent(socket: &mut RustSocket, data: &[u8]) {
+    recorder::record(socket.id, Direction::Out, &socket.host, socket.port, data);
+    socket.stats.record_out(data);
+}
+
 /// Sends data asynchronously via the writer task.
+///
+/// While the send queue holds more than `tx_limit` bytes the data is refused, as a
+/// non-blocking `send` would with `WSAEWOULDBLOCK`, and `OnWrite` fires once the writer has
+/// caught up so the OCX can send it again. The OCX's thread is never parked.
 pub fn send_socket(id: u32, data: &[u8]) -> bool {
-    let mut sent = false;
-    if let Ok(reg) = get_registry().lock() {
-        if let Some(socket_arc) = reg.get(&id) {
-            if let Ok(socket) = socket_arc.lock() {
-                if let Some(ref tx) = socket.tx {
-                    let _ = tx.send(data.to_vec());
-                    sent = true;
-                }
-            }
+    let Some(socket_arc) = get_registry()
+        .lock()
+        .ok()
+        .and_then(|reg| reg.get(&id).cloned())
+    else {
+        return false;
+    };
+    let Ok(mut socket) = socket_arc.lock() else {
+        return false;
+    };
+
+    if socket.reconnecting {
+        // Nothing reaches the server before the session is registered again.
+        return outbox::hold(data);
+    }
+    let offered = match socket.send_queue {
+        Some(ref queue) => queue.try_push(data),
+        None => Err(Refused::Closed),
+    };
+    match offered {
+        Ok(()) => {
+            record_sent(&mut socket, data);
+            reconnect::capture_outgoing(&mut socket, data);
+            // Messages left over from a crashed session follow their room's JOIN.
+            outbox::flush_joined(&mut socket, data);
+            true
+        }
+        Err(Refused::Full) => {
+            log::debug!(
+                "Socket {} send queue is full, refusing {} bytes",
+                id,
+                data.len()
+            );
+            false
         }
+        Err(Refused::Closed) => false,
     }
-    sent
+}
+
+/// Returns how much outgoing traffic is waiting in the socket's send queue.
+pub fn queue_depth(id: u32) -> Option<QueueDepth> {
+    let reg = get_registry().lock().ok()?;
+    let socket = reg.get(&id)?.lock().ok()?;
+    socket.send_queue.as_ref().map(|q| q.depth())
+}
+
+/// Returns traffic and health counters for one socket.
+pub fn socket_stats(id: u32) -> Option<SocketSnapshot> {
+    let socket_arc = get_registry().lock().ok()?.get(&id)?.clone();
+    let socket = socket_arc.lock().ok()?;
+    Some(socket.snapshot())
+}
+
+/// Returns traffic and health counters for every open socket, ordered by ID.
+pub fn all_socket_stats() -> Vec<SocketSnapshot> {
+    let sockets: Vec<_> = match get_registry().lock() {
+        Ok(reg) => reg.values().cloned().collect(),
+        Err(_) => return Vec::new(),
+    };
+    let mut snapshots: Vec<_> = sockets
+        .iter()
+        .filter_map(|s| s.lock().ok().map(|s| s.snapshot()))
+        .collect();
+    snapshots.sort_by_key(|s| s.id);
+    snapshots
 }
 
 /// Associates callback delegates to the socket for async event dispatch.
+#[cfg(all(windows, target_arch = "x86"))]
 pub fn register_socket(
     socket_id: u32,
     callback_ptr: *mut c_void,
@@ -313,7 +684,16 @@ pub fn register_socket(
         callback_ptr,
         context_ptr
     );
+    set_socket_events(
+        socket_id,
+        Arc::new(OcxEvents::new(callback_ptr, context_ptr)),
+    )
+}
 
+/// Attaches an event sink to the socket, replacing any previous one.
+///
+/// If the socket is already connected, `on_write` fires immediately.
+pub fn set_socket_events(socket_id: u32, events: Arc<dyn SocketEvents>) -> bool {
     let socket_arc = if let Ok(reg) = get_registry().lock() {
         match reg.get(&socket_id) {
             Some(arc) => arc.clone(),
@@ -332,8 +712,7 @@ pub fn register_socket(
     let mut trigger_write = false;
     {
         if let Ok(mut socket) = socket_arc.lock() {
-            socket.callback_ptr = callback_ptr;
-            socket.context_ptr = context_ptr;
+            socket.events = Some(events.clone());
             if socket.connected {
                 trigger_write = true;
             }
@@ -345,8 +724,252 @@ pub fn register_socket(
             "Socket {} already connected. Triggering OnWrite immediately.",
             socket_id
         );
-        unsafe { trigger_on_write(callback_ptr, context_ptr) };
+        events.on_write(socket_id);
     }
 
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    use crate::config::BufferConfig;
+    use crate::network::events::{ChannelEvents, SocketEvent};
+
+    /// Lines queued before the socket is shut down; more than the kernel buffers take while
+    /// the server is not reading yet.
+    fn burst() -> Vec<u8> {
+        (0..2000)
+            .map(|i| format!("PRIVMSG #room :line {} {}\r\n", i, "x".repeat(100)))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    /// Connects a new socket to a server that waits a moment, then reads until FIN and
+    /// returns everything it got.
+    fn connect_to_slow_reader() -> (u32, std::thread::JoinHandle<Vec<u8>>) {
+        let (id, _rx, server) = connect_to_slow_reader_with(NetworkConfig::default());
+        (id, server)
+    }
+
+    fn connect_to_slow_reader_with(
+        config: NetworkConfig,
+    ) -> (
+        u32,
+        tokio::sync::mpsc::UnboundedReceiver<SocketEvent>,
+        std::thread::JoinHandle<Vec<u8>>,
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            std::thread::sleep(Duration::from_millis(200));
+            let mut received = Vec::new();
+            stream.read_to_end(&mut received).unwrap();
+            received
+        });
+
+        let id = create_socket();
+        let (events, mut rx) = ChannelEvents::new();
+        assert!(set_socket_events(id, Arc::new(events)));
+        assert!(connect_socket_with_config(
+            id,
+            "127.0.0.1".to_string(),
+            port,
+            Arc::new(config),
+        ));
+        let event = get_rt()
+            .block_on(async { tokio::time::timeout(Duration::from_secs(5), rx.recv()).await });
+        assert_eq!(event, Ok(Some(SocketEvent::Write(id))));
+        (id, rx, server)
+    }
+
+    #[test]
+    fn a_full_queue_refuses_sends_until_on_write() {
+        let (id, mut rx, server) = connect_to_slow_reader_with(NetworkConfig {
+            buffers: BufferConfig {
+                tx_limit: Some(16 * 1024),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let data = burst();
+        let mut chunks = data.chunks(4096);
+        let mut sent = Vec::new();
+        let mut refusals = 0;
+        let mut chunk = chunks.next();
+        while let Some(next) = chunk {
+            let started = std::time::Instant::now();
+            if send_socket(id, next) {
+                sent.extend_from_slice(next);
+                chunk = chunks.next();
+                continue;
+            }
+            // Refused straight away rather than parked until the writer catches up.
+            assert!(started.elapsed() < Duration::from_millis(50));
+            refusals += 1;
+            let event = get_rt()
+                .block_on(async { tokio::time::timeout(Duration::from_secs(5), rx.recv()).await });
+            assert_eq!(event, Ok(Some(SocketEvent::Write(id))));
+        }
+        assert!(refusals > 0);
+        shutdown_socket(id);
+
+        let received = server.join().unwrap();
+        assert!(received == data);
+        assert!(sent == data);
+        close_socket(id);
+    }
+
+    #[test]
+    fn each_failed_over_dial_is_counted_once() {
+        // Two ports nothing listens on.
+        let ports: Vec<u16> = (0..2)
+            .map(|_| TcpListener::bind("127.0.0.1:0").unwrap())
+            .map(|l| l.local_addr().unwrap().port())
+            .collect();
+        let mut config = NetworkConfig::default();
+        config.directory.servers = ports.iter().map(|p| format!("127.0.0.1:{}", p)).collect();
+
+        let id = create_socket();
+        let (events, mut rx) = ChannelEvents::new();
+        assert!(set_socket_events(id, Arc::new(events)));
+        assert!(connect_socket_with_config(
+            id,
+            "127.0.0.1".to_string(),
+            ports[0],
+            Arc::new(config),
+        ));
+        let event = get_rt()
+            .block_on(async { tokio::time::timeout(Duration::from_secs(5), rx.recv()).await });
+        assert_eq!(event, Ok(Some(SocketEvent::Error(id))));
+        assert_eq!(socket_stats(id).unwrap().stats.connect_attempts, 2);
+        close_socket(id);
+    }
+
+    #[test]
+    fn shutdown_flushes_queued_data_before_fin() {
+        let (id, server) = connect_to_slow_reader();
+        let data = burst();
+        for chunk in data.chunks(4096) {
+            assert!(send_socket(id, chunk));
+        }
+        shutdown_socket(id);
+        assert!(!send_socket(id, b"PRIVMSG #room :too late\r\n"));
+
+        let received = server.join().unwrap();
+        assert_eq!(received.len(), data.len());
+        assert!(received == data);
+        close_socket(id);
+    }
+
+    #[test]
+    fn close_without_shutdown_flushes_the_tail_within_the_linger() {
+        let (id, server) = connect_to_slow_reader();
+        let mut data = burst();
+        data.extend_from_slice(b"PRIVMSG #room :last\r\n");
+        for chunk in data.chunks(4096) {
+            assert!(send_socket(id, chunk));
+        }
+        close_socket(id);
+
+        let received = server.join().unwrap();
+        assert!(received == data);
+    }
+
+    #[test]
+    fn send_after_close_is_rejected_and_not_written() {
+        let (id, server) = connect_to_slow_reader();
+        close_socket(id);
+        assert!(!send_socket(id, b"PRIVMSG #room :too late\r\n"));
+
+        assert!(server.join().unwrap().is_empty());
+    }
+
+    #[test]
+    fn close_after_shutdown_aborts_both_tasks_once_the_linger_expires() {
+        const LINGER: Duration = Duration::from_millis(100);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (closed_tx, closed_rx) = std::sync::mpsc::channel();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            closed_rx.recv().unwrap();
+            // The reader is gone, so this must not be reported.
+            stream.write_all(b"PING :late\r\n").unwrap();
+            std::thread::sleep(LINGER + Duration::from_millis(500));
+
+            // Reading only now, after the writer has been aborted: the connection ends
+            // without the rest of the queue.
+            let started = std::time::Instant::now();
+            let mut received = Vec::new();
+            let _ = stream.read_to_end(&mut received);
+            (received.len(), started.elapsed())
+        });
+
+        let config = NetworkConfig {
+            linger_ms: Some(LINGER.as_millis() as u64),
+            buffers: BufferConfig {
+                tx_limit: Some(64 * 1024 * 1024),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let id = create_socket();
+        let (events, mut rx) = ChannelEvents::new();
+        assert!(set_socket_events(id, Arc::new(events)));
+        assert!(connect_socket_with_config(
+            id,
+            "127.0.0.1".to_string(),
+            port,
+            Arc::new(config),
+        ));
+        let event = get_rt()
+            .block_on(async { tokio::time::timeout(Duration::from_secs(5), rx.recv()).await });
+        assert_eq!(event, Ok(Some(SocketEvent::Write(id))));
+
+        // Far more than the kernel buffers hold while the server is not reading.
+        let data = burst().repeat(64);
+        for chunk in data.chunks(64 * 1024) {
+            assert!(send_socket(id, chunk));
+        }
+        shutdown_socket(id);
+        close_socket(id);
+        closed_tx.send(()).unwrap();
+
+        let event = get_rt()
+            .block_on(async { tokio::time::timeout(Duration::from_millis(300), rx.recv()).await });
+        assert!(
+            !matches!(event, Ok(Some(_))),
+            "event after close: {:?}",
+            event
+        );
+        let (received, elapsed) = server.join().unwrap();
+        assert!(
+            received < data.len(),
+            "{} of {} bytes",
+            received,
+            data.len()
+        );
+        assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn close_after_shutdown_keeps_the_tail() {
+        let (id, server) = connect_to_slow_reader();
+        let mut data = burst();
+        data.extend_from_slice(b"PRIVMSG #room :last\r\n");
+        for chunk in data.chunks(4096) {
+            assert!(send_socket(id, chunk));
+        }
+        shutdown_socket(id);
+        close_socket(id);
+
+        let received = server.join().unwrap();
+        assert!(received.ends_with(b"PRIVMSG #room :last\r\n"));
+        assert!(received == data);
+    }
+}
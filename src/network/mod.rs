@@ -3,10 +3,32 @@
 #![allow(clippy::collapsible_if)]
 #![allow(clippy::not_unsafe_ptr_arg_deref)]
 
+pub mod bouncer;
+pub mod dial;
+pub mod dispatch;
+pub mod events;
+pub mod failover;
+pub mod framing;
+pub mod gatekeeper;
 pub mod manager;
+pub mod outbox;
+pub mod pattern;
+pub mod proxy;
+pub mod reconnect;
+pub mod recorder;
+pub mod replay;
+pub mod rewrite;
+pub mod send_queue;
 pub mod socket;
+pub mod tls;
+pub mod transport;
+pub mod websocket;
 
+pub use events::{ChannelEvents, SocketEvent, SocketEvents};
+#[cfg(all(windows, target_arch = "x86"))]
+pub use manager::register_socket;
 pub use manager::{
-    close_socket, connect_socket, create_socket, receive_socket, register_socket, send_socket,
-    shutdown_socket,
+    all_socket_stats, close_socket, connect_socket, connect_socket_with_config, create_socket,
+    init, queue_depth, receive_socket, send_socket, set_socket_events, shutdown_socket,
+    socket_stats,
 };
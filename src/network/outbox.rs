@@ -0,0 +1,314 @@
+//! Outgoing messages held while a channel socket is reconnecting.
+//!
+//! With `[network.outbox]` enabled, PRIVMSG, WHISPER and NOTICE lines the OCX sends while
+//! its channel socket is down are kept here instead of being dropped. Each is written to the
+//! socket right after the JOIN for the room it was sent to, so it is never sent to a room
+//! the session is not in yet; private messages go with the first JOIN. The queue is
+//! mirrored to a JSON file so a crash does not lose it; anything older than `expiry_secs`
+//! is discarded rather than sent late. The file is written by a background thread, never
+//! while the socket locks are held.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::OutboxConfig;
+use crate::network::manager::enqueue;
+use crate::network::socket::RustSocket;
+use crate::protocol::message::{Command, Message};
+
+const DEFAULT_EXPIRY_SECS: u64 = 300;
+const DEFAULT_PATH: &str = "outbox.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OutboxItem {
+    /// Milliseconds since the Unix epoch.
+    pub queued_at_ms: u64,
+    /// The line without its CRLF terminator.
+    pub line: String,
+    /// The room the line was sent to, or `None` for a private message.
+    #[serde(default)]
+    pub channel: Option<String>,
+}
+
+impl OutboxItem {
+    /// Seconds since the item was queued.
+    pub fn age_secs(&self) -> u64 {
+        now_ms().saturating_sub(self.queued_at_ms) / 1000
+    }
+}
+
+struct Outbox {
+    enabled: bool,
+    expiry_secs: u64,
+    path: PathBuf,
+    items: Vec<OutboxItem>,
+}
+
+static OUTBOX: Mutex<Option<Outbox>> = Mutex::new(None);
+static WRITER: OnceLock<Sender<(PathBuf, Vec<OutboxItem>)>> = OnceLock::new();
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Applies `[network.outbox]` and, the first time, loads items left over from a previous run.
+pub fn configure(config: &OutboxConfig) {
+    let Ok(mut outbox) = OUTBOX.lock() else {
+        return;
+    };
+    let path = config
+        .path
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_PATH));
+    let expiry_secs = config.expiry_secs.unwrap_or(DEFAULT_EXPIRY_SECS);
+
+    match outbox.as_mut() {
+        Some(outbox) => {
+            outbox.enabled = config.enabled;
+            outbox.expiry_secs = expiry_secs;
+            outbox.path = path;
+        }
+        None => {
+            let items: Vec<OutboxItem> = if config.enabled {
+                fs::read_to_string(&path)
+                    .ok()
+                    .and_then(|contents| serde_json::from_str(&contents).ok())
+                    .unwrap_or_default()
+            } else {
+                Vec::new()
+            };
+            if !items.is_empty() {
+                log::info!(
+                    "Loaded {} queued message(s) from {}",
+                    items.len(),
+                    path.display()
+                );
+            }
+            *outbox = Some(Outbox {
+                enabled: config.enabled,
+                expiry_secs,
+                path,
+                items,
+            });
+        }
+    }
+}
+
+/// Hands a copy of the queue to the writer thread.
+fn save(outbox: &Outbox) {
+    let writer = WRITER.get_or_init(|| {
+        let (tx, rx) = mpsc::channel::<(PathBuf, Vec<OutboxItem>)>();
+        std::thread::spawn(move || {
+            while let Ok(mut latest) = rx.recv() {
+                // Only the newest copy needs to reach the disk.
+                while let Ok(newer) = rx.try_recv() {
+                    latest = newer;
+                }
+                write(&latest.0, &latest.1);
+            }
+        });
+        tx
+    });
+    let _ = writer.send((outbox.path.clone(), outbox.items.clone()));
+}
+
+fn write(path: &PathBuf, items: &[OutboxItem]) {
+    let result = if items.is_empty() {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    } else {
+        serde_json::to_string_pretty(items)
+            .map_err(std::io::Error::other)
+            .and_then(|json| fs::write(path, json))
+    };
+    if let Err(e) = result {
+        log::error!("Failed to save outbox {}: {}", path.display(), e);
+    }
+}
+
+/// The room a message is addressed to, if its target is a channel.
+fn channel_of(message: &Message) -> Option<String> {
+    message
+        .arg(0)
+        .filter(|target| target.starts_with(['#', '%', '&']))
+        .map(str::to_string)
+}
+
+/// Drops items older than the expiry time. Returns how many were dropped.
+fn expire(outbox: &mut Outbox) -> usize {
+    let cutoff = now_ms().saturating_sub(outbox.expiry_secs.saturating_mul(1000));
+    let before = outbox.items.len();
+    outbox.items.retain(|item| item.queued_at_ms >= cutoff);
+    before - outbox.items.len()
+}
+
+/// Holds the queueable lines in `data`. Returns false if the outbox is disabled or any
+/// line in `data` had to be dropped.
+pub fn hold(data: &[u8]) -> bool {
+    let Ok(mut guard) = OUTBOX.lock() else {
+        return false;
+    };
+    let Some(outbox) = guard.as_mut().filter(|o| o.enabled) else {
+        return false;
+    };
+
+    let mut held = 0;
+    let mut dropped = 0;
+    for line in String::from_utf8_lossy(data).split('\n') {
+        let line = line.trim_end_matches('\r');
+        let message = Message::parse(line).filter(|m| {
+            matches!(
+                m.command,
+                Command::Privmsg | Command::Whisper | Command::Notice
+            )
+        });
+        match message {
+            Some(message) => {
+                outbox.items.push(OutboxItem {
+                    queued_at_ms: now_ms(),
+                    line: line.to_string(),
+                    channel: channel_of(&message),
+                });
+                held += 1;
+            }
+            None if !line.is_empty() => {
+                log::info!("Dropping line sent while disconnected: {}", line);
+                dropped += 1;
+            }
+            None => {}
+        }
+    }
+    if held > 0 {
+        log::info!("Holding {} outgoing message(s) until reconnected", held);
+        save(outbox);
+    }
+    held > 0 && dropped == 0
+}
+
+/// Writes the unexpired items for `room`, and any private messages, to `socket` in the
+/// order they were queued. Call right after the JOIN for `room` has been queued.
+pub fn flush(socket: &mut RustSocket, room: &str) {
+    let Ok(mut guard) = OUTBOX.lock() else {
+        return;
+    };
+    let Some(outbox) = guard.as_mut().filter(|o| !o.items.is_empty()) else {
+        return;
+    };
+
+    let expired = expire(outbox);
+    if expired > 0 {
+        log::info!("Discarded {} expired queued message(s)", expired);
+    }
+    let (due, mut kept): (Vec<_>, Vec<_>) = std::mem::take(&mut outbox.items)
+        .into_iter()
+        .partition(|item| {
+            item.channel
+                .as_deref()
+                .is_none_or(|channel| channel.eq_ignore_ascii_case(room))
+        });
+    if !due.is_empty() {
+        log::info!("Sending {} queued message(s) for {}", due.len(), room);
+    }
+    for (i, item) in due.iter().enumerate() {
+        if !enqueue(socket, format!("{}\r\n", item.line).as_bytes()) {
+            // Keep whatever did not fit for the next flush, in its original order.
+            kept.extend_from_slice(&due[i..]);
+            kept.sort_by_key(|item| item.queued_at_ms);
+            break;
+        }
+    }
+    outbox.items = kept;
+    save(outbox);
+}
+
+/// Flushes the items for every room joined by the JOIN lines in `data`.
+pub fn flush_joined(socket: &mut RustSocket, data: &[u8]) {
+    for line in String::from_utf8_lossy(data).split('\n') {
+        let Some(message) = Message::parse(line.trim_end_matches('\r')) else {
+            continue;
+        };
+        if message.command != Command::Join {
+            continue;
+        }
+        for room in message.arg(0).unwrap_or_default().split(',') {
+            if !room.is_empty() {
+                flush(socket, room);
+            }
+        }
+    }
+}
+
+/// Items currently held, after dropping expired ones.
+pub fn items() -> Vec<OutboxItem> {
+    let Ok(mut guard) = OUTBOX.lock() else {
+        return Vec::new();
+    };
+    let Some(outbox) = guard.as_mut() else {
+        return Vec::new();
+    };
+    if expire(outbox) > 0 {
+        save(outbox);
+    }
+    outbox.items.clone()
+}
+
+/// Discards every held item. Returns how many there were.
+pub fn clear() -> usize {
+    let Ok(mut guard) = OUTBOX.lock() else {
+        return 0;
+    };
+    let Some(outbox) = guard.as_mut() else {
+        return 0;
+    };
+    let count = outbox.items.len();
+    outbox.items.clear();
+    save(outbox);
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::network::send_queue::SendQueue;
+
+    #[test]
+    fn held_lines_wait_for_their_room() {
+        configure(&OutboxConfig {
+            enabled: true,
+            path: Some(std::env::temp_dir().join("msnchat-outbox-test.json")),
+            ..Default::default()
+        });
+        clear();
+
+        assert!(hold(b"PRIVMSG #a :one\r\nPRIVMSG #b :two\r\n"));
+        assert!(!hold(b"PRIVMSG nick :three\r\nMODE #a +m\r\n"));
+        let channels: Vec<_> = items().into_iter().map(|i| i.channel).collect();
+        assert_eq!(
+            channels,
+            [Some("#a".to_string()), Some("#b".to_string()), None]
+        );
+
+        let mut socket = RustSocket::new(1);
+        let queue = Arc::new(SendQueue::new(1024));
+        socket.send_queue = Some(queue.clone());
+        flush_joined(&mut socket, b"JOIN #B\r\n");
+        assert_eq!(queue.depth().bulk, 2);
+        let left: Vec<_> = items().into_iter().map(|i| i.line).collect();
+        assert_eq!(left, ["PRIVMSG #a :one"]);
+
+        flush(&mut socket, "#a");
+        assert_eq!(queue.depth().bulk, 3);
+        assert!(items().is_empty());
+    }
+}
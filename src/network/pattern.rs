@@ -0,0 +1,68 @@
+//! Case-insensitive `*` wildcard matching for host rules in the `[network]` config.
+
+/// Returns true if `host` matches `pattern`, where `*` matches any run of characters
+/// (including none). Comparison ignores ASCII case.
+pub fn host_matches(pattern: &str, host: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    let host = host.to_ascii_lowercase();
+    wildcard_match(pattern.as_bytes(), host.as_bytes())
+}
+
+fn wildcard_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == text[t] {
+            p += 1;
+            t += 1;
+        } else if let Some((star_p, star_t)) = star {
+            // Let the last `*` swallow one more character and retry.
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wildcards_match_any_run_of_characters() {
+        let cases = [
+            ("dir.irc7.com", "dir.irc7.com", true),
+            ("dir.irc7.com", "DIR.IRC7.COM", true),
+            ("dir.irc7.com", "dir.irc7.co", false),
+            ("*", "", true),
+            ("*", "anything.example", true),
+            ("*.irc7.com", "dir.irc7.com", true),
+            ("*.irc7.com", "a.b.irc7.com", true),
+            ("*.irc7.com", "irc7.com", false),
+            ("dir.*.com", "dir.irc7.com", true),
+            ("dir.*.com", "dir.irc7.net", false),
+            ("*irc7*", "chat.irc7.net", true),
+            ("*.*", "localhost", false),
+            ("a*b*c", "aXbYbZc", true),
+            ("a*b*c", "aXbYcZ", false),
+            ("**", "x", true),
+            ("", "", true),
+            ("", "x", false),
+        ];
+        for (pattern, host, expected) in cases {
+            assert_eq!(
+                host_matches(pattern, host),
+                expected,
+                "{pattern:?} vs {host:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,617 @@
+//! Proxy handshakes performed by the Tokio connect path before the OCX is notified.
+//!
+//! The returned stream is already tunnelled to the requested target, so the rest of the
+//! socket registry treats it exactly like a direct connection.
+
+use base64::Engine;
+use std::io;
+use std::net::IpAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::config::{ProxyConfig, ProxyKind};
+
+const DEFAULT_PROXY_TIMEOUT_SECS: u64 = 30;
+/// Upper bound on the HTTP proxy's response headers, to avoid buffering a misbehaving proxy forever.
+const MAX_HTTP_RESPONSE_HEAD: usize = 8192;
+
+const SOCKS_VERSION: u8 = 0x05;
+const SOCKS_AUTH_VERSION: u8 = 0x01;
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_USER_PASS: u8 = 0x02;
+const METHOD_NONE_ACCEPTABLE: u8 = 0xFF;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// Opens a tunnel to `host:port` through the configured proxy.
+///
+/// The whole exchange (TCP connect plus handshake) is bounded by the proxy timeout, and an
+/// expired timer is reported as `ErrorKind::TimedOut`.
+pub async fn connect(proxy: &ProxyConfig, host: &str, port: u16) -> io::Result<TcpStream> {
+    let timeout = Duration::from_secs(proxy.timeout_secs.unwrap_or(DEFAULT_PROXY_TIMEOUT_SECS));
+    let handshake = async {
+        match proxy.kind {
+            ProxyKind::Socks5 => connect_via_socks5(proxy, host, port).await,
+            ProxyKind::Http => connect_via_http(proxy, host, port).await,
+        }
+    };
+    match tokio::time::timeout(timeout, handshake).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!(
+                "proxy {}:{} did not complete the handshake within {:?}",
+                proxy.host, proxy.port, timeout
+            ),
+        )),
+    }
+}
+
+fn credentials(proxy: &ProxyConfig) -> Option<(&str, &str)> {
+    match (&proxy.username, &proxy.password) {
+        (Some(user), Some(pass)) => Some((user.as_str(), pass.as_str())),
+        (Some(user), None) => Some((user.as_str(), "")),
+        _ => None,
+    }
+}
+
+/// Connects to the configured SOCKS5 proxy and asks it to open a tunnel to `host:port`.
+pub async fn connect_via_socks5(
+    proxy: &ProxyConfig,
+    host: &str,
+    port: u16,
+) -> io::Result<TcpStream> {
+    log::info!(
+        "Connecting to {}:{} via SOCKS5 proxy {}:{}",
+        host,
+        port,
+        proxy.host,
+        proxy.port
+    );
+    let mut stream = TcpStream::connect((proxy.host.as_str(), proxy.port)).await?;
+    socks5_handshake(&mut stream, host, port, credentials(proxy)).await?;
+    Ok(stream)
+}
+
+/// Connects to the configured HTTP proxy and issues a `CONNECT host:port` request.
+pub async fn connect_via_http(proxy: &ProxyConfig, host: &str, port: u16) -> io::Result<TcpStream> {
+    log::info!(
+        "Connecting to {}:{} via HTTP proxy {}:{}",
+        host,
+        port,
+        proxy.host,
+        proxy.port
+    );
+    let mut stream = TcpStream::connect((proxy.host.as_str(), proxy.port)).await?;
+    http_connect_handshake(&mut stream, host, port, credentials(proxy)).await?;
+    Ok(stream)
+}
+
+/// Sends an HTTP/1.1 `CONNECT` request, with Basic auth if credentials are given, and
+/// consumes the proxy's response headers.
+pub async fn http_connect_handshake(
+    stream: &mut TcpStream,
+    host: &str,
+    port: u16,
+    credentials: Option<(&str, &str)>,
+) -> io::Result<()> {
+    let authority = match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => format!("[{}]:{}", ip, port),
+        _ => format!("{}:{}", host, port),
+    };
+
+    let mut request = format!(
+        "CONNECT {0} HTTP/1.1\r\nHost: {0}\r\nProxy-Connection: Keep-Alive\r\n",
+        authority
+    );
+    if let Some((user, pass)) = credentials {
+        let token = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, pass));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", token));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // Read byte-by-byte so that nothing past the header terminator (i.e. tunnelled server
+    // data) is consumed here.
+    let mut head = Vec::with_capacity(256);
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_HTTP_RESPONSE_HEAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "HTTP proxy response headers too large",
+            ));
+        }
+        if stream.read(&mut byte).await? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "HTTP proxy closed the connection during CONNECT",
+            ));
+        }
+        head.push(byte[0]);
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    let status_line = head.lines().next().unwrap_or_default();
+    let mut parts = status_line.splitn(3, ' ');
+    let version = parts.next().unwrap_or_default();
+    let code = parts.next().and_then(|c| c.parse::<u16>().ok());
+    let reason = parts.next().unwrap_or_default();
+
+    if !version.starts_with("HTTP/") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("HTTP proxy sent a malformed status line: {:?}", status_line),
+        ));
+    }
+
+    match code {
+        Some(200..=299) => {
+            log::info!("HTTP CONNECT tunnel to {} established", authority);
+            Ok(())
+        }
+        Some(407) => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("HTTP proxy: 407 {}", reason),
+        )),
+        Some(502) => Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("HTTP proxy: 502 {}", reason),
+        )),
+        Some(code) => Err(io::Error::other(format!("HTTP proxy: {} {}", code, reason))),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("HTTP proxy sent a malformed status line: {:?}", status_line),
+        )),
+    }
+}
+
+/// Performs the RFC 1928 greeting, optional RFC 1929 authentication and CONNECT request.
+pub async fn socks5_handshake(
+    stream: &mut TcpStream,
+    host: &str,
+    port: u16,
+    credentials: Option<(&str, &str)>,
+) -> io::Result<()> {
+    // 1. Method negotiation
+    let greeting: &[u8] = if credentials.is_some() {
+        &[SOCKS_VERSION, 2, METHOD_NO_AUTH, METHOD_USER_PASS]
+    } else {
+        &[SOCKS_VERSION, 1, METHOD_NO_AUTH]
+    };
+    stream.write_all(greeting).await?;
+
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("SOCKS5 proxy replied with version {}", reply[0]),
+        ));
+    }
+
+    match reply[1] {
+        METHOD_NO_AUTH => {}
+        METHOD_USER_PASS => {
+            let (user, pass) = credentials.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "SOCKS5 proxy requires credentials",
+                )
+            })?;
+            // 2. Username/password sub-negotiation
+            if user.len() > 255 || pass.len() > 255 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "SOCKS5 username or password longer than 255 bytes",
+                ));
+            }
+            let mut auth = Vec::with_capacity(3 + user.len() + pass.len());
+            auth.push(SOCKS_AUTH_VERSION);
+            auth.push(user.len() as u8);
+            auth.extend_from_slice(user.as_bytes());
+            auth.push(pass.len() as u8);
+            auth.extend_from_slice(pass.as_bytes());
+            stream.write_all(&auth).await?;
+
+            let mut status = [0u8; 2];
+            stream.read_exact(&mut status).await?;
+            if status[0] != SOCKS_AUTH_VERSION {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "SOCKS5 proxy answered authentication with version {}",
+                        status[0]
+                    ),
+                ));
+            }
+            if status[1] != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "SOCKS5 proxy rejected the username/password",
+                ));
+            }
+        }
+        METHOD_NONE_ACCEPTABLE => {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "SOCKS5 proxy accepted none of the offered auth methods",
+            ));
+        }
+        other => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("SOCKS5 proxy selected unsupported auth method {}", other),
+            ));
+        }
+    }
+
+    // 3. CONNECT request. Hostnames are sent as-is so that the proxy resolves them.
+    let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0x00];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            if host.len() > 255 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "SOCKS5 target hostname longer than 255 bytes",
+                ));
+            }
+            request.push(ATYP_DOMAIN);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut head = [0u8; 4];
+    stream.read_exact(&mut head).await?;
+    if head[0] != SOCKS_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("SOCKS5 proxy answered CONNECT with version {}", head[0]),
+        ));
+    }
+    if head[1] != 0 {
+        return Err(socks5_reply_error(head[1]));
+    }
+
+    // Drain the bound address so the stream is positioned at the tunnelled data.
+    let addr_len = match head[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len).await?;
+            len[0] as usize
+        }
+        other => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("SOCKS5 proxy replied with unknown address type {}", other),
+            ));
+        }
+    };
+    let mut bound = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut bound).await?;
+
+    log::info!("SOCKS5 tunnel to {}:{} established", host, port);
+    Ok(())
+}
+
+fn socks5_reply_error(code: u8) -> io::Error {
+    let (kind, reason) = match code {
+        0x01 => (io::ErrorKind::Other, "general SOCKS server failure"),
+        0x02 => (
+            io::ErrorKind::PermissionDenied,
+            "connection not allowed by ruleset",
+        ),
+        0x03 => (io::ErrorKind::Other, "network unreachable"),
+        0x04 => (io::ErrorKind::Other, "host unreachable"),
+        0x05 => (io::ErrorKind::ConnectionRefused, "connection refused"),
+        0x06 => (io::ErrorKind::TimedOut, "TTL expired"),
+        0x07 => (io::ErrorKind::Unsupported, "command not supported"),
+        0x08 => (io::ErrorKind::Unsupported, "address type not supported"),
+        _ => (io::ErrorKind::Other, "unknown error"),
+    };
+    io::Error::new(kind, format!("SOCKS5 proxy: {} ({:#04x})", reason, code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    use crate::network::manager::get_rt;
+
+    /// Runs the client side of the `kind` handshake for `example.com:6667` against a proxy
+    /// that follows `script`. Returns the result and the first bytes read through the tunnel.
+    fn handshake(
+        kind: ProxyKind,
+        credentials: Option<(&str, &str)>,
+        script: impl FnOnce(&mut std::net::TcpStream) + Send + 'static,
+    ) -> (io::Result<()>, Vec<u8>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let proxy = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            script(&mut stream);
+        });
+        let result = get_rt().block_on(async {
+            let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+            let result = match kind {
+                ProxyKind::Socks5 => {
+                    socks5_handshake(&mut stream, "example.com", 6667, credentials).await
+                }
+                ProxyKind::Http => {
+                    http_connect_handshake(&mut stream, "example.com", 6667, credentials).await
+                }
+            };
+            let mut tunnelled = Vec::new();
+            if result.is_ok() {
+                stream.read_to_end(&mut tunnelled).await.unwrap();
+            }
+            (result, tunnelled)
+        });
+        proxy.join().unwrap();
+        result
+    }
+
+    fn expect(stream: &mut std::net::TcpStream, bytes: &[u8]) {
+        let mut got = vec![0u8; bytes.len()];
+        stream.read_exact(&mut got).unwrap();
+        assert_eq!(got, bytes);
+    }
+
+    /// The CONNECT request for `example.com:6667`, and a success reply bound to 0.0.0.0:0.
+    fn connect_and_reply(stream: &mut std::net::TcpStream) {
+        let mut request = vec![SOCKS_VERSION, CMD_CONNECT, 0, ATYP_DOMAIN, 11];
+        request.extend_from_slice(b"example.com");
+        request.extend_from_slice(&6667u16.to_be_bytes());
+        expect(stream, &request);
+        stream
+            .write_all(&[SOCKS_VERSION, 0, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+            .unwrap();
+    }
+
+    #[test]
+    fn no_auth_tunnel_is_positioned_at_server_data() {
+        let (result, tunnelled) = handshake(ProxyKind::Socks5, None, |stream| {
+            expect(stream, &[SOCKS_VERSION, 1, METHOD_NO_AUTH]);
+            stream.write_all(&[SOCKS_VERSION, METHOD_NO_AUTH]).unwrap();
+            connect_and_reply(stream);
+            stream.write_all(b"PING :irc7\r\n").unwrap();
+        });
+        result.unwrap();
+        assert_eq!(tunnelled, b"PING :irc7\r\n");
+    }
+
+    #[test]
+    fn username_and_password_are_sent() {
+        let (result, _) = handshake(ProxyKind::Socks5, Some(("user", "secret")), |stream| {
+            expect(
+                stream,
+                &[SOCKS_VERSION, 2, METHOD_NO_AUTH, METHOD_USER_PASS],
+            );
+            stream
+                .write_all(&[SOCKS_VERSION, METHOD_USER_PASS])
+                .unwrap();
+            expect(stream, b"\x01\x04user\x06secret");
+            stream.write_all(&[SOCKS_AUTH_VERSION, 0]).unwrap();
+            connect_and_reply(stream);
+        });
+        result.unwrap();
+    }
+
+    #[test]
+    fn auth_reply_with_wrong_version_is_rejected() {
+        let (result, _) = handshake(ProxyKind::Socks5, Some(("user", "secret")), |stream| {
+            expect(
+                stream,
+                &[SOCKS_VERSION, 2, METHOD_NO_AUTH, METHOD_USER_PASS],
+            );
+            stream
+                .write_all(&[SOCKS_VERSION, METHOD_USER_PASS])
+                .unwrap();
+            expect(stream, b"\x01\x04user\x06secret");
+            stream.write_all(&[SOCKS_VERSION, 0]).unwrap();
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejected_credentials_are_reported() {
+        let (result, _) = handshake(ProxyKind::Socks5, Some(("user", "wrong")), |stream| {
+            expect(
+                stream,
+                &[SOCKS_VERSION, 2, METHOD_NO_AUTH, METHOD_USER_PASS],
+            );
+            stream
+                .write_all(&[SOCKS_VERSION, METHOD_USER_PASS])
+                .unwrap();
+            expect(stream, b"\x01\x04user\x05wrong");
+            stream.write_all(&[SOCKS_AUTH_VERSION, 1]).unwrap();
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn connect_failure_maps_the_reply_code() {
+        let (result, _) = handshake(ProxyKind::Socks5, None, |stream| {
+            expect(stream, &[SOCKS_VERSION, 1, METHOD_NO_AUTH]);
+            stream.write_all(&[SOCKS_VERSION, METHOD_NO_AUTH]).unwrap();
+            let mut request = [0u8; 18];
+            stream.read_exact(&mut request).unwrap();
+            stream
+                .write_all(&[SOCKS_VERSION, 0x05, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+                .unwrap();
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::ConnectionRefused);
+    }
+
+    #[test]
+    fn connect_reply_with_wrong_version_is_rejected() {
+        let (result, _) = handshake(ProxyKind::Socks5, None, |stream| {
+            expect(stream, &[SOCKS_VERSION, 1, METHOD_NO_AUTH]);
+            stream.write_all(&[SOCKS_VERSION, METHOD_NO_AUTH]).unwrap();
+            let mut request = [0u8; 18];
+            stream.read_exact(&mut request).unwrap();
+            stream
+                .write_all(&[0x04, 0, 0, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+                .unwrap();
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    /// Reads an HTTP request up to and including the blank line that ends its headers.
+    fn read_request(stream: &mut std::net::TcpStream) -> String {
+        let mut request = Vec::new();
+        let mut byte = [0u8; 1];
+        while !request.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).unwrap();
+            request.push(byte[0]);
+        }
+        String::from_utf8(request).unwrap()
+    }
+
+    #[test]
+    fn http_tunnel_is_positioned_at_server_data() {
+        let (result, tunnelled) = handshake(ProxyKind::Http, None, |stream| {
+            let request = read_request(stream);
+            assert!(request.starts_with("CONNECT example.com:6667 HTTP/1.1\r\n"));
+            assert!(request.contains("Host: example.com:6667\r\n"));
+            assert!(!request.contains("Proxy-Authorization"));
+            stream
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\nPING :irc7\r\n")
+                .unwrap();
+        });
+        result.unwrap();
+        assert_eq!(tunnelled, b"PING :irc7\r\n");
+    }
+
+    #[test]
+    fn http_auth_required_is_reported() {
+        let (result, _) = handshake(ProxyKind::Http, None, |stream| {
+            read_request(stream);
+            stream
+                .write_all(
+                    b"HTTP/1.1 407 Proxy Authentication Required\r\n\
+                      Proxy-Authenticate: Basic realm=\"proxy\"\r\n\r\n",
+                )
+                .unwrap();
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn http_basic_auth_is_sent() {
+        let (result, _) = handshake(ProxyKind::Http, Some(("user", "secret")), |stream| {
+            let request = read_request(stream);
+            assert!(request.contains("Proxy-Authorization: Basic dXNlcjpzZWNyZXQ=\r\n"));
+            stream
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                .unwrap();
+        });
+        result.unwrap();
+    }
+
+    #[test]
+    fn http_rejected_credentials_are_reported() {
+        let (result, _) = handshake(ProxyKind::Http, Some(("user", "wrong")), |stream| {
+            let request = read_request(stream);
+            assert!(request.contains("Proxy-Authorization: Basic dXNlcjp3cm9uZw==\r\n"));
+            stream
+                .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+                .unwrap();
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn http_bad_gateway_is_reported() {
+        let (result, _) = handshake(ProxyKind::Http, None, |stream| {
+            read_request(stream);
+            stream
+                .write_all(b"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::ConnectionRefused);
+    }
+
+    #[test]
+    fn http_other_status_is_an_error() {
+        let (result, _) = handshake(ProxyKind::Http, None, |stream| {
+            read_request(stream);
+            stream.write_all(b"HTTP/1.1 403 Forbidden\r\n\r\n").unwrap();
+        });
+        let err = result.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert!(err.to_string().contains("403"));
+    }
+
+    #[test]
+    fn http_oversized_headers_are_rejected() {
+        let (result, _) = handshake(ProxyKind::Http, None, |stream| {
+            read_request(stream);
+            let filler = format!("X-Filler: {}\r\n", "a".repeat(100));
+            let mut response = b"HTTP/1.1 200 Connection established\r\n".to_vec();
+            while response.len() <= MAX_HTTP_RESPONSE_HEAD {
+                response.extend_from_slice(filler.as_bytes());
+            }
+            // The client stops reading once the limit is hit.
+            let _ = stream.write_all(&response);
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn http_malformed_status_line_is_rejected() {
+        let (result, _) = handshake(ProxyKind::Http, None, |stream| {
+            read_request(stream);
+            stream.write_all(b"SSH-2.0-OpenSSH_9.6\r\n\r\n").unwrap();
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        let (result, _) = handshake(ProxyKind::Http, None, |stream| {
+            read_request(stream);
+            stream.write_all(b"HTTP/1.1 OK\r\n\r\n").unwrap();
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn http_handshake_times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // Reads the request, then says nothing until the client gives up.
+        let proxy = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            read_request(&mut stream);
+            let mut rest = Vec::new();
+            let _ = stream.read_to_end(&mut rest);
+        });
+        let config = ProxyConfig {
+            enabled: true,
+            kind: ProxyKind::Http,
+            host: "127.0.0.1".to_string(),
+            port,
+            timeout_secs: Some(1),
+            ..Default::default()
+        };
+        let result = get_rt().block_on(connect(&config, "example.com", 6667));
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        proxy.join().unwrap();
+    }
+}
@@ -0,0 +1,790 @@
+//! Opt-in reconnect supervisor for dropped OCX channel sessions.
+//!
+//! When a channel socket loses its connection, the supervisor re-dials the same target
+//! with exponential backoff and jitter and registers again the way the OCX did: IRCVERS,
+//! the AUTH exchange, then NICK and USER. Only once the server has welcomed the session
+//! (001) does it re-JOIN the rooms that socket was in and send anything held in the outbox
+//! meanwhile. Until then the server's lines go to the supervisor rather than the OCX, which
+//! keeps its socket ID throughout and is only told about the disconnect if every attempt
+//! fails.
+//!
+//! AUTH tokens are single-use, so a GateKeeper answer is rebuilt for the new challenge (see
+//! [`gatekeeper`](crate::network::gatekeeper)); the other AUTH lines are replayed as sent.
+//! A session whose answer could not be reproduced from the OCX's own exchange is handed
+//! back to the OCX as closed instead, so it reconnects and authenticates afresh.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::config::NetworkConfig;
+use crate::network::dial::DialReport;
+use crate::network::dispatch::notify_user;
+use crate::network::framing::LineFramer;
+use crate::network::gatekeeper::{self, Answerer};
+use crate::network::manager::{attach_transport, enqueue, open_transport, registered_events};
+use crate::network::outbox;
+use crate::network::socket::RustSocket;
+use crate::protocol::message::{Command, Message};
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 10;
+const DEFAULT_INITIAL_DELAY_MS: u64 = 1000;
+const DEFAULT_MAX_DELAY_MS: u64 = 60_000;
+/// Cap on captured registration lines, in case the OCX keeps re-sending USER or AUTH.
+const MAX_REGISTRATION_LINES: usize = 16;
+/// How long the server gets to welcome a resumed session before the attempt is dropped.
+const REGISTRATION_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone)]
+pub struct JoinedRoom {
+    pub name: String,
+    pub key: Option<String>,
+}
+
+/// Rooms joined on each socket, by socket ID.
+static JOINED_ROOMS: Mutex<BTreeMap<u32, Vec<JoinedRoom>>> = Mutex::new(BTreeMap::new());
+
+/// What the OCX sent to register a socket, kept to register it again after a reconnect.
+#[derive(Debug, Clone, Default)]
+pub struct Registration {
+    /// The IRCVERS line, sent before anything else.
+    ircvers: Option<String>,
+    /// NICK and USER lines, in the order sent.
+    lines: Vec<String>,
+    /// AUTH lines, verbatim.
+    auth: Vec<Vec<u8>>,
+    /// The server's latest GateKeeper challenge, until the OCX answers it.
+    challenge: Option<Vec<u8>>,
+    /// Which AUTH line answered a GateKeeper challenge, and how to answer a new one.
+    answerer: Option<(usize, Answerer)>,
+    /// Set when an AUTH answer could not be reproduced; such a session is not resumed.
+    unanswerable: bool,
+}
+
+impl Registration {
+    /// Whether the session can be registered again without the OCX: it has a nickname,
+    /// and any AUTH exchange it went through can be answered afresh.
+    pub fn is_resumable(&self) -> bool {
+        let nick = self
+            .lines
+            .iter()
+            .any(|l| is_command(l.as_bytes(), &Command::Nick));
+        nick && !self.unanswerable && (self.auth.is_empty() || self.answerer.is_some())
+    }
+
+    /// Whether server lines still need watching for a GateKeeper challenge.
+    pub fn awaits_challenge(&self) -> bool {
+        !self.auth.is_empty() && self.answerer.is_none() && !self.unanswerable
+    }
+}
+
+fn is_command(line: &[u8], command: &Command) -> bool {
+    Message::parse(&String::from_utf8_lossy(line)).is_some_and(|m| &m.command == command)
+}
+
+/// The forms of a server's address a GateKeeper answer may hash, in a fixed order.
+fn addresses(host: &str, port: u16, remote: Option<SocketAddr>) -> Vec<String> {
+    let mut addresses = vec![host.to_string(), format!("{}:{}", host, port)];
+    if let Some(remote) = remote {
+        addresses.push(remote.ip().to_string());
+        addresses.push(remote.to_string());
+    }
+    addresses
+}
+
+/// Records a JOIN sent on socket `id`.
+pub fn note_join(id: u32, room: &str, key: Option<&str>) {
+    if let Ok(mut sockets) = JOINED_ROOMS.lock() {
+        let rooms = sockets.entry(id).or_default();
+        rooms.retain(|r| !r.name.eq_ignore_ascii_case(room));
+        rooms.push(JoinedRoom {
+            name: room.to_string(),
+            key: key.map(str::to_string),
+        });
+    }
+}
+
+/// Records a PART sent on socket `id`.
+pub fn note_part(id: u32, room: &str) {
+    if let Ok(mut sockets) = JOINED_ROOMS.lock() {
+        if let Some(rooms) = sockets.get_mut(&id) {
+            rooms.retain(|r| !r.name.eq_ignore_ascii_case(room));
+        }
+    }
+}
+
+/// Rooms currently joined on socket `id`, in the order they were joined.
+pub fn joined_rooms(id: u32) -> Vec<JoinedRoom> {
+    JOINED_ROOMS
+        .lock()
+        .ok()
+        .and_then(|sockets| sockets.get(&id).cloned())
+        .unwrap_or_default()
+}
+
+/// Forgets the rooms of a socket the OCX has closed.
+pub fn forget(id: u32) {
+    if let Ok(mut sockets) = JOINED_ROOMS.lock() {
+        sockets.remove(&id);
+    }
+}
+
+/// Inspects data the OCX sends and keeps what is needed to resume the session: the
+/// IRCVERS, AUTH, NICK and USER lines, and the rooms joined and parted.
+///
+/// NICK replaces any earlier NICK so that the latest nickname is the one replayed. An AUTH
+/// line answering a GateKeeper challenge is checked against [`Answerer::learn`].
+pub fn capture_outgoing(socket: &mut RustSocket, data: &[u8]) {
+    if socket.reconnecting {
+        return;
+    }
+    for line in data.split(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let Some(message) = Message::parse(&String::from_utf8_lossy(line)) else {
+            continue;
+        };
+        let text = String::from_utf8_lossy(line);
+        let registration = &mut socket.registration;
+        match message.command {
+            Command::Nick => {
+                let line = text.into_owned();
+                if let Some(existing) = registration
+                    .lines
+                    .iter_mut()
+                    .find(|l| is_command(l.as_bytes(), &Command::Nick))
+                {
+                    *existing = line;
+                } else if registration.lines.len() < MAX_REGISTRATION_LINES {
+                    registration.lines.push(line);
+                }
+            }
+            Command::Other(ref command) if command == "IRCVERS" => {
+                registration.ircvers = Some(text.into_owned());
+            }
+            Command::Other(ref command)
+                if command == "USER" && registration.lines.len() < MAX_REGISTRATION_LINES =>
+            {
+                registration.lines.push(text.into_owned());
+            }
+            Command::Auth if registration.auth.len() < MAX_REGISTRATION_LINES => {
+                if let Some(challenge) = registration.challenge.take() {
+                    let addresses = addresses(&socket.host, socket.port, socket.stats.remote_addr);
+                    match Answerer::learn(&challenge, line, &addresses) {
+                        Some(answerer) => {
+                            registration.answerer = Some((registration.auth.len(), answerer))
+                        }
+                        None => {
+                            log::info!(
+                                "Socket {} AUTH answer could not be reproduced, it will not be resumed.",
+                                socket.id
+                            );
+                            registration.unanswerable = true;
+                        }
+                    }
+                } else if !registration.auth.is_empty() && gatekeeper::is_gatekeeper(line) {
+                    // A GateKeeper token answering a challenge that was never seen.
+                    registration.unanswerable = true;
+                }
+                registration.auth.push(line.to_vec());
+            }
+            Command::Auth => registration.unanswerable = true,
+            Command::Join => {
+                socket.joined = true;
+                let rooms = message.arg(0).unwrap_or_default();
+                let mut keys = message.arg(1).unwrap_or_default().split(',');
+                for room in rooms.split(',').filter(|r| !r.is_empty()) {
+                    note_join(socket.id, room, keys.next().filter(|k| !k.is_empty()));
+                }
+            }
+            Command::Part => {
+                let rooms = message.arg(0).unwrap_or_default();
+                for room in rooms.split(',').filter(|r| !r.is_empty()) {
+                    note_part(socket.id, room);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Inspects lines from the server while the OCX authenticates, keeping the GateKeeper
+/// challenge its next AUTH line answers.
+pub fn capture_incoming(socket: &mut RustSocket, lines: &[Vec<u8>]) {
+    if socket.reconnecting || !socket.registration.awaits_challenge() {
+        return;
+    }
+    for line in lines {
+        if gatekeeper::is_gatekeeper(line) && is_command(line, &Command::Auth) {
+            socket.registration.challenge = Some(line.clone());
+        }
+    }
+}
+
+/// Routes a resumed connection's lines to the supervisor until the server's welcome.
+pub(crate) struct Diversion {
+    tx: UnboundedSender<Vec<u8>>,
+    framer: LineFramer,
+}
+
+impl Diversion {
+    fn new() -> (Self, UnboundedReceiver<Vec<u8>>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let diversion = Self {
+            tx,
+            framer: LineFramer::new(),
+        };
+        (diversion, rx)
+    }
+
+    /// Takes the complete lines in `data` for the supervisor. Once a 001 line has gone by,
+    /// returns whatever follows it in `data`, which is for the OCX.
+    pub(crate) fn divert<'a>(&mut self, data: &'a [u8]) -> Option<&'a [u8]> {
+        let mut consumed = 0;
+        for line in data.split_inclusive(|&b| b == b'\n') {
+            consumed += line.len();
+            for line in self.framer.push(line) {
+                let welcome = is_command(&line, &Command::Numeric(1));
+                // The supervisor may have given up already; the reader still must not
+                // pass these lines on.
+                let _ = self.tx.send(line);
+                if welcome {
+                    return Some(&data[consumed..]);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Why a registration attempt failed.
+enum Failure {
+    /// The connection dropped or the server hiccupped; another attempt may succeed.
+    Retry(String),
+    /// The server will not take this registration; the OCX has to start over.
+    Refused(String),
+}
+
+/// Queues one line on the resumed connection.
+fn send(socket_arc: &Mutex<RustSocket>, line: &[u8]) -> Result<(), Failure> {
+    let mut data = line.to_vec();
+    data.extend_from_slice(b"\r\n");
+    let sent = socket_arc
+        .lock()
+        .is_ok_and(|mut socket| enqueue(&mut socket, &data));
+    if sent {
+        Ok(())
+    } else {
+        Err(Failure::Retry("the connection closed".to_string()))
+    }
+}
+
+/// The next line from the server that concerns registration. PINGs are answered on the
+/// way; errors end the attempt.
+async fn next_reply(
+    socket_arc: &Mutex<RustSocket>,
+    rx: &mut UnboundedReceiver<Vec<u8>>,
+) -> Result<(Vec<u8>, Message), Failure> {
+    loop {
+        let Some(line) = rx.recv().await else {
+            return Err(Failure::Retry("the connection closed".to_string()));
+        };
+        let Some(message) = Message::parse(&String::from_utf8_lossy(&line)) else {
+            continue;
+        };
+        match message.command {
+            Command::Ping => {
+                let mut pong = message.clone();
+                pong.prefix = None;
+                pong.command = Command::Pong;
+                send(socket_arc, pong.to_string().as_bytes())?;
+            }
+            Command::Error => return Err(Failure::Retry(message.to_string())),
+            // The old session may not have timed out yet.
+            Command::Numeric(433) => return Err(Failure::Retry(message.to_string())),
+            Command::Numeric(465) => return Err(Failure::Refused(message.to_string())),
+            ref command if command.is_ircx_error() => {
+                return Err(Failure::Refused(message.to_string()));
+            }
+            _ => return Ok((line, message)),
+        }
+    }
+}
+
+/// Registers the resumed connection as the OCX did and waits for the server's welcome.
+async fn register(
+    socket_arc: &Mutex<RustSocket>,
+    rx: &mut UnboundedReceiver<Vec<u8>>,
+    registration: &Registration,
+    addresses: &[String],
+) -> Result<(), Failure> {
+    if let Some(ref ircvers) = registration.ircvers {
+        send(socket_arc, ircvers.as_bytes())?;
+    }
+
+    for (i, auth) in registration.auth.iter().enumerate() {
+        let line = match registration.answerer {
+            Some((index, ref answerer)) if index == i => {
+                let challenge = loop {
+                    let (line, message) = next_reply(socket_arc, rx).await?;
+                    if message.command == Command::Auth {
+                        break line;
+                    }
+                };
+                answerer.answer(&challenge, addresses).ok_or_else(|| {
+                    Failure::Refused(format!(
+                        "no GateKeeper challenge to answer: {}",
+                        String::from_utf8_lossy(&challenge)
+                    ))
+                })?
+            }
+            _ => auth.clone(),
+        };
+        send(socket_arc, &line)?;
+    }
+    if !registration.auth.is_empty() {
+        // `AUTH <package> * <id> <mode>` ends a successful exchange.
+        loop {
+            let (_, message) = next_reply(socket_arc, rx).await?;
+            if message.command == Command::Auth && message.arg(1) == Some("*") {
+                break;
+            }
+        }
+    }
+
+    for line in &registration.lines {
+        send(socket_arc, line.as_bytes())?;
+    }
+    loop {
+        let (_, message) = next_reply(socket_arc, rx).await?;
+        if message.command == Command::Numeric(1) {
+            return Ok(());
+        }
+    }
+}
+
+/// Drops a resumed connection that did not register.
+fn detach(socket_arc: &Mutex<RustSocket>) {
+    if let Ok(mut socket) = socket_arc.lock() {
+        socket.resume = None;
+        socket.connected = false;
+        if let Some(reader) = socket.reader_task.take() {
+            reader.abort();
+        }
+        if let Some(writer) = socket.writer_task.take() {
+            writer.abort();
+        }
+        if let Some(queue) = socket.send_queue.take() {
+            queue.close();
+        }
+    }
+}
+
+/// Backoff delay before `attempt` (1-based), including up to 50% random jitter.
+fn backoff_delay(config: &NetworkConfig, attempt: u32) -> Duration {
+    let initial = config
+        .reconnect
+        .initial_delay_ms
+        .unwrap_or(DEFAULT_INITIAL_DELAY_MS);
+    let max = config
+        .reconnect
+        .max_delay_ms
+        .unwrap_or(DEFAULT_MAX_DELAY_MS);
+    let base = initial
+        .saturating_mul(1u64 << attempt.saturating_sub(1).min(20))
+        .min(max);
+    let jitter_range = base / 2;
+    let jitter = if jitter_range > 0 {
+        (uuid::Uuid::new_v4().as_u128() % jitter_range as u128) as u64
+    } else {
+        0
+    };
+    Duration::from_millis(base + jitter)
+}
+
+/// Re-dials a dropped socket until it is re-registered, the OCX closes it, or the attempt
+/// budget runs out.
+pub async fn supervise(id: u32, socket_arc: Arc<Mutex<RustSocket>>, config: Arc<NetworkConfig>) {
+    let (host, port) = match socket_arc.lock() {
+        Ok(socket) => (socket.host.clone(), socket.port),
+        Err(_) => return,
+    };
+    let max_attempts = config
+        .reconnect
+        .max_attempts
+        .unwrap_or(DEFAULT_MAX_ATTEMPTS);
+
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        if max_attempts != 0 && attempt > max_attempts {
+            break;
+        }
+
+        let delay = backoff_delay(&config, attempt);
+        notify_user(format!(
+            "Connection to {} lost. Reconnecting in {}s (attempt {})...",
+            host,
+            delay.as_secs().max(1),
+            attempt
+        ));
+        tokio::time::sleep(delay).await;
+
+        if socket_arc.lock().map(|s| s.closed).unwrap_or(true) {
+            log::info!("Socket {} closed while reconnecting, giving up.", id);
+            return;
+        }
+
+        let mut report = DialReport::default();
+        let result = open_transport(&config, &host, port, &mut report).await;
+        if let Ok(mut socket) = socket_arc.lock() {
+            socket.stats.record_dial(&report);
+        }
+        let transport = match result {
+            Ok(transport) => transport,
+            Err(e) => {
+                log::warn!(
+                    "Reconnect attempt {} to {}:{} failed: {:?}",
+                    attempt,
+                    host,
+                    port,
+                    e
+                );
+                continue;
+            }
+        };
+
+        let (diversion, mut rx) = Diversion::new();
+        let registration = match socket_arc.lock() {
+            Ok(mut socket) => {
+                socket.resume = Some(diversion);
+                socket.registration.clone()
+            }
+            Err(_) => return,
+        };
+        attach_transport(id, &socket_arc, transport, config.clone());
+
+        let addresses = addresses(&host, port, report.addr);
+        let result = tokio::time::timeout(
+            REGISTRATION_TIMEOUT,
+            register(&socket_arc, &mut rx, &registration, &addresses),
+        )
+        .await
+        .unwrap_or_else(|_| {
+            Err(Failure::Retry(format!(
+                "no welcome within {:?}",
+                REGISTRATION_TIMEOUT
+            )))
+        });
+        match result {
+            Ok(()) => {}
+            Err(Failure::Retry(reason)) => {
+                log::warn!(
+                    "Reconnect attempt {} to {}:{} did not register: {}",
+                    attempt,
+                    host,
+                    port,
+                    reason
+                );
+                detach(&socket_arc);
+                continue;
+            }
+            Err(Failure::Refused(reason)) => {
+                log::warn!("Server refused to resume socket {}: {}", id, reason);
+                detach(&socket_arc);
+                give_up(id, &socket_arc, format!("{} refused the session.", host));
+                return;
+            }
+        }
+
+        let rooms = joined_rooms(id);
+        if let Ok(mut socket) = socket_arc.lock() {
+            for room in &rooms {
+                let join = match room.key {
+                    Some(ref key) => format!("JOIN {} {}\r\n", room.name, key),
+                    None => format!("JOIN {}\r\n", room.name),
+                };
+                enqueue(&mut socket, join.as_bytes());
+                outbox::flush(&mut socket, &room.name);
+            }
+            socket.stats.reconnects += 1;
+            socket.reconnecting = false;
+        }
+
+        notify_user(format!(
+            "Reconnected to {}. Rejoining {} room(s).",
+            host,
+            rooms.len()
+        ));
+        return;
+    }
+
+    give_up(
+        id,
+        &socket_arc,
+        format!(
+            "Could not reconnect to {} after {} attempt(s).",
+            host, max_attempts
+        ),
+    );
+}
+
+/// Tells the user and the OCX that the session is gone, leaving the OCX to reconnect.
+fn give_up(id: u32, socket_arc: &Arc<Mutex<RustSocket>>, message: String) {
+    notify_user(message);
+    if let Ok(mut socket) = socket_arc.lock() {
+        socket.reconnecting = false;
+    }
+    if let Some(events) = registered_events(socket_arc) {
+        events.on_read(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+
+    use crate::config::ReconnectConfig;
+    use crate::network::events::{ChannelEvents, SocketEvent};
+    use crate::network::gatekeeper::tests::{answer_line, challenge_line};
+    use crate::network::manager::{
+        close_socket, connect_socket_with_config, create_socket, get_rt, receive_socket,
+        send_socket, set_socket_events, socket_stats,
+    };
+
+    const IRCVERS: &[u8] = b"IRCVERS IRC8 MSN-OCX!9.02.0310.2401";
+    const AUTH_I: &[u8] = b"AUTH GateKeeper I :GKSSP\\0JD\x03\\0\\0\\0\x01\\0\\0\\0";
+    const AUTH_OK: &[u8] = b"AUTH GateKeeper * 1234ABCD@GateKeeper 0";
+
+    fn line(data: &[u8]) -> Vec<u8> {
+        [data, b"\r\n"].concat()
+    }
+
+    #[test]
+    fn capture_keeps_registration_and_tracks_rooms_per_socket() {
+        let mut first = RustSocket::new(1_000_001);
+        let mut second = RustSocket::new(1_000_002);
+        capture_outgoing(
+            &mut first,
+            b"IRCVERS IRC8 MSN-OCX!9.02.0310.2401\r\nNICK a\r\n\
+              USER a 0 * :a\r\nNICK b\r\nJOIN #one,#two key\r\n",
+        );
+        capture_outgoing(&mut second, b"JOIN #three\r\n");
+        capture_outgoing(&mut first, b"PART #one\r\n");
+
+        let registration = &first.registration;
+        assert_eq!(
+            registration.ircvers.as_deref(),
+            Some("IRCVERS IRC8 MSN-OCX!9.02.0310.2401")
+        );
+        assert_eq!(registration.lines, ["NICK b", "USER a 0 * :a"]);
+        assert!(registration.is_resumable());
+        assert!(first.joined);
+        let rooms: Vec<_> = joined_rooms(first.id).into_iter().map(|r| r.name).collect();
+        assert_eq!(rooms, ["#two"]);
+        let rooms: Vec<_> = joined_rooms(second.id)
+            .into_iter()
+            .map(|r| r.name)
+            .collect();
+        assert_eq!(rooms, ["#three"]);
+
+        forget(first.id);
+        forget(second.id);
+        assert!(joined_rooms(first.id).is_empty());
+    }
+
+    #[test]
+    fn auth_is_resumable_only_when_the_answer_is_reproduced() {
+        let challenge = *b"abcdefgh";
+        let mut socket = RustSocket::new(1_000_003);
+        socket.host = "irc.example".to_string();
+        socket.port = 6667;
+        capture_outgoing(&mut socket, &[line(AUTH_I), line(b"NICK a")].concat());
+        assert!(!socket.registration.is_resumable());
+
+        capture_incoming(&mut socket, &[challenge_line(&challenge)]);
+        capture_outgoing(
+            &mut socket,
+            &line(&answer_line(&challenge, b"irc.example:6667")),
+        );
+        assert!(socket.registration.is_resumable());
+        assert_eq!(socket.registration.auth.len(), 2);
+        assert_eq!(
+            socket.registration.answerer.as_ref().map(|(i, _)| *i),
+            Some(1)
+        );
+
+        let mut socket = RustSocket::new(1_000_004);
+        capture_outgoing(&mut socket, &[line(AUTH_I), line(b"NICK a")].concat());
+        capture_incoming(&mut socket, &[challenge_line(&challenge)]);
+        capture_outgoing(&mut socket, &line(&answer_line(&challenge, b"elsewhere")));
+        assert!(!socket.registration.is_resumable());
+
+        // An answer to a challenge that was never seen cannot be trusted either.
+        let mut socket = RustSocket::new(1_000_005);
+        capture_outgoing(&mut socket, &[line(AUTH_I), line(b"NICK a")].concat());
+        capture_outgoing(&mut socket, &line(&answer_line(&challenge, b"")));
+        assert!(!socket.registration.is_resumable());
+    }
+
+    /// One side of a scripted server connection.
+    struct Peer(BufReader<TcpStream>);
+
+    impl Peer {
+        fn accept(listener: &TcpListener) -> Self {
+            let (stream, _) = listener.accept().unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+            Self(BufReader::new(stream))
+        }
+
+        fn line(&mut self) -> Vec<u8> {
+            let mut line = Vec::new();
+            self.0.read_until(b'\n', &mut line).unwrap();
+            assert!(
+                line.ends_with(b"\r\n"),
+                "{:?}",
+                String::from_utf8_lossy(&line)
+            );
+            line.truncate(line.len() - 2);
+            line
+        }
+
+        fn send(&mut self, data: &[u8]) {
+            self.0.get_mut().write_all(&line(data)).unwrap();
+        }
+
+        /// Asserts the client sends nothing for a moment.
+        fn expect_silence(&mut self) {
+            let stream = self.0.get_ref();
+            stream
+                .set_read_timeout(Some(Duration::from_millis(300)))
+                .unwrap();
+            let mut line = Vec::new();
+            let result = self.0.read_until(b'\n', &mut line);
+            assert!(
+                result.is_err(),
+                "unexpected {:?}",
+                String::from_utf8_lossy(&line)
+            );
+            self.0
+                .get_ref()
+                .set_read_timeout(Some(Duration::from_secs(5)))
+                .unwrap();
+        }
+    }
+
+    /// Reads what the OCX would until `needle` has arrived. Any event other than
+    /// `ReadReady` fails the test.
+    fn receive_until(
+        id: u32,
+        rx: &mut tokio::sync::mpsc::UnboundedReceiver<SocketEvent>,
+        needle: &[u8],
+    ) -> Vec<u8> {
+        let mut received = Vec::new();
+        loop {
+            let mut buf = [0u8; 4096];
+            let n = receive_socket(id, &mut buf);
+            received.extend_from_slice(&buf[..n as usize]);
+            if received.windows(needle.len()).any(|w| w == needle) {
+                return received;
+            }
+            let event = get_rt()
+                .block_on(async { tokio::time::timeout(Duration::from_secs(5), rx.recv()).await })
+                .expect("timed out waiting for a socket event");
+            assert_eq!(event, Some(SocketEvent::ReadReady(id)));
+        }
+    }
+
+    #[test]
+    fn gatekeeper_session_is_resumed_and_rejoined_after_the_welcome() {
+        let first = *b"\x00first\r\n";
+        let second = *b"second ,";
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let hashed = format!("127.0.0.1:{}", port);
+
+        let server = std::thread::spawn(move || {
+            // The OCX's own session, which then drops.
+            let mut peer = Peer::accept(&listener);
+            assert_eq!(peer.line(), IRCVERS);
+            assert_eq!(peer.line(), AUTH_I);
+            peer.send(&challenge_line(&first));
+            assert_eq!(peer.line(), answer_line(&first, hashed.as_bytes()));
+            peer.send(AUTH_OK);
+            assert_eq!(peer.line(), b"NICK a");
+            assert_eq!(peer.line(), b"USER a 0 * :a");
+            peer.send(b":irc7 001 a :Welcome");
+            assert_eq!(peer.line(), b"JOIN %#room");
+            drop(peer);
+
+            // The supervisor's.
+            let mut peer = Peer::accept(&listener);
+            assert_eq!(peer.line(), IRCVERS);
+            assert_eq!(peer.line(), AUTH_I);
+            peer.send(b"PING :irc7");
+            assert_eq!(peer.line(), b"PONG :irc7");
+            peer.send(&challenge_line(&second));
+            assert_eq!(peer.line(), answer_line(&second, hashed.as_bytes()));
+            peer.send(AUTH_OK);
+            assert_eq!(peer.line(), b"NICK a");
+            assert_eq!(peer.line(), b"USER a 0 * :a");
+            peer.expect_silence();
+            peer.send(b":irc7 001 a :Welcome\r\n:irc7 PRIVMSG %#room :back");
+            assert_eq!(peer.line(), b"JOIN %#room");
+            peer
+        });
+
+        let config = NetworkConfig {
+            reconnect: ReconnectConfig {
+                enabled: true,
+                max_attempts: Some(3),
+                initial_delay_ms: Some(10),
+                max_delay_ms: Some(10),
+            },
+            ..NetworkConfig::default()
+        };
+        let id = create_socket();
+        let (events, mut rx) = ChannelEvents::new();
+        assert!(set_socket_events(id, Arc::new(events)));
+        assert!(connect_socket_with_config(
+            id,
+            "127.0.0.1".to_string(),
+            port,
+            Arc::new(config),
+        ));
+        let event = get_rt()
+            .block_on(async { tokio::time::timeout(Duration::from_secs(5), rx.recv()).await });
+        assert_eq!(event, Ok(Some(SocketEvent::Write(id))));
+
+        assert!(send_socket(id, &[line(IRCVERS), line(AUTH_I)].concat()));
+        receive_until(id, &mut rx, &challenge_line(&first));
+        assert!(send_socket(
+            id,
+            &line(&answer_line(
+                &first,
+                format!("127.0.0.1:{}", port).as_bytes()
+            ))
+        ));
+        receive_until(id, &mut rx, AUTH_OK);
+        assert!(send_socket(id, b"NICK a\r\nUSER a 0 * :a\r\n"));
+        receive_until(id, &mut rx, b" 001 ");
+        assert!(send_socket(id, b"JOIN %#room\r\n"));
+
+        // The resumed registration never reaches the OCX, only what follows the welcome.
+        let received = receive_until(id, &mut rx, b":back\r\n");
+        assert_eq!(received, b":irc7 PRIVMSG %#room :back\r\n");
+
+        let peer = server.join().unwrap();
+        let stats = socket_stats(id).unwrap();
+        assert!(!stats.reconnecting);
+        assert_eq!(stats.stats.reconnects, 1);
+        close_socket(id);
+        drop(peer);
+    }
+}
@@ -0,0 +1,479 @@
+//! Offline replay transport that serves a recorded transcript in place of a real server.
+//!
+//! Each OCX connect is matched to the next unused session (one recorded socket) in the
+//! transcript, preferring the same host and port. The session is exposed as an in-memory
+//! duplex stream, so the reader/writer tasks, `receive_socket` and `send_socket` behave
+//! exactly as they would on a live connection. Inbound chunks are paced by their original
+//! timestamps or released one at a time with `/replay`, and everything the OCX writes is
+//! checked line by line against what was recorded. With several replayed connections,
+//! `/replay` releases whichever waiting chunk was recorded first.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio::sync::Notify;
+
+use crate::config::{ReplayConfig, ReplayPacing};
+use crate::network::recorder::{Direction, REDACTED, RecordEntry};
+use crate::network::transport::BoxedTransport;
+
+const DUPLEX_CAPACITY: usize = 64 * 1024;
+
+struct Session {
+    socket: u32,
+    host: String,
+    port: u16,
+    entries: Vec<RecordEntry>,
+}
+
+struct Transcript {
+    sessions: Vec<Session>,
+    used: Vec<bool>,
+}
+
+/// Manual pacing of one replayed connection.
+#[derive(Default)]
+struct Stepper {
+    /// Recorded time of the chunk waiting for `/replay`, if one is.
+    waiting: Mutex<Option<u64>>,
+    notify: Notify,
+}
+
+impl Stepper {
+    /// Waits for `/replay` to release the chunk recorded at `ts_ms`.
+    async fn wait(&self, ts_ms: u64) {
+        if let Ok(mut waiting) = self.waiting.lock() {
+            *waiting = Some(ts_ms);
+        }
+        self.notify.notified().await;
+    }
+}
+
+static TRANSCRIPT: OnceLock<Mutex<Option<Transcript>>> = OnceLock::new();
+static STEPPERS: Mutex<Vec<Weak<Stepper>>> = Mutex::new(Vec::new());
+
+fn load_transcript(config: &ReplayConfig) -> io::Result<Transcript> {
+    let contents = fs::read_to_string(&config.path)?;
+    let mut sessions: Vec<Session> = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: RecordEntry = serde_json::from_str(line).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}:{}: {}", config.path.display(), number + 1, e),
+            )
+        })?;
+        match sessions.iter_mut().find(|s| s.socket == entry.socket) {
+            Some(session) => session.entries.push(entry),
+            None => sessions.push(Session {
+                socket: entry.socket,
+                host: entry.host.clone(),
+                port: entry.port,
+                entries: vec![entry],
+            }),
+        }
+    }
+
+    log::info!(
+        "Loaded replay transcript {} with {} session(s)",
+        config.path.display(),
+        sessions.len()
+    );
+    let used = vec![false; sessions.len()];
+    Ok(Transcript { sessions, used })
+}
+
+/// Releases the earliest recorded inbound chunk that is waiting, when replaying with
+/// manual pacing.
+pub fn step() {
+    let Ok(mut steppers) = STEPPERS.lock() else {
+        return;
+    };
+    steppers.retain(|stepper| stepper.strong_count() > 0);
+    let next = steppers
+        .iter()
+        .filter_map(Weak::upgrade)
+        .filter_map(|stepper| {
+            let ts_ms = (*stepper.waiting.lock().ok()?)?;
+            Some((ts_ms, stepper))
+        })
+        .min_by_key(|&(ts_ms, _)| ts_ms);
+    match next {
+        Some((_, stepper)) => {
+            // Cleared here so a second `/replay` moves on to the next chunk.
+            if let Ok(mut waiting) = stepper.waiting.lock() {
+                *waiting = None;
+            }
+            stepper.notify.notify_one();
+        }
+        None => log::info!("Replay: no chunk is waiting for /replay"),
+    }
+}
+
+/// Opens a replayed connection standing in for `host:port`. The transcript is read on
+/// first use.
+pub async fn open(config: &ReplayConfig, host: &str, port: u16) -> io::Result<BoxedTransport> {
+    let lock = TRANSCRIPT.get_or_init(|| Mutex::new(None));
+    let poisoned = || io::Error::other("replay transcript lock poisoned");
+    let loaded = if lock.lock().map_err(|_| poisoned())?.is_some() {
+        None
+    } else {
+        let config = config.clone();
+        Some(
+            tokio::task::spawn_blocking(move || load_transcript(&config))
+                .await
+                .map_err(io::Error::other)??,
+        )
+    };
+    let mut guard = lock.lock().map_err(|_| poisoned())?;
+    // Another connection may have loaded it meanwhile.
+    if guard.is_none() {
+        *guard = loaded;
+    }
+    let Some(transcript) = guard.as_mut() else {
+        return Err(io::Error::other("replay transcript not loaded"));
+    };
+
+    let index = transcript
+        .sessions
+        .iter()
+        .enumerate()
+        .position(|(i, s)| !transcript.used[i] && s.host == host && s.port == port)
+        .or_else(|| transcript.used.iter().position(|used| !used))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!(
+                    "replay transcript has no unused session for {}:{}",
+                    host, port
+                ),
+            )
+        })?;
+    transcript.used[index] = true;
+
+    let session = &transcript.sessions[index];
+    log::info!(
+        "Replaying recorded socket {} ({}:{}) for connection to {}:{}",
+        session.socket,
+        session.host,
+        session.port,
+        host,
+        port
+    );
+
+    let inbound: Vec<RecordEntry> = session
+        .entries
+        .iter()
+        .filter(|e| e.dir == Direction::In)
+        .cloned()
+        .collect();
+    let outbound: VecDeque<String> = session
+        .entries
+        .iter()
+        .filter(|e| e.dir == Direction::Out)
+        .flat_map(|e| split_lines(&e.bytes()))
+        .collect();
+    let start_ts = session.entries.first().map_or(0, |e| e.ts_ms);
+
+    let stepper = Arc::new(Stepper::default());
+    if let Ok(mut steppers) = STEPPERS.lock() {
+        steppers.push(Arc::downgrade(&stepper));
+    }
+
+    let (client, server) = tokio::io::duplex(DUPLEX_CAPACITY);
+    let (server_read, server_write) = tokio::io::split(server);
+    tokio::spawn(feed_inbound(
+        server_write,
+        inbound,
+        start_ts,
+        Pacing {
+            mode: config.pacing,
+            stepper,
+        },
+        config.close_at_end,
+    ));
+    tokio::spawn(check_outbound(server_read, outbound));
+
+    Ok(Box::new(client))
+}
+
+fn split_lines(data: &[u8]) -> Vec<String> {
+    String::from_utf8_lossy(data)
+        .split('\n')
+        .map(|l| l.trim_end_matches('\r'))
+        .filter(|l| !l.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+struct Pacing {
+    mode: ReplayPacing,
+    stepper: Arc<Stepper>,
+}
+
+async fn feed_inbound(
+    mut writer: tokio::io::WriteHalf<DuplexStream>,
+    inbound: Vec<RecordEntry>,
+    start_ts: u64,
+    pacing: Pacing,
+    close_at_end: bool,
+) {
+    let mut last_ts = start_ts;
+    for (i, entry) in inbound.iter().enumerate() {
+        match pacing.mode {
+            ReplayPacing::Timed => {
+                let gap = entry.ts_ms.saturating_sub(last_ts);
+                tokio::time::sleep(Duration::from_millis(gap)).await;
+            }
+            ReplayPacing::Manual => {
+                log::info!(
+                    "Replay: chunk {}/{} ready, waiting for /replay",
+                    i + 1,
+                    inbound.len()
+                );
+                pacing.stepper.wait(entry.ts_ms).await;
+            }
+        }
+        last_ts = entry.ts_ms;
+
+        if let Err(e) = writer.write_all(&entry.bytes()).await {
+            log::info!("Replay: connection closed by the OCX ({:?})", e);
+            return;
+        }
+    }
+
+    log::info!("Replay: all {} inbound chunk(s) delivered", inbound.len());
+    // Without `close_at_end` the duplex stays open until the OCX closes its side, because
+    // the outbound checker still holds the read half.
+    if close_at_end {
+        let _ = writer.shutdown().await;
+    }
+}
+
+/// What [`check_outbound`] found once the OCX closed its side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct OutboundCheck {
+    lines: usize,
+    mismatches: usize,
+    unsent: usize,
+}
+
+async fn check_outbound(
+    mut reader: tokio::io::ReadHalf<DuplexStream>,
+    mut expected: VecDeque<String>,
+) -> OutboundCheck {
+    let mut pending = Vec::new();
+    let mut buf = [0u8; 4096];
+    let mut line_number = 0usize;
+    let mut mismatches = 0usize;
+
+    loop {
+        let n = match reader.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        pending.extend_from_slice(&buf[..n]);
+
+        while let Some(pos) = pending.iter().position(|&b| b == b'\n') {
+            let raw: Vec<u8> = pending.drain(..=pos).collect();
+            let actual = String::from_utf8_lossy(&raw)
+                .trim_end_matches(['\r', '\n'])
+                .to_string();
+            if actual.is_empty() {
+                continue;
+            }
+            line_number += 1;
+
+            match expected.pop_front() {
+                Some(recorded) if lines_match(&recorded, &actual) => {}
+                Some(recorded) => {
+                    mismatches += 1;
+                    log::warn!(
+                        "Replay: outgoing line {} differs from recording.\n  recorded: {}\n  actual:   {}",
+                        line_number,
+                        recorded,
+                        actual
+                    );
+                }
+                None => {
+                    mismatches += 1;
+                    log::warn!(
+                        "Replay: unexpected outgoing line {} past end of recording: {}",
+                        line_number,
+                        actual
+                    );
+                }
+            }
+        }
+    }
+
+    log::info!(
+        "Replay: outgoing check finished ({} line(s), {} mismatch(es), {} recorded line(s) unsent)",
+        line_number,
+        mismatches,
+        expected.len()
+    );
+    OutboundCheck {
+        lines: line_number,
+        mismatches,
+        unsent: expected.len(),
+    }
+}
+
+/// Compares an outgoing line with its recording, ignoring anything the recorder redacted.
+fn lines_match(recorded: &str, actual: &str) -> bool {
+    match recorded.find(REDACTED) {
+        Some(pos) => actual.starts_with(recorded[..pos].trim_end()),
+        None => recorded == actual,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::manager::get_rt;
+    use std::time::Instant;
+    use tokio::io::ReadHalf;
+
+    fn entry(ts_ms: u64, text: &str) -> RecordEntry {
+        RecordEntry {
+            ts_ms,
+            socket: 1,
+            dir: Direction::In,
+            host: "irc7".to_string(),
+            port: 6667,
+            text: Some(text.to_string()),
+            hex: None,
+        }
+    }
+
+    /// Starts feeding `inbound` and returns the OCX's side of the connection.
+    fn feed(
+        inbound: Vec<RecordEntry>,
+        mode: ReplayPacing,
+        stepper: Arc<Stepper>,
+    ) -> ReadHalf<DuplexStream> {
+        let (client, server) = tokio::io::duplex(DUPLEX_CAPACITY);
+        let (_, server_write) = tokio::io::split(server);
+        let start_ts = inbound.first().map_or(0, |e| e.ts_ms);
+        tokio::spawn(feed_inbound(
+            server_write,
+            inbound,
+            start_ts,
+            Pacing { mode, stepper },
+            true,
+        ));
+        tokio::io::split(client).0
+    }
+
+    async fn read_chunk(reader: &mut ReadHalf<DuplexStream>) -> String {
+        let mut buf = [0u8; 256];
+        let n = tokio::time::timeout(Duration::from_secs(2), reader.read(&mut buf))
+            .await
+            .expect("no chunk arrived")
+            .unwrap();
+        String::from_utf8_lossy(&buf[..n]).into_owned()
+    }
+
+    #[test]
+    fn timed_pacing_keeps_the_recorded_gaps() {
+        get_rt().block_on(async {
+            let inbound = vec![
+                entry(1_000, "NOTICE AUTH :one\r\n"),
+                entry(1_150, "NOTICE AUTH :two\r\n"),
+                entry(1_300, "NOTICE AUTH :three\r\n"),
+            ];
+            let start = Instant::now();
+            let mut reader = feed(inbound, ReplayPacing::Timed, Arc::default());
+            let mut arrivals = Vec::new();
+            for _ in 0..3 {
+                let chunk = read_chunk(&mut reader).await;
+                arrivals.push((chunk, start.elapsed()));
+            }
+
+            assert_eq!(arrivals[0].0, "NOTICE AUTH :one\r\n");
+            assert_eq!(arrivals[2].0, "NOTICE AUTH :three\r\n");
+            assert!(arrivals[0].1 < Duration::from_millis(100));
+            assert!(arrivals[1].1 >= Duration::from_millis(150));
+            assert!(arrivals[2].1 >= Duration::from_millis(300));
+            assert_eq!(read_chunk(&mut reader).await, "");
+        });
+    }
+
+    #[test]
+    fn replay_releases_the_earliest_waiting_chunk_of_any_connection() {
+        get_rt().block_on(async {
+            let steppers = [Arc::new(Stepper::default()), Arc::new(Stepper::default())];
+            if let Ok(mut registered) = STEPPERS.lock() {
+                registered.extend(steppers.iter().map(Arc::downgrade));
+            }
+            let mut late = feed(
+                vec![entry(200, "late\r\n")],
+                ReplayPacing::Manual,
+                steppers[0].clone(),
+            );
+            let mut early = feed(
+                vec![entry(100, "early\r\n"), entry(300, "last\r\n")],
+                ReplayPacing::Manual,
+                steppers[1].clone(),
+            );
+            // Wait until both connections are holding a chunk back.
+            while steppers.iter().any(|s| s.waiting.lock().unwrap().is_none()) {
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+
+            step();
+            assert_eq!(read_chunk(&mut early).await, "early\r\n");
+            step();
+            assert_eq!(read_chunk(&mut late).await, "late\r\n");
+            step();
+            assert_eq!(read_chunk(&mut early).await, "last\r\n");
+        });
+    }
+
+    #[test]
+    fn outgoing_lines_are_checked_against_the_recording() {
+        get_rt().block_on(async {
+            let (mut client, server) = tokio::io::duplex(DUPLEX_CAPACITY);
+            let (server_read, _server_write) = tokio::io::split(server);
+            let expected = ["NICK me", "PASS [REDACTED]", "JOIN #a", "PART #a"]
+                .into_iter()
+                .map(str::to_string)
+                .collect();
+            let check = tokio::spawn(check_outbound(server_read, expected));
+
+            client
+                .write_all(b"NICK me\r\nPASS hunter2\r\n\r\nJOIN #b\n")
+                .await
+                .unwrap();
+            client.shutdown().await.unwrap();
+            assert_eq!(
+                check.await.unwrap(),
+                OutboundCheck {
+                    lines: 3,
+                    mismatches: 1,
+                    unsent: 1,
+                }
+            );
+
+            let (mut client, server) = tokio::io::duplex(DUPLEX_CAPACITY);
+            let (server_read, _server_write) = tokio::io::split(server);
+            let check = tokio::spawn(check_outbound(server_read, VecDeque::new()));
+            client.write_all(b"QUIT\r\n").await.unwrap();
+            client.shutdown().await.unwrap();
+            assert_eq!(
+                check.await.unwrap(),
+                OutboundCheck {
+                    lines: 1,
+                    mismatches: 1,
+                    unsent: 0,
+                }
+            );
+        });
+    }
+}
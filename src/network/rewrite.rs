@@ -0,0 +1,137 @@
+//! Host/port rewrite table applied to every OCX connect before resolution.
+//!
+//! This lets the hardcoded `dir.irc7.com` directory server, or channel servers returned in
+//! FINDS replies, be redirected to staging servers or a local stand-in.
+
+use crate::config::RewriteRule;
+use crate::network::pattern::host_matches;
+
+/// Returns the target for `host:port` after applying the first matching rule.
+pub fn apply(rules: &[RewriteRule], host: &str, port: u16) -> (String, u16) {
+    for rule in rules {
+        let (from_host, from_port) = split_host_port(&rule.from);
+        let port_matches = match from_port {
+            None | Some("*") => true,
+            Some(p) => p.parse::<u16>().is_ok_and(|p| p == port),
+        };
+        if !port_matches || !host_matches(from_host, host) {
+            continue;
+        }
+
+        let (to_host, to_port) = split_host_port(&rule.to);
+        let new_port = match to_port {
+            None | Some("*") => port,
+            Some(p) => match p.parse::<u16>() {
+                Ok(p) => p,
+                Err(_) => {
+                    log::warn!("Ignoring rewrite rule with invalid port: {:?}", rule.to);
+                    continue;
+                }
+            },
+        };
+        let new_host = if to_host.is_empty() || to_host == "*" {
+            host.to_string()
+        } else {
+            to_host.to_string()
+        };
+
+        log::info!(
+            "Rewriting connection {}:{} -> {}:{} (rule {:?})",
+            host,
+            port,
+            new_host,
+            new_port,
+            rule.from
+        );
+        return (new_host, new_port);
+    }
+
+    (host.to_string(), port)
+}
+
+/// Splits `host:port`, `[v6]:port`, or a bare host into its parts.
+pub(crate) fn split_host_port(spec: &str) -> (&str, Option<&str>) {
+    let spec = spec.trim();
+    if let Some(rest) = spec.strip_prefix('[') {
+        if let Some((host, tail)) = rest.split_once(']') {
+            return (host, tail.strip_prefix(':'));
+        }
+    }
+    match spec.rsplit_once(':') {
+        // More than one colon without brackets is a bare IPv6 address.
+        Some((host, _)) if host.contains(':') => (spec, None),
+        Some((host, port)) => (host, Some(port)),
+        None => (spec, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(pairs: &[(&str, &str)]) -> Vec<RewriteRule> {
+        pairs
+            .iter()
+            .map(|(from, to)| RewriteRule {
+                from: from.to_string(),
+                to: to.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn host_and_port_are_split() {
+        let cases = [
+            ("dir.irc7.com:6667", ("dir.irc7.com", Some("6667"))),
+            ("dir.irc7.com", ("dir.irc7.com", None)),
+            ("*.irc7.com:*", ("*.irc7.com", Some("*"))),
+            ("  localhost:7000 ", ("localhost", Some("7000"))),
+            ("[::1]:6667", ("::1", Some("6667"))),
+            ("[2001:db8::1]", ("2001:db8::1", None)),
+            ("2001:db8::1", ("2001:db8::1", None)),
+            ("::1", ("::1", None)),
+        ];
+        for (spec, expected) in cases {
+            assert_eq!(split_host_port(spec), expected, "{spec:?}");
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_is_applied() {
+        let rules = rules(&[
+            ("dir.irc7.com:6667", "staging.example:7000"),
+            ("dir.irc7.com", "127.0.0.1"),
+            ("*.irc7.com:*", "[::1]:6668"),
+        ]);
+
+        assert_eq!(
+            apply(&rules, "dir.irc7.com", 6667),
+            ("staging.example".to_string(), 7000)
+        );
+        // The first rule is port-specific; the second keeps the original port.
+        assert_eq!(
+            apply(&rules, "DIR.irc7.com", 6668),
+            ("127.0.0.1".to_string(), 6668)
+        );
+        assert_eq!(
+            apply(&rules, "chat1.irc7.com", 6667),
+            ("::1".to_string(), 6668)
+        );
+        assert_eq!(
+            apply(&rules, "chat.other.net", 6667),
+            ("chat.other.net".to_string(), 6667)
+        );
+    }
+
+    #[test]
+    fn rules_with_a_bad_target_port_are_skipped() {
+        let rules = rules(&[
+            ("*:6667", "staging.example:not-a-port"),
+            ("*:6667", "*:7000"),
+        ]);
+        assert_eq!(
+            apply(&rules, "dir.irc7.com", 6667),
+            ("dir.irc7.com".to_string(), 7000)
+        );
+    }
+}
@@ -0,0 +1,390 @@
+//! Per-socket outgoing queue with priorities and an optional token-bucket rate limit.
+//!
+//! `send_socket` frames whatever the OCX writes into lines and queues each complete one as
+//! control or bulk traffic; a partial line waits in the queue's framer for the rest of it.
+//! The writer task always drains control lines first, and when `[network.rate_limit]` is
+//! enabled bulk lines (and control lines, unless they bypass the limiter) have to take a
+//! token from the bucket before they hit the wire. This keeps a large paste from getting
+//! the user killed for flooding while PONG and NICK still go out immediately. QUIT is bulk
+//! traffic, so everything the OCX sent before it still reaches the server first.
+//!
+//! Past its byte limit the queue refuses the OCX's data through [`SendQueue::try_push`],
+//! like a non-blocking `send` failing with `WSAEWOULDBLOCK`, and [`SendQueue::take_unblocked`]
+//! tells the writer when to report the socket writable again. Lines the Rust side queues
+//! itself (PONGs, replayed registration) always go in through [`SendQueue::push`].
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+use crate::config::RateLimitConfig;
+use crate::network::framing::LineFramer;
+use crate::protocol::message::{Command, Message};
+
+const DEFAULT_BURST: u32 = 5;
+const DEFAULT_LINES_PER_SEC: f64 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    Control,
+    Bulk,
+}
+
+/// Why [`SendQueue::try_push`] did not take data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refused {
+    /// Over the byte limit; try again once the writer has caught up.
+    Full,
+    Closed,
+}
+
+/// Snapshot of a socket's queued outgoing traffic.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QueueDepth {
+    pub control: usize,
+    pub bulk: usize,
+    pub bytes: usize,
+}
+
+/// Classifies one outgoing line by its command word. Keepalives, NICK and registration jump
+/// ahead of bulk traffic.
+pub fn classify(line: &[u8]) -> Priority {
+    let Some(message) = Message::parse(&String::from_utf8_lossy(line)) else {
+        return Priority::Bulk;
+    };
+    match message.command {
+        Command::Ping | Command::Pong | Command::Nick | Command::Auth => Priority::Control,
+        Command::Other(ref c) if matches!(c.as_str(), "PASS" | "USER" | "IRCVERS") => {
+            Priority::Control
+        }
+        _ => Priority::Bulk,
+    }
+}
+
+#[derive(Default)]
+struct QueueState {
+    control: VecDeque<Vec<u8>>,
+    bulk: VecDeque<Vec<u8>>,
+    bytes: usize,
+    closed: bool,
+    framer: LineFramer,
+    /// Where the rest of a line goes after its start was queued unterminated.
+    continuing: Option<Priority>,
+    /// Set when `try_push` refused data, until the queue is back under its limit.
+    blocked: bool,
+}
+
+impl QueueState {
+    /// Queues `line`, or the next piece of an unterminated one in the queue its start
+    /// went to.
+    fn queue(&mut self, line: Vec<u8>) {
+        let priority = self.continuing.take().unwrap_or_else(|| classify(&line));
+        if !line.ends_with(b"\n") {
+            self.continuing = Some(priority);
+        }
+        self.bytes += line.len();
+        match priority {
+            Priority::Control => self.control.push_back(line),
+            Priority::Bulk => self.bulk.push_back(line),
+        }
+    }
+}
+
+pub struct SendQueue {
+    state: Mutex<QueueState>,
+    notify: Notify,
+    /// Bytes that may be waiting before `try_push` refuses more.
+    limit: usize,
+}
+
+impl SendQueue {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            state: Mutex::default(),
+            notify: Notify::new(),
+            limit,
+        }
+    }
+
+    /// Frames `data` and queues each line it completes, even past the byte limit. Returns
+    /// false only once the queue is closed.
+    pub fn push(&self, data: &[u8]) -> bool {
+        let Ok(mut state) = self.state.lock() else {
+            return false;
+        };
+        if state.closed {
+            return false;
+        }
+        for line in state.framer.push_inclusive(data) {
+            state.queue(line);
+        }
+        drop(state);
+        self.notify.notify_one();
+        true
+    }
+
+    /// Like [`push`](Self::push), but takes nothing while more than the byte limit is
+    /// queued.
+    pub fn try_push(&self, data: &[u8]) -> Result<(), Refused> {
+        let Ok(mut state) = self.state.lock() else {
+            return Err(Refused::Closed);
+        };
+        if state.closed {
+            return Err(Refused::Closed);
+        }
+        if state.bytes > self.limit {
+            state.blocked = true;
+            return Err(Refused::Full);
+        }
+        for line in state.framer.push_inclusive(data) {
+            state.queue(line);
+        }
+        drop(state);
+        self.notify.notify_one();
+        Ok(())
+    }
+
+    /// Stops accepting new lines. Lines already queued, and a partial line still waiting
+    /// for its terminator, are still handed to the writer.
+    pub fn close(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            if !state.closed {
+                let partial = state.framer.take_partial();
+                if !partial.is_empty() {
+                    state.queue(partial);
+                }
+            }
+            state.closed = true;
+        }
+        self.notify.notify_one();
+    }
+
+    /// Whether more is queued than the byte limit allows.
+    pub fn is_full(&self) -> bool {
+        self.state.lock().is_ok_and(|s| s.bytes > self.limit)
+    }
+
+    /// Whether data was refused since the queue was last under its limit, and it is under it
+    /// now. Clears the flag, so each refusal is answered once.
+    pub fn take_unblocked(&self) -> bool {
+        let Ok(mut state) = self.state.lock() else {
+            return false;
+        };
+        let unblocked = state.blocked && state.bytes <= self.limit;
+        if unblocked {
+            state.blocked = false;
+        }
+        unblocked
+    }
+
+    pub fn depth(&self) -> QueueDepth {
+        self.state
+            .lock()
+            .map(|s| QueueDepth {
+                control: s.control.len(),
+                bulk: s.bulk.len(),
+                bytes: s.bytes,
+            })
+            .unwrap_or_default()
+    }
+
+    /// Waits for the next line the writer may send, honouring priority and the rate limit.
+    /// Returns `None` once the queue is closed and drained.
+    pub async fn next(&self, limiter: &mut Option<RateLimiter>) -> Option<Vec<u8>> {
+        loop {
+            match self.try_next(limiter) {
+                Next::Line(line) => return Some(line),
+                Next::Closed => return None,
+                // Nothing queued: sleep until the next push or close.
+                Next::Empty => self.notify.notified().await,
+                // Out of tokens: wait for one to accrue, or for a control line to jump the queue.
+                Next::Throttled(wait) => {
+                    let _ = tokio::time::timeout(wait, self.notify.notified()).await;
+                }
+            }
+        }
+    }
+
+    fn try_next(&self, limiter: &mut Option<RateLimiter>) -> Next {
+        let Ok(mut state) = self.state.lock() else {
+            return Next::Closed;
+        };
+        let priority = if !state.control.is_empty() {
+            Priority::Control
+        } else if !state.bulk.is_empty() {
+            Priority::Bulk
+        } else if state.closed {
+            return Next::Closed;
+        } else {
+            return Next::Empty;
+        };
+
+        if let Some(Err(wait)) = limiter.as_mut().map(|l| l.admit(priority)) {
+            return Next::Throttled(wait);
+        }
+        let line = match priority {
+            Priority::Control => state.control.pop_front(),
+            Priority::Bulk => state.bulk.pop_front(),
+        }
+        .unwrap_or_default();
+        state.bytes -= line.len();
+        Next::Line(line)
+    }
+}
+
+enum Next {
+    Line(Vec<u8>),
+    Empty,
+    Throttled(Duration),
+    Closed,
+}
+
+/// Token bucket measured in lines.
+pub struct RateLimiter {
+    capacity: f64,
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+    control_bypass: bool,
+}
+
+impl RateLimiter {
+    /// Builds a limiter from config, or `None` when rate limiting is disabled.
+    pub fn from_config(config: &RateLimitConfig) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        let capacity = config.burst.unwrap_or(DEFAULT_BURST).max(1) as f64;
+        let rate = config
+            .lines_per_sec
+            .unwrap_or(DEFAULT_LINES_PER_SEC)
+            .max(0.01);
+        Some(Self {
+            capacity,
+            rate,
+            tokens: capacity,
+            last: Instant::now(),
+            control_bypass: config.control_bypass.unwrap_or(true),
+        })
+    }
+
+    /// Takes a token for a line of `priority`, or returns how long until one is available.
+    fn admit(&mut self, priority: Priority) -> Result<(), Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = now;
+
+        if priority == Priority::Control && self.control_bypass {
+            // Still spend a token when one is available so bulk traffic cannot exceed the
+            // configured rate by riding along with control lines.
+            self.tokens = (self.tokens - 1.0).max(0.0);
+            return Ok(());
+        }
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::framing::MAX_LINE_LEN;
+
+    fn drain(queue: &SendQueue) -> Vec<u8> {
+        let mut sent = Vec::new();
+        while let Next::Line(line) = queue.try_next(&mut None) {
+            sent.extend_from_slice(&line);
+        }
+        sent
+    }
+
+    #[test]
+    fn a_line_split_across_pushes_is_classified_whole() {
+        let queue = SendQueue::new(1024);
+        assert!(queue.push(b"PRIVMSG #a :hi\r\nPO"));
+        assert_eq!(queue.depth().bulk, 1);
+        assert_eq!(queue.depth().control, 0);
+        assert!(queue.push(b"NG :irc7\r\n"));
+        assert_eq!(queue.depth().control, 1);
+        assert_eq!(drain(&queue), b"PONG :irc7\r\nPRIVMSG #a :hi\r\n");
+    }
+
+    #[test]
+    fn an_unframed_line_keeps_its_queue() {
+        let queue = SendQueue::new(64 * 1024);
+        let mut long = b"PRIVMSG #a :".to_vec();
+        long.resize(MAX_LINE_LEN + 10, b'x');
+        assert!(queue.push(&long));
+        // Starts like a control line, but is the rest of the PRIVMSG.
+        assert!(queue.push(b"PONG\r\nPING :x\r\n"));
+        let depth = queue.depth();
+        assert_eq!((depth.bulk, depth.control), (2, 1));
+    }
+
+    #[test]
+    fn a_full_queue_refuses_data_until_drained() {
+        let queue = SendQueue::new(16);
+        assert_eq!(queue.try_push(b"PRIVMSG #a :0123456789\r\n"), Ok(()));
+        assert!(queue.is_full());
+        assert_eq!(queue.try_push(b"PRIVMSG #a :more\r\n"), Err(Refused::Full));
+        assert!(!queue.take_unblocked());
+        // The Rust side's own lines still go in.
+        assert!(queue.push(b"PONG :irc7\r\n"));
+
+        assert_eq!(drain(&queue), b"PONG :irc7\r\nPRIVMSG #a :0123456789\r\n");
+        assert!(queue.take_unblocked());
+        assert!(!queue.take_unblocked());
+        assert_eq!(queue.try_push(b"PRIVMSG #a :more\r\n"), Ok(()));
+
+        queue.close();
+        assert_eq!(
+            queue.try_push(b"PRIVMSG #a :late\r\n"),
+            Err(Refused::Closed)
+        );
+    }
+
+    #[test]
+    fn quit_follows_queued_bulk_while_keepalives_jump_ahead() {
+        let queue = SendQueue::new(1024);
+        assert!(queue.push(b"PRIVMSG #a :one\r\nPRIVMSG #a :two\r\nQUIT :bye\r\n"));
+        assert!(queue.push(b"PONG :irc7\r\nPING :x\r\n"));
+        assert_eq!(
+            drain(&queue),
+            b"PONG :irc7\r\nPING :x\r\nPRIVMSG #a :one\r\nPRIVMSG #a :two\r\nQUIT :bye\r\n"
+        );
+    }
+
+    #[test]
+    fn lines_are_classified_by_their_command() {
+        for line in [
+            "PING :x",
+            "PONG :x",
+            "NICK me",
+            "pass secret",
+            "USER a b c :d",
+            "IRCVERS IRC8 MSN-OCX!9.02.0310.2401",
+            "AUTH GateKeeper I :x",
+        ] {
+            assert_eq!(classify(line.as_bytes()), Priority::Control, "{line}");
+        }
+        for line in ["QUIT :bye", "PRIVMSG #a :PING", "JOIN #a", "", "PINGS x"] {
+            assert_eq!(classify(line.as_bytes()), Priority::Bulk, "{line}");
+        }
+    }
+
+    #[test]
+    fn close_sends_the_partial_tail() {
+        let queue = SendQueue::new(1024);
+        assert!(queue.push(b"QUIT :bye"));
+        assert_eq!(queue.depth().bytes, 0);
+        queue.close();
+        assert_eq!(drain(&queue), b"QUIT :bye");
+        assert!(matches!(queue.try_next(&mut None), Next::Closed));
+    }
+}
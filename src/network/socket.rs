@@ -1,29 +1,249 @@
-use std::ffi::c_void;
-use tokio::sync::mpsc::UnboundedSender;
+use bytes::BytesMut;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::Notify;
+use tokio::task::AbortHandle;
+
+use crate::network::dial::DialReport;
+use crate::network::events::SocketEvents;
+use crate::network::reconnect::{Diversion, Registration};
+use crate::network::send_queue::{QueueDepth, SendQueue};
+
+/// Traffic and health counters kept for every `RustSocket`.
+#[derive(Debug, Clone, Default)]
+pub struct SocketStats {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub lines_in: u64,
+    pub lines_out: u64,
+    pub connected_at: Option<SystemTime>,
+    pub last_activity: Option<SystemTime>,
+    pub reconnects: u32,
+    /// Round-trip time of the most recent PING/PONG exchange.
+    pub ping_rtt: Option<Duration>,
+    /// TCP connect attempts made for this socket, across reconnects.
+    pub connect_attempts: u32,
+    /// Address the current connection was dialled to, when connected directly.
+    pub remote_addr: Option<SocketAddr>,
+    ping_sent_at: Option<Instant>,
+}
+
+/// Whether a framed IRC line carries `command` (upper case), skipping any `:prefix`. Only
+/// looks at the command word, so it is cheap enough for every line.
+fn has_command(line: &[u8], command: &[u8]) -> bool {
+    let mut rest = line;
+    if rest.first() == Some(&b':') {
+        match rest.iter().position(|&b| b == b' ') {
+            Some(end) => rest = &rest[end..],
+            None => return false,
+        }
+    }
+    let rest = rest.trim_ascii_start();
+    rest.get(..command.len())
+        .is_some_and(|word| word.eq_ignore_ascii_case(command))
+        && rest.get(command.len()).is_none_or(|&b| b == b' ')
+}
+
+impl SocketStats {
+    /// Counts `data` read from the server, and `lines`, the lines the reader framed from it.
+    pub fn record_in(&mut self, data: &[u8], lines: &[Vec<u8>]) {
+        self.bytes_in += data.len() as u64;
+        self.last_activity = Some(SystemTime::now());
+        self.lines_in += lines.len() as u64;
+        if lines.iter().any(|line| has_command(line, b"PONG")) {
+            if let Some(sent) = self.ping_sent_at.take() {
+                self.ping_rtt = Some(sent.elapsed());
+            }
+        }
+    }
+
+    pub fn record_dial(&mut self, report: &DialReport) {
+        self.connect_attempts += report.attempts;
+        self.remote_addr = report.addr;
+    }
+
+    /// Counts `data` queued for the server.
+    pub fn record_out(&mut self, data: &[u8]) {
+        self.bytes_out += data.len() as u64;
+        self.last_activity = Some(SystemTime::now());
+    }
+
+    /// Counts `lines`, framed by the writer as it put them on the wire.
+    pub fn record_lines_out(&mut self, lines: &[Vec<u8>]) {
+        self.lines_out += lines.len() as u64;
+        if self.ping_sent_at.is_none() && lines.iter().any(|line| has_command(line, b"PING")) {
+            self.ping_sent_at = Some(Instant::now());
+        }
+    }
+
+    /// Time since the last byte was read or written.
+    pub fn idle(&self) -> Option<Duration> {
+        self.last_activity.and_then(|t| t.elapsed().ok())
+    }
+}
+
+/// Point-in-time view of a socket returned by `network::manager::socket_stats`.
+#[derive(Debug, Clone)]
+pub struct SocketSnapshot {
+    pub id: u32,
+    pub host: String,
+    pub port: u16,
+    pub connected: bool,
+    pub reconnecting: bool,
+    pub queue: QueueDepth,
+    pub stats: SocketStats,
+}
+
+impl fmt::Display for SocketSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.reconnecting {
+            "reconnecting"
+        } else if self.connected {
+            "connected"
+        } else {
+            "not connected"
+        };
+        write!(f, "#{} {}:{} {}", self.id, self.host, self.port, state)?;
+        if let Some(addr) = self.stats.remote_addr {
+            write!(f, " via {}", addr)?;
+        }
+        if let Some(up) = self.stats.connected_at.and_then(|t| t.elapsed().ok()) {
+            write!(f, " for {}s", up.as_secs())?;
+        }
+        write!(
+            f,
+            ", in {} B/{} lines, out {} B/{} lines",
+            self.stats.bytes_in, self.stats.lines_in, self.stats.bytes_out, self.stats.lines_out
+        )?;
+        if let Some(idle) = self.stats.idle() {
+            write!(f, ", idle {}s", idle.as_secs())?;
+        }
+        if let Some(rtt) = self.stats.ping_rtt {
+            write!(f, ", rtt {}ms", rtt.as_millis())?;
+        }
+        write!(
+            f,
+            ", queued {}/{}, connect attempts {}, reconnects {}",
+            self.queue.control, self.queue.bulk, self.stats.connect_attempts, self.stats.reconnects
+        )
+    }
+}
 
 pub struct RustSocket {
     pub id: u32,
-    pub tx: Option<UnboundedSender<Vec<u8>>>,
-    pub rx_buffer: Vec<u8>,
-    pub callback_ptr: *mut c_void,
-    pub context_ptr: *mut c_void,
+    pub send_queue: Option<Arc<SendQueue>>,
+    pub rx_buffer: BytesMut,
+    /// Signalled whenever `receive_socket` frees space in `rx_buffer`, or the socket closes.
+    pub rx_space: Arc<Notify>,
+    /// Where connection events are reported; the OCX callback object once registered.
+    pub events: Option<Arc<dyn SocketEvents>>,
     pub connected: bool,
     pub closed: bool,
+    /// Target actually dialled, after `[[network.rewrite]]` rules.
+    pub host: String,
+    pub port: u16,
+    /// Set while the reconnect supervisor is re-dialling this socket.
+    pub reconnecting: bool,
+    /// How the OCX registered (IRCVERS, AUTH, NICK/USER), replayed on reconnect.
+    pub registration: Registration,
+    /// Set while a resumed connection registers; its lines go to the supervisor.
+    pub(crate) resume: Option<Diversion>,
+    /// Whether the OCX has sent a JOIN on this socket, i.e. it is a channel connection.
+    pub joined: bool,
+    pub stats: SocketStats,
+    pub reader_task: Option<AbortHandle>,
+    pub writer_task: Option<AbortHandle>,
+    /// How long `close_socket` lets the writer flush queued data.
+    pub linger: Duration,
 }
 
-unsafe impl Send for RustSocket {}
-unsafe impl Sync for RustSocket {}
-
 impl RustSocket {
     pub fn new(id: u32) -> Self {
         Self {
             id,
-            tx: None,
-            rx_buffer: Vec::new(),
-            callback_ptr: std::ptr::null_mut(),
-            context_ptr: std::ptr::null_mut(),
+            send_queue: None,
+            rx_buffer: BytesMut::new(),
+            rx_space: Arc::new(Notify::new()),
+            events: None,
             connected: false,
             closed: false,
+            host: String::new(),
+            port: 0,
+            reconnecting: false,
+            registration: Registration::default(),
+            resume: None,
+            joined: false,
+            stats: SocketStats::default(),
+            reader_task: None,
+            writer_task: None,
+            linger: Duration::ZERO,
+        }
+    }
+
+    pub fn snapshot(&self) -> SocketSnapshot {
+        SocketSnapshot {
+            id: self.id,
+            host: self.host.clone(),
+            port: self.port,
+            connected: self.connected,
+            reconnecting: self.reconnecting,
+            queue: self
+                .send_queue
+                .as_ref()
+                .map(|q| q.depth())
+                .unwrap_or_default(),
+            stats: self.stats.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::framing::LineFramer;
+
+    #[test]
+    fn commands_are_found_by_their_word() {
+        for (line, command, expected) in [
+            (&b"PONG :irc7"[..], &b"PONG"[..], true),
+            (b"pong irc7", b"PONG", true),
+            (b":irc7.example PONG irc7 :irc7", b"PONG", true),
+            (b":irc7.example  PONG", b"PONG", true),
+            (b"PONG", b"PONG", true),
+            (b"PONGS :x", b"PONG", false),
+            (b"PRIVMSG #a :PONG", b"PONG", false),
+            (b":PONG", b"PONG", false),
+            (b"PON", b"PONG", false),
+            (b"", b"PING", false),
+        ] {
+            assert_eq!(
+                has_command(line, command),
+                expected,
+                "{:?}",
+                String::from_utf8_lossy(line)
+            );
+        }
+    }
+
+    #[test]
+    fn a_pong_split_across_reads_ends_the_round_trip() {
+        let mut stats = SocketStats::default();
+        let mut framer = LineFramer::new();
+        let ping = framer.push(b"PING :irc7\r\n");
+        stats.record_out(b"PING :irc7\r\n");
+        stats.record_lines_out(&ping);
+        assert_eq!(stats.lines_out, 1);
+
+        let mut framer = LineFramer::new();
+        for chunk in [&b":irc7 PO"[..], b"NG irc7 :irc7\r", b"\n"] {
+            assert!(stats.ping_rtt.is_none());
+            let lines = framer.push(chunk);
+            stats.record_in(chunk, &lines);
         }
+        assert!(stats.ping_rtt.is_some());
+        assert_eq!(stats.lines_in, 1);
+        assert_eq!(stats.bytes_in, 23);
     }
 }
@@ -0,0 +1,246 @@
+//! Optional rustls layer wrapped around an established TCP (or proxied) stream.
+
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::crypto::{CryptoProvider, ring};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio_rustls::rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+
+use crate::config::{TlsConfig, TlsRule};
+use crate::network::pattern::host_matches;
+
+/// A client config built for one CA bundle list and `accept_invalid` setting.
+struct CachedConfig {
+    ca_bundles: Vec<PathBuf>,
+    accept_invalid: bool,
+    client_config: Arc<ClientConfig>,
+}
+
+/// Client configs built so far. Building one reads the CA bundles from disk, so each
+/// setting is built once and shared by later connects.
+static CLIENT_CONFIGS: Mutex<Vec<CachedConfig>> = Mutex::new(Vec::new());
+
+/// Returns the first TLS rule matching `host:port`, if TLS should be used for it.
+pub fn rule_for<'a>(config: &'a TlsConfig, host: &str, port: u16) -> Option<&'a TlsRule> {
+    config
+        .rules
+        .iter()
+        .find(|rule| rule.port.is_none_or(|p| p == port) && host_matches(&rule.host, host))
+}
+
+/// Performs the TLS handshake over `stream` according to `rule`.
+pub async fn connect(
+    config: &TlsConfig,
+    rule: &TlsRule,
+    host: &str,
+    stream: TcpStream,
+) -> io::Result<TlsStream<TcpStream>> {
+    let accept_invalid = config.accept_invalid_certs || rule.accept_invalid_certs;
+    let client_config = client_config(config, accept_invalid)?;
+
+    let name = rule.server_name.as_deref().unwrap_or(host).to_owned();
+    let server_name = ServerName::try_from(name.clone()).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid TLS server name {:?}: {}", name, e),
+        )
+    })?;
+
+    log::info!(
+        "Starting TLS handshake with {} (verify certificates: {})",
+        name,
+        !accept_invalid
+    );
+    let stream = TlsConnector::from(client_config)
+        .connect(server_name, stream)
+        .await?;
+    log::info!("TLS session with {} established", name);
+    Ok(stream)
+}
+
+/// The cached client config for these settings, built on first use.
+fn client_config(config: &TlsConfig, accept_invalid: bool) -> io::Result<Arc<ClientConfig>> {
+    let cached = |cache: &[CachedConfig]| {
+        cache
+            .iter()
+            .find(|c| c.accept_invalid == accept_invalid && c.ca_bundles == config.ca_bundles)
+            .map(|c| c.client_config.clone())
+    };
+    if let Some(client_config) = CLIENT_CONFIGS.lock().ok().and_then(|c| cached(&c)) {
+        return Ok(client_config);
+    }
+
+    // Built without the lock held; if a racing connect built the same config first, its
+    // copy is kept.
+    let built = Arc::new(build_client_config(config, accept_invalid)?);
+    let Ok(mut cache) = CLIENT_CONFIGS.lock() else {
+        return Ok(built);
+    };
+    if let Some(client_config) = cached(&cache) {
+        return Ok(client_config);
+    }
+    cache.push(CachedConfig {
+        ca_bundles: config.ca_bundles.clone(),
+        accept_invalid,
+        client_config: built.clone(),
+    });
+    Ok(built)
+}
+
+fn build_client_config(config: &TlsConfig, accept_invalid: bool) -> io::Result<ClientConfig> {
+    let provider = Arc::new(ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?;
+
+    if accept_invalid {
+        log::warn!("TLS certificate verification is DISABLED (accept_invalid_certs)");
+        return Ok(builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyCert(provider)))
+            .with_no_client_auth());
+    }
+
+    let mut roots = RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    for bundle in &config.ca_bundles {
+        let certs = CertificateDer::pem_file_iter(bundle)
+            .and_then(|iter| iter.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("failed to read CA bundle {}: {}", bundle.display(), e),
+                )
+            })?;
+        let (added, ignored) = roots.add_parsable_certificates(certs);
+        log::info!(
+            "Loaded {} CA certificate(s) from {} ({} ignored)",
+            added,
+            bundle.display(),
+            ignored
+        );
+    }
+
+    Ok(builder.with_root_certificates(roots).with_no_client_auth())
+}
+
+/// Certificate verifier used by the `accept_invalid_certs` development switch.
+///
+/// Handshake signatures are still checked so that the session keys are sound; only the
+/// chain of trust and the host name are ignored.
+#[derive(Debug)]
+struct AcceptAnyCert(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCert {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        tokio_rustls::rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        tokio_rustls::rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(host: &str, port: Option<u16>) -> TlsRule {
+        TlsRule {
+            host: host.to_string(),
+            port,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let config = TlsConfig {
+            rules: vec![
+                rule("irc.example.com", Some(6697)),
+                rule("*.example.com", None),
+                rule("*", Some(994)),
+            ],
+            ..Default::default()
+        };
+        let host_of = |host, port| rule_for(&config, host, port).map(|r| r.host.as_str());
+
+        assert_eq!(host_of("irc.example.com", 6697), Some("irc.example.com"));
+        // The port filter skips the first rule.
+        assert_eq!(host_of("IRC.Example.com", 6667), Some("*.example.com"));
+        assert_eq!(host_of("chat.other.net", 994), Some("*"));
+        assert_eq!(host_of("chat.other.net", 6667), None);
+    }
+
+    #[test]
+    fn client_configs_are_built_once_per_setting() {
+        let config = TlsConfig::default();
+        let verified = client_config(&config, false).unwrap();
+        let unverified = client_config(&config, true).unwrap();
+
+        assert!(Arc::ptr_eq(
+            &verified,
+            &client_config(&config, false).unwrap()
+        ));
+        assert!(Arc::ptr_eq(
+            &unverified,
+            &client_config(&config, true).unwrap()
+        ));
+        assert!(!Arc::ptr_eq(&verified, &unverified));
+    }
+
+    #[test]
+    fn unreadable_ca_bundle_is_an_error_and_not_cached() {
+        let config = TlsConfig {
+            ca_bundles: vec![PathBuf::from("does-not-exist/ca.pem")],
+            ..Default::default()
+        };
+        let err = client_config(&config, false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let cache = CLIENT_CONFIGS.lock().unwrap();
+        assert!(cache.iter().all(|c| c.ca_bundles != config.ca_bundles));
+    }
+}
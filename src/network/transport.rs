@@ -0,0 +1,12 @@
+//! Byte-stream abstraction shared by the reader and writer tasks.
+//!
+//! Whatever sits between the OCX and the server (plain TCP, a proxy tunnel, TLS) is boxed
+//! into a [`BoxedTransport`] so that `receive_socket`/`send_socket` only ever see plaintext.
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+pub trait Transport: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Transport for T {}
+
+pub type BoxedTransport = Box<dyn Transport>;
@@ -0,0 +1,207 @@
+//! IRC-over-WebSocket transport.
+//!
+//! Hosts matched by a `[[network.transports]]` rule with `kind = "websocket"` are reached
+//! over `ws://` or `wss://`. The WebSocket runs over the same TCP/proxy/TLS stack as a plain
+//! connection, and a bridge task turns it into an in-memory byte stream: every received
+//! message becomes one CRLF-terminated line, and every line the OCX writes is sent as one
+//! message without its terminator.
+
+use futures_util::{SinkExt, StreamExt};
+use std::io;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream, ReadHalf, WriteHalf};
+use tokio_tungstenite::WebSocketStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+
+use crate::config::{NetworkConfig, TlsRule, TransportKind, TransportRule};
+use crate::network::dial::DialReport;
+use crate::network::framing::LineFramer;
+use crate::network::manager::open_stream;
+use crate::network::pattern::host_matches;
+use crate::network::tls;
+use crate::network::transport::BoxedTransport;
+
+const DUPLEX_CAPACITY: usize = 64 * 1024;
+
+type WsSink = futures_util::stream::SplitSink<WebSocketStream<BoxedTransport>, Message>;
+type WsStream = futures_util::stream::SplitStream<WebSocketStream<BoxedTransport>>;
+
+/// Returns the first transport rule matching `host:port` if it selects WebSocket.
+pub fn rule_for<'a>(
+    rules: &'a [TransportRule],
+    host: &str,
+    port: u16,
+) -> Option<&'a TransportRule> {
+    rules
+        .iter()
+        .find(|rule| rule.port.is_none_or(|p| p == port) && host_matches(&rule.host, host))
+        .filter(|rule| rule.kind == TransportKind::WebSocket)
+}
+
+/// Connects to the WebSocket endpoint for `host:port` described by `rule`.
+pub async fn connect(
+    config: &NetworkConfig,
+    rule: &TransportRule,
+    host: &str,
+    port: u16,
+    report: &mut DialReport,
+) -> io::Result<BoxedTransport> {
+    let tls_rule = tls::rule_for(&config.tls, host, port);
+    let url = match rule.url {
+        Some(ref url) => url.clone(),
+        None => {
+            let scheme = if tls_rule.is_some() { "wss" } else { "ws" };
+            format!("{}://{}:{}/", scheme, host, port)
+        }
+    };
+
+    let mut request = url
+        .as_str()
+        .into_client_request()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    if let Some(ref subprotocol) = rule.subprotocol {
+        let value = HeaderValue::from_str(subprotocol)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        request
+            .headers_mut()
+            .insert("Sec-WebSocket-Protocol", value);
+    }
+
+    let secure = request.uri().scheme_str() == Some("wss");
+    let ws_host = request.uri().host().unwrap_or(host).to_string();
+    let ws_port = request
+        .uri()
+        .port_u16()
+        .unwrap_or(if secure { 443 } else { 80 });
+
+    log::info!("Opening WebSocket {} for {}:{}", url, host, port);
+    let stream = open_stream(config, &ws_host, ws_port, report).await?;
+    let stream: BoxedTransport = if secure {
+        let default_rule = TlsRule {
+            host: ws_host.clone(),
+            ..Default::default()
+        };
+        let rule = tls::rule_for(&config.tls, &ws_host, ws_port).unwrap_or(&default_rule);
+        Box::new(tls::connect(&config.tls, rule, &ws_host, stream).await?)
+    } else {
+        Box::new(stream)
+    };
+
+    let (ws, _response) = tokio_tungstenite::client_async(request, stream)
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::ConnectionRefused, e))?;
+    log::info!("WebSocket {} established", url);
+
+    let (sink, ws_stream) = ws.split();
+    let (client, server) = tokio::io::duplex(DUPLEX_CAPACITY);
+    let (server_read, server_write) = tokio::io::split(server);
+    tokio::spawn(pump_inbound(ws_stream, server_write));
+    tokio::spawn(pump_outbound(server_read, sink, rule.binary));
+
+    Ok(Box::new(client))
+}
+
+/// Unwraps received messages into CRLF-terminated lines for the reader task.
+async fn pump_inbound(mut ws: WsStream, mut writer: WriteHalf<DuplexStream>) {
+    while let Some(message) = ws.next().await {
+        let mut data = match message {
+            Ok(Message::Text(text)) => text.as_bytes().to_vec(),
+            Ok(Message::Binary(data)) => data.to_vec(),
+            Ok(Message::Close(frame)) => {
+                log::info!("WebSocket closed by server: {:?}", frame);
+                break;
+            }
+            // Pings are answered by tungstenite itself.
+            Ok(_) => continue,
+            Err(e) => {
+                log::error!("WebSocket read error: {}", e);
+                break;
+            }
+        };
+        if !data.ends_with(b"\n") {
+            data.extend_from_slice(b"\r\n");
+        }
+        if writer.write_all(&data).await.is_err() {
+            break;
+        }
+    }
+    let _ = writer.shutdown().await;
+}
+
+/// Sends each line written by the writer task as one message. An unterminated tail left
+/// when the writer task finishes goes out as a last message before the close.
+async fn pump_outbound(mut reader: ReadHalf<DuplexStream>, mut ws: WsSink, binary: bool) {
+    let mut framer = LineFramer::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = match reader.read(&mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => n,
+        };
+        for line in framer.push(&buf[..n]) {
+            if let Err(e) = ws.send(to_message(line, binary)).await {
+                log::error!("WebSocket write error: {}", e);
+                return;
+            }
+        }
+    }
+    let tail = framer.take_partial();
+    if !tail.is_empty() {
+        if let Err(e) = ws.send(to_message(tail, binary)).await {
+            log::error!("WebSocket write error: {}", e);
+            return;
+        }
+    }
+    let _ = ws.close().await;
+}
+
+fn to_message(line: Vec<u8>, binary: bool) -> Message {
+    match String::from_utf8(line) {
+        Ok(text) if !binary => Message::text(text),
+        Ok(text) => Message::binary(text.into_bytes()),
+        Err(e) => Message::binary(e.into_bytes()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::manager::get_rt;
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn an_unterminated_tail_is_sent_before_the_close() {
+        get_rt().block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let server = tokio::spawn(async move {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+                let mut received = Vec::new();
+                while let Some(Ok(message)) = ws.next().await {
+                    match message {
+                        Message::Text(text) => received.push(text.to_string()),
+                        Message::Close(_) => break,
+                        _ => {}
+                    }
+                }
+                received
+            });
+
+            let stream: BoxedTransport = Box::new(TcpStream::connect(addr).await.unwrap());
+            let (ws, _) = tokio_tungstenite::client_async(format!("ws://{addr}/"), stream)
+                .await
+                .unwrap();
+            let (sink, _ws_stream) = ws.split();
+            let (mut client, bridge) = tokio::io::duplex(DUPLEX_CAPACITY);
+            let (bridge_read, _bridge_write) = tokio::io::split(bridge);
+            let pump = tokio::spawn(pump_outbound(bridge_read, sink, false));
+
+            client.write_all(b"NICK me\r\nQUIT :bye").await.unwrap();
+            client.shutdown().await.unwrap();
+            pump.await.unwrap();
+            assert_eq!(server.await.unwrap(), ["NICK me", "QUIT :bye"]);
+        });
+    }
+}
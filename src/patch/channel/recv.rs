@@ -1,4 +1,6 @@
 use crate::patch::module_info::ModuleInfo;
+use crate::protocol::message::{self, Source};
+use crate::protocol::split;
 use std::ffi::c_void;
 
 type OnLineReceivedFn =
@@ -29,49 +31,86 @@ unsafe extern "thiscall" fn hook_on_line_received_cs(
     line: *const std::ffi::c_char,
     len: u32,
 ) -> i8 {
-    let mut trimmed_line = Vec::new();
-    let mut final_line = line;
-    let mut final_len = len;
+    let Some(orig) = (unsafe { TRAMPOLINE }) else {
+        return 1;
+    };
 
-    // TODO: Temporary workaround for IRC7 issue #199: trim lines over 510 bytes to prevent OCX logout.
+    // IRC7 issue #199: the OCX logs out on lines over 510 bytes, so those are re-emitted as
+    // several lines that fit.
     if !line.is_null() && len > 512 {
-        unsafe {
-            let slice = std::slice::from_raw_parts(line as *const u8, len as usize);
-            let ends_with_crlf = slice.ends_with(b"\r\n");
-            
-            let payload_limit = 510;
-            let trimmed_payload = if ends_with_crlf {
-                &slice[..std::cmp::min(slice.len() - 2, payload_limit)]
-            } else {
-                &slice[..std::cmp::min(slice.len(), payload_limit)]
-            };
-
-            trimmed_line.extend_from_slice(trimmed_payload);
-            if ends_with_crlf {
-                trimmed_line.extend_from_slice(b"\r\n");
-            }
-            trimmed_line.push(0);
-
-            let text_lossy = String::from_utf8_lossy(trimmed_payload);
-            log::warn!("⚠️🚨 Received line over 510 bytes (original length: {}). Trimming to 510 bytes! Preview: {} 🚨⚠️", len, text_lossy);
+        let slice = unsafe { std::slice::from_raw_parts(line as *const u8, len as usize) };
+        return unsafe { deliver_oversized(orig, this, slice) };
+    }
 
-            final_line = trimmed_line.as_ptr() as *const std::ffi::c_char;
-            final_len = (trimmed_line.len() - 1) as u32;
-        }
-    } else if !line.is_null() {
+    if !line.is_null() {
         unsafe {
             let cstr = std::ffi::CStr::from_ptr(line);
             if let Ok(text) = cstr.to_str() {
                 log::info!("{}", text);
+                message::publish(Source::Channel, text);
             }
         }
     }
 
-    unsafe {
-        if let Some(orig) = TRAMPOLINE {
-            orig(this, final_line, final_len)
-        } else {
-            1
+    unsafe { orig(this, line, len) }
+}
+
+/// Hands an over-long line to the OCX as lines it can take. PRIVMSG, NOTICE and WHISPER text
+/// is split by [`split::split_inbound`], or replaced by a short notice when it cannot be;
+/// only other lines are cut at [`split::MAX_LINE_LEN`] without breaking a UTF-8 character.
+unsafe fn deliver_oversized(orig: OnLineReceivedFn, this: *mut c_void, slice: &[u8]) -> i8 {
+    let ends_with_crlf = slice.ends_with(b"\r\n");
+    let payload = if ends_with_crlf {
+        &slice[..slice.len() - 2]
+    } else {
+        slice
+    };
+
+    let lines = match std::str::from_utf8(payload)
+        .ok()
+        .and_then(split::split_inbound)
+    {
+        Some(lines) => {
+            log::warn!(
+                "Received line over 510 bytes (length: {}), passing it on as {} line(s)",
+                slice.len(),
+                lines.len()
+            );
+            lines.into_iter().map(String::into_bytes).collect()
+        }
+        None => {
+            let mut cut = split::MAX_LINE_LEN;
+            while cut > 0 && (payload[cut] & 0xC0) == 0x80 {
+                cut -= 1;
+            }
+            let trimmed = &payload[..cut];
+            log::warn!(
+                "Received line over 510 bytes (length: {}), trimming to {} bytes. Preview: {}",
+                slice.len(),
+                cut,
+                String::from_utf8_lossy(trimmed)
+            );
+            vec![trimmed.to_vec()]
+        }
+    };
+
+    let mut result = 1;
+    for mut line in lines {
+        message::publish(Source::Channel, &String::from_utf8_lossy(&line));
+        if ends_with_crlf {
+            line.extend_from_slice(b"\r\n");
+        }
+        line.push(0);
+        result = unsafe {
+            orig(
+                this,
+                line.as_ptr() as *const std::ffi::c_char,
+                (line.len() - 1) as u32,
+            )
+        };
+        if result == 0 {
+            break;
         }
     }
+    result
 }
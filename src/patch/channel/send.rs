@@ -1,4 +1,5 @@
 use crate::patch::module_info::ModuleInfo;
+use crate::protocol::{ChannelCommand, cstring_arg, middleware, to_cstrings};
 use std::ffi::c_void;
 use windows::Win32::System::Threading::CRITICAL_SECTION;
 use windows::core::PCSTR;
@@ -52,224 +53,64 @@ unsafe extern "thiscall" fn hook_sub_37230eb3(
     let p_a7 = unsafe { pcstr_to_opt(a7) };
     let p_a8 = unsafe { pcstr_to_opt(a8) };
 
-    let build_command = || -> Option<String> {
-        let cmd = match a2 as usize {
-            0 => {
-                // ACCESS ADD
-                let lp = p_lp?;
-                let a5 = p_a5?;
-                let a6 = p_a6?;
-                let mut s = format!("ACCESS {} ADD {} {}", lp, a5, a6);
-                if let Some(a7) = p_a7 {
-                    s.push_str(&format!(" {}", a7));
-                    if let Some(a8) = p_a8 {
-                        s.push_str(&format!(" :{}", a8));
-                    }
-                }
-                s
-            }
-            1 => {
-                // ACCESS DELETE
-                let lp = p_lp?;
-                let a5 = p_a5?;
-                let a6 = p_a6?;
-                let mut s = format!("ACCESS {} DELETE {} {}", lp, a5, a6);
-                if let Some(a7) = p_a7 {
-                    s.push_str(&format!(" {}", a7));
-                    if let Some(a8) = p_a8 {
-                        s.push_str(&format!(" :{}", a8));
-                    }
-                }
-                s
-            }
-            2 => format!("ACCESS {} CLEAR", p_lp?),
-            3 => format!("ACCESS {} LIST", p_lp?),
-            4 => {
-                // AUTH
-                let lp = p_lp?;
-                let a5 = p_a5?;
-                let mut s = format!("AUTH {} {}", lp, a5);
-                if let Some(a6) = p_a6 {
-                    s.push_str(&format!(" {}", a6));
-                }
-                s
-            }
-            5 => {
-                // AWAY
-                if let Some(lp) = p_lp {
-                    format!("AWAY :{}", lp)
-                } else {
-                    "AWAY".to_string()
-                }
-            }
-            6 => format!("DATA {} {} :{}", p_lp?, p_a5?, p_a6?),
-            7 => {
-                // EVENT ADD
-                let lp = p_lp?;
-                if let Some(a5) = p_a5 {
-                    format!("EVENT ADD {} {}", lp, a5)
-                } else {
-                    format!("EVENT ADD {}", lp)
-                }
-            }
-            8 => {
-                // EVENT DELETE
-                let lp = p_lp?;
-                if let Some(a5) = p_a5 {
-                    format!("EVENT DELETE {} {}", lp, a5)
-                } else {
-                    format!("EVENT DELETE {}", lp)
-                }
-            }
-            9 => format!("EVENT LIST {}", p_lp?),
-            10 => format!("EPRIVMSG {} :{}", p_lp?, p_a5?),
-            11 => format!("EQUESTION {} {} {} :{}", p_lp?, p_a5?, p_a6?, p_a7?),
-            12 => format!("ESUBMIT {} :{}", p_lp?, p_a5?),
-            13 => format!("GOTO {} :{}", p_lp?, p_a5?),
-            14 => "INFO".to_string(),
-            15 => format!("INVITE {}", p_lp?),
-            16 => {
-                // IRCVERS
-                let lp = p_lp?;
-                let mut s = format!("IRCVERS {}", lp);
-                if let Some(a5) = p_a5 {
-                    s.push_str(&format!(" {}", a5));
-                    if let Some(a6) = p_a6 {
-                        s.push_str(&format!(" {}", a6));
-                        if let Some(a7) = p_a7 {
-                            s.push_str(&format!(" :{}", a7));
-                        }
-                    }
-                }
-                s
-            }
-            17 => {
-                // JOIN
-                let lp = p_lp?;
-                if let Some(a5) = p_a5 {
-                    format!("JOIN {} {}", lp, a5)
-                } else {
-                    format!("JOIN {}", lp)
-                }
-            }
-            18 => {
-                // KICK
-                let lp = p_lp?;
-                let a5 = p_a5?;
-                if let Some(a6) = p_a6 {
-                    format!("KICK {} {} :{}", lp, a5, a6)
-                } else {
-                    format!("KICK {} {}", lp, a5)
-                }
-            }
-            19 => format!("KILL {} :{}", p_lp?, p_a5?),
-            20 => "LINKS".to_string(),
-            21 => format!("LIST {}", p_lp?),
-            22 => format!("LISTX {}", p_lp?),
-            23 => "LUSERS".to_string(),
-            24 => format!("MESSAGE {} :{}", p_lp?, p_a5?),
-            25 => {
-                // MODE
-                let lp = p_lp?;
-                match (p_a5, p_a6) {
-                    (Some(a5), Some(a6)) => format!("MODE {} {} {}", lp, a5, a6),
-                    (Some(a5), None) => format!("MODE {} {}", lp, a5),
-                    _ => format!("MODE {}", lp),
-                }
-            }
-            26 => "MOTD".to_string(),
-            27 => format!("NAMES {}", p_lp?),
-            28 => format!("NICK {}", p_lp?),
-            29 => format!("NOTICE {} :{}", p_lp?, p_a5?),
-            30 => format!("OPER {} :{}", p_lp?, p_a5?),
-            31 => format!("PART {} :{}", p_lp?, p_a5?),
-            32 => format!("PASS {}", p_lp?),
-            33 => format!("PING {}", p_lp?),
-            34 => "PONG ".to_string(),
-            35 => format!("PRIVMSG {} :{}", p_lp?, p_a5?),
-            36 => {
-                // PROP
-                let lp = p_lp?;
-                let a5 = p_a5?;
-                let mut s = format!("PROP {} {}", lp, a5);
-                if let Some(a6) = p_a6 {
-                    s.push_str(&format!(" :{}", a6));
-                }
-                s
-            }
-            37 => {
-                // QUIT
-                if let Some(lp) = p_lp {
-                    format!("QUIT :{}", lp)
-                } else {
-                    "QUIT".to_string()
-                }
-            }
-            38 => format!("REPLY {} {} :{}", p_lp?, p_a5?, p_a6?),
-            39 => format!("REQUEST {} {} :{}", p_lp?, p_a5?, p_a6?),
-            40 => format!("SILENCE {}", p_lp?),
-            41 => "TIME".to_string(),
-            42 => {
-                // TOPIC
-                let lp = p_lp?;
-                if let Some(a5) = p_a5 {
-                    format!("TOPIC {} {}", lp, a5)
-                } else {
-                    format!("TOPIC {}", lp)
-                }
-            }
-            43 => format!("USER {} {} {} :{}", p_lp?, p_a5?, p_a6?, p_a7?),
-            44 => format!("USERHOST {}", p_lp?),
-            45 => "VERSION".to_string(),
-            46 => format!("WALLOPS {}", p_lp?),
-            47 => format!("WALLUSERS {}", p_lp?),
-            48 => format!("WHISPER {} {} :{}", p_lp?, p_a5?, p_a6?),
-            49 => {
-                // WHO
-                let lp = p_lp?;
-                if let Some(a5) = p_a5 {
-                    format!("WHO {} {}", lp, a5)
-                } else {
-                    format!("WHO {}", lp)
-                }
-            }
-            50 => {
-                // WHOIS
-                let lp = p_lp?;
-                if let Some(a5) = p_a5 {
-                    format!("WHOIS {} {}", lp, a5)
-                } else {
-                    format!("WHOIS {}", lp)
-                }
-            }
-            _ => return None,
-        };
-        Some(cmd)
+    let Some(orig) = (unsafe { TRAMPOLINE }) else {
+        return false;
+    };
+    let forward_original = || unsafe {
+        orig(
+            this,
+            a2,
+            lp_critical_section,
+            lp_string,
+            a5,
+            a6,
+            a7,
+            a8,
+            a9,
+            a10,
+            a11,
+        )
     };
 
-    if let Some(cmd_string) = build_command() {
-        log::info!("{}", cmd_string);
-    }
+    let Some(command) = ChannelCommand::from_ocx(a2 as usize, &[p_lp, p_a5, p_a6, p_a7, p_a8])
+    else {
+        return forward_original();
+    };
 
-    if let Some(orig) = unsafe { TRAMPOLINE } {
+    // Blocked commands report success so the OCX carries on as if they were sent.
+    middleware::send_all(middleware::run_channel(command.clone()), |out| {
+        log::info!("{}", out);
+        if out == command {
+            return forward_original();
+        }
+        let args = match to_cstrings(&out.ocx_args()) {
+            Ok(args) => args,
+            Err(e) => {
+                log::error!(
+                    "Not sending {:?}: argument has a NUL byte at {}",
+                    out,
+                    e.nul_position()
+                );
+                return false;
+            }
+        };
+        let arg = |i: usize| PCSTR(cstring_arg(&args, i));
         unsafe {
             orig(
                 this,
-                a2,
+                out.id() as *mut c_void,
                 lp_critical_section,
-                lp_string,
-                a5,
-                a6,
-                a7,
-                a8,
+                arg(0),
+                arg(1),
+                arg(2),
+                arg(3),
+                arg(4),
                 a9,
                 a10,
                 a11,
             )
         }
-    } else {
-        false
-    }
+    })
 }
 
 unsafe fn pcstr_to_opt<'a>(p: PCSTR) -> Option<&'a str> {
@@ -1,8 +1,18 @@
 use super::module_info::ModuleInfo;
 use std::ffi::c_void;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use windows::Win32::Foundation::HWND;
+use windows::Win32::UI::Input::KeyboardAndMouse::GetFocus;
+use windows::Win32::UI::WindowsAndMessaging::IsWindow;
 
 static mut TRAMPOLINE: Option<FnProcessCommand> = None;
 
+/// Chat control the user last typed into; target for messages raised outside the UI.
+static ACTIVE_CHAT: AtomicPtr<c_void> = AtomicPtr::new(std::ptr::null_mut());
+/// The window that had focus when the user typed into `ACTIVE_CHAT` (its edit box). It is
+/// destroyed together with the control, so `IsWindow` tells whether the control is gone.
+static ACTIVE_CHAT_WINDOW: AtomicPtr<c_void> = AtomicPtr::new(std::ptr::null_mut());
+
 type FnProcessCommand =
     unsafe extern "thiscall" fn(this: *mut c_void, lp_wide_char_str: *const u16, a3: *mut u8) -> i8;
 
@@ -59,6 +69,16 @@ unsafe extern "thiscall" fn detour_process_command(
     lp_wide_char_str: *const u16,
     a3: *mut u8,
 ) -> i8 {
+    // Without a window to check, the control is not remembered at all.
+    let focus = unsafe { GetFocus() };
+    let active = if focus.is_invalid() {
+        std::ptr::null_mut()
+    } else {
+        this
+    };
+    ACTIVE_CHAT.store(active, Ordering::Release);
+    ACTIVE_CHAT_WINDOW.store(focus.0, Ordering::Release);
+
     // 1. Convert command string to Rust String
     let mut len = 0;
     while unsafe { *lp_wide_char_str.add(len) } != 0 {
@@ -97,11 +117,44 @@ unsafe extern "thiscall" fn detour_process_command(
             }
         }
         return 0; // Handled, clears the editbox
+    } else if full_cmd == "/netstat" {
+        let sockets = crate::network::all_socket_stats();
+        unsafe {
+            if sockets.is_empty() {
+                append_system_message(this, "No open connections.");
+            }
+            for snapshot in sockets {
+                append_system_message(this, &snapshot.to_string());
+            }
+        }
+        return 0; // Handled, clears the editbox
+    } else if full_cmd == "/queue" || full_cmd == "/queue clear" {
+        unsafe {
+            if full_cmd == "/queue clear" {
+                let count = crate::network::outbox::clear();
+                append_system_message(this, &format!("Discarded {} queued message(s).", count));
+            } else {
+                let items = crate::network::outbox::items();
+                if items.is_empty() {
+                    append_system_message(this, "No messages are queued.");
+                }
+                for item in items {
+                    append_system_message(
+                        this,
+                        &format!("[{}s ago] {}", item.age_secs(), item.line),
+                    );
+                }
+            }
+        }
+        return 0; // Handled, clears the editbox
+    } else if full_cmd == "/replay" {
+        crate::network::replay::step();
+        return 0; // Handled, clears the editbox
     } else if full_cmd == "/help" {
         unsafe {
             append_system_message(
                 this,
-                "Available commands: /nick, /topic, /me, /away, /clear, /credits, /version, /quit, /part, /help",
+                "Available commands: /nick, /topic, /me, /away, /clear, /credits, /version, /quit, /part, /netstat, /queue, /replay, /help",
             );
         }
         return 0; // Handled, clears the editbox
@@ -115,7 +168,25 @@ unsafe extern "thiscall" fn detour_process_command(
     }
 }
 
-unsafe fn append_system_message(this: *mut c_void, text: &str) {
+/// Shows `text` as a system line in the active chat window, or only logs it if the user
+/// has not interacted with a chat window yet or that window has since been destroyed.
+///
+/// Must be called on the UI thread, which is where chat windows are destroyed.
+pub fn notify_user(text: &str) {
+    log::info!("{}", text);
+    let chat = ACTIVE_CHAT.load(Ordering::Acquire);
+    if chat.is_null() {
+        return;
+    }
+    let window = HWND(ACTIVE_CHAT_WINDOW.load(Ordering::Acquire));
+    if !unsafe { IsWindow(Some(window)) }.as_bool() {
+        ACTIVE_CHAT.store(std::ptr::null_mut(), Ordering::Release);
+        return;
+    }
+    unsafe { append_system_message(chat, text) };
+}
+
+pub unsafe fn append_system_message(this: *mut c_void, text: &str) {
     if let Some(append_fn) = unsafe { FN_APPEND_TEXT } {
         let chat_output = unsafe { (this as *mut u8).add(18400) as *mut c_void };
 
@@ -1,4 +1,5 @@
 use super::super::module_info::ModuleInfo;
+use crate::protocol::message::{self, Source};
 use std::ffi::c_void;
 
 type OnLineReceivedFn =
@@ -34,6 +35,7 @@ unsafe extern "thiscall" fn hook_on_line_received_ds(
             let cstr = std::ffi::CStr::from_ptr(line);
             if let Ok(text) = cstr.to_str() {
                 log::info!("{}", text);
+                message::publish(Source::Directory, text);
             }
         }
     }
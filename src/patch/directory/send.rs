@@ -1,4 +1,5 @@
 use super::super::module_info::ModuleInfo;
+use crate::protocol::{DirectoryCommand, cstring_arg, middleware, to_cstrings};
 use std::ffi::c_void;
 use windows::Win32::System::Threading::CRITICAL_SECTION;
 use windows::core::PCSTR;
@@ -58,132 +59,66 @@ unsafe extern "thiscall" fn hook_sub_372321ae(
     let p_a11 = unsafe { pcstr_to_opt(a11) };
     let p_a12 = unsafe { pcstr_to_opt(a12) };
 
-    let build_command = || -> Option<String> {
-        let cmd = match a2 as usize {
-            0 => {
-                // AUTH
-                let lp = p_lp?;
-                let a5 = p_a5?;
-                if let Some(a6) = p_a6 {
-                    format!("AUTH {} {} {}", lp, a5, a6)
-                } else {
-                    format!("AUTH {} {}", lp, a5)
-                }
-            }
-            1 => {
-                // CREATE
-                let lp = p_lp?;
-                let a5 = p_a5?;
-                let a6 = p_a6?;
-                let a7 = p_a7?;
-                let a8 = p_a8?;
-                let a9 = p_a9?;
-                let a10 = p_a10?;
-                let a11 = p_a11?;
-                if let Some(a12) = p_a12 {
-                    format!(
-                        "CREATE {} {} {} {} {} {} {} {} {}",
-                        lp, a5, a6, a7, a8, a9, a10, a11, a12
-                    )
-                } else {
-                    format!(
-                        "CREATE {} {} {} {} {} {} {} {}",
-                        lp, a5, a6, a7, a8, a9, a10, a11
-                    )
-                }
-            }
-            2 => "CREDITS".to_string(),
-            3 => format!("FINDS {}", p_lp?),
-            4 => format!("FINDU {}", p_lp?),
-            5 => {
-                // IRCVERS
-                let lp = p_lp?;
-                if let Some(a5) = p_a5 {
-                    format!("IRCVERS {} {}", lp, a5)
-                } else {
-                    format!("IRCVERS {}", lp)
-                }
-            }
-            6 => "LINKSX".to_string(),
-            7 => {
-                // LIST
-                let lp = p_lp?;
-                if let Some(a5) = p_a5 {
-                    format!("LIST {} {}", lp, a5)
-                } else {
-                    format!("LIST {}", lp)
-                }
-            }
-            8 => "LISTC".to_string(),
-            9 => format!("LISTR {}", p_lp?),
-            10 => "LISTU".to_string(),
-            11 => {
-                // LISTX
-                let lp = p_lp?;
-                if let Some(a5) = p_a5 {
-                    format!("LISTX {} {}", lp, a5)
-                } else {
-                    format!("LISTX {}", lp)
-                }
-            }
-            12 => {
-                // LISTZ
-                let lp = p_lp?;
-                if let Some(a5) = p_a5 {
-                    format!("LISTZ {} {}", lp, a5)
-                } else {
-                    format!("LISTZ {}", lp)
-                }
-            }
-            14 => format!("NICK {}", p_lp?),
-            15 => format!("MOVE {} {}", p_lp?, p_a5?),
-            16 => format!("PASS {}", p_lp?),
-            17 => "STATS".to_string(),
-            18 => "STATSD".to_string(),
-            19 => "STATSG".to_string(),
-            20 => "STATSGD".to_string(),
-            21 => "UPTIME".to_string(),
-            22 => format!("USER {} {} {} {}", p_lp?, p_a5?, p_a6?, p_a7?),
-            23 => "VERSION".to_string(),
-            24 => {
-                // PROP
-                let lp = p_lp?;
-                let a5 = p_a5?;
-                if let Some(a6) = p_a6 {
-                    format!("PROP {} {} :{}", lp, a5, a6)
-                } else {
-                    format!("PROP {} {}", lp, a5)
-                }
-            }
-            _ => return None,
-        };
-        Some(cmd)
+    let Some(orig) = (unsafe { TRAMPOLINE }) else {
+        return false;
+    };
+    let forward_original = || unsafe {
+        orig(
+            this,
+            a2,
+            lp_critical_section,
+            lp_string,
+            a5,
+            a6,
+            a7,
+            a8,
+            a9,
+            a10,
+            a11,
+            a12,
+        )
     };
 
-    if let Some(cmd_string) = build_command() {
-        log::info!("{}", cmd_string);
-    }
+    let args = [p_lp, p_a5, p_a6, p_a7, p_a8, p_a9, p_a10, p_a11, p_a12];
+    let Some(command) = DirectoryCommand::from_ocx(a2 as usize, &args) else {
+        return forward_original();
+    };
 
-    if let Some(orig) = unsafe { TRAMPOLINE } {
+    // Blocked commands report success so the OCX carries on as if they were sent.
+    middleware::send_all(middleware::run_directory(command.clone()), |out| {
+        log::info!("{}", out);
+        if out == command {
+            return forward_original();
+        }
+        let args = match to_cstrings(&out.ocx_args()) {
+            Ok(args) => args,
+            Err(e) => {
+                log::error!(
+                    "Not sending {:?}: argument has a NUL byte at {}",
+                    out,
+                    e.nul_position()
+                );
+                return false;
+            }
+        };
+        let arg = |i: usize| PCSTR(cstring_arg(&args, i));
         unsafe {
             orig(
                 this,
-                a2,
+                out.id() as *mut c_void,
                 lp_critical_section,
-                lp_string,
-                a5,
-                a6,
-                a7,
-                a8,
-                a9,
-                a10,
-                a11,
-                a12,
+                arg(0),
+                arg(1),
+                arg(2),
+                arg(3),
+                arg(4),
+                arg(5),
+                arg(6),
+                arg(7),
+                arg(8),
             )
         }
-    } else {
-        false
-    }
+    })
 }
 
 unsafe fn pcstr_to_opt<'a>(p: PCSTR) -> Option<&'a str> {
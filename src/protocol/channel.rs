@@ -0,0 +1,1061 @@
+//! Commands sent through the channel connection (`sub_37230eb3`).
+
+use std::fmt;
+
+use super::{OcxArgs, Params, arg};
+
+/// A command the OCX sends to a channel server. Variants are listed in OCX id order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChannelCommand {
+    AccessAdd {
+        object: String,
+        level: String,
+        mask: String,
+        timeout: Option<String>,
+        reason: Option<String>,
+    },
+    AccessDelete {
+        object: String,
+        level: String,
+        mask: String,
+        timeout: Option<String>,
+        reason: Option<String>,
+    },
+    AccessClear {
+        object: String,
+    },
+    AccessList {
+        object: String,
+    },
+    Auth {
+        mechanism: String,
+        sequence: String,
+        data: Option<String>,
+    },
+    Away {
+        message: Option<String>,
+    },
+    Data {
+        target: String,
+        tag: String,
+        message: String,
+    },
+    EventAdd {
+        event: String,
+        mask: Option<String>,
+    },
+    EventDelete {
+        event: String,
+        mask: Option<String>,
+    },
+    EventList {
+        event: String,
+    },
+    EPrivmsg {
+        target: String,
+        text: String,
+    },
+    EQuestion {
+        channel: String,
+        nick: String,
+        target: String,
+        text: String,
+    },
+    ESubmit {
+        channel: String,
+        text: String,
+    },
+    Goto {
+        target: String,
+        text: String,
+    },
+    Info,
+    Invite {
+        target: String,
+    },
+    IrcVers {
+        version: String,
+        client: Option<String>,
+        locale: Option<String>,
+        text: Option<String>,
+    },
+    Join {
+        channel: String,
+        key: Option<String>,
+    },
+    Kick {
+        channel: String,
+        nick: String,
+        reason: Option<String>,
+    },
+    Kill {
+        nick: String,
+        reason: String,
+    },
+    Links,
+    List {
+        mask: String,
+    },
+    ListX {
+        mask: String,
+    },
+    Lusers,
+    Message {
+        target: String,
+        text: String,
+    },
+    Mode {
+        target: String,
+        modes: Option<String>,
+        args: Option<String>,
+    },
+    Motd,
+    Names {
+        channel: String,
+    },
+    Nick {
+        nick: String,
+    },
+    Notice {
+        target: String,
+        text: String,
+    },
+    Oper {
+        name: String,
+        password: String,
+    },
+    Part {
+        channel: String,
+        reason: String,
+    },
+    Pass {
+        password: String,
+    },
+    Ping {
+        token: String,
+    },
+    Pong,
+    Privmsg {
+        target: String,
+        text: String,
+    },
+    Prop {
+        target: String,
+        property: String,
+        value: Option<String>,
+    },
+    Quit {
+        reason: Option<String>,
+    },
+    Reply {
+        target: String,
+        tag: String,
+        text: String,
+    },
+    Request {
+        target: String,
+        tag: String,
+        text: String,
+    },
+    Silence {
+        mask: String,
+    },
+    Time,
+    Topic {
+        channel: String,
+        topic: Option<String>,
+    },
+    User {
+        user: String,
+        mode: String,
+        unused: String,
+        realname: String,
+    },
+    Userhost {
+        nick: String,
+    },
+    Version,
+    Wallops {
+        text: String,
+    },
+    Wallusers {
+        text: String,
+    },
+    Whisper {
+        channel: String,
+        nick: String,
+        text: String,
+    },
+    Who {
+        mask: String,
+        flags: Option<String>,
+    },
+    Whois {
+        target: String,
+        nick: Option<String>,
+    },
+}
+
+impl ChannelCommand {
+    /// Builds the command for OCX id `id` from its string arguments. Returns `None` for an
+    /// unknown id or when a required argument is missing.
+    pub fn from_ocx(id: usize, args: &OcxArgs) -> Option<Self> {
+        let a = |i| arg(args, i);
+        let cmd = match id {
+            0 | 1 => {
+                let (object, level, mask) = (a(0)?, a(1)?, a(2)?);
+                let timeout = a(3);
+                let reason = timeout.as_ref().and(a(4));
+                if id == 0 {
+                    Self::AccessAdd {
+                        object,
+                        level,
+                        mask,
+                        timeout,
+                        reason,
+                    }
+                } else {
+                    Self::AccessDelete {
+                        object,
+                        level,
+                        mask,
+                        timeout,
+                        reason,
+                    }
+                }
+            }
+            2 => Self::AccessClear { object: a(0)? },
+            3 => Self::AccessList { object: a(0)? },
+            4 => Self::Auth {
+                mechanism: a(0)?,
+                sequence: a(1)?,
+                data: a(2),
+            },
+            5 => Self::Away { message: a(0) },
+            6 => Self::Data {
+                target: a(0)?,
+                tag: a(1)?,
+                message: a(2)?,
+            },
+            7 => Self::EventAdd {
+                event: a(0)?,
+                mask: a(1),
+            },
+            8 => Self::EventDelete {
+                event: a(0)?,
+                mask: a(1),
+            },
+            9 => Self::EventList { event: a(0)? },
+            10 => Self::EPrivmsg {
+                target: a(0)?,
+                text: a(1)?,
+            },
+            11 => Self::EQuestion {
+                channel: a(0)?,
+                nick: a(1)?,
+                target: a(2)?,
+                text: a(3)?,
+            },
+            12 => Self::ESubmit {
+                channel: a(0)?,
+                text: a(1)?,
+            },
+            13 => Self::Goto {
+                target: a(0)?,
+                text: a(1)?,
+            },
+            14 => Self::Info,
+            15 => Self::Invite { target: a(0)? },
+            16 => {
+                let version = a(0)?;
+                let client = a(1);
+                let locale = client.as_ref().and(a(2));
+                let text = locale.as_ref().and(a(3));
+                Self::IrcVers {
+                    version,
+                    client,
+                    locale,
+                    text,
+                }
+            }
+            17 => Self::Join {
+                channel: a(0)?,
+                key: a(1),
+            },
+            18 => Self::Kick {
+                channel: a(0)?,
+                nick: a(1)?,
+                reason: a(2),
+            },
+            19 => Self::Kill {
+                nick: a(0)?,
+                reason: a(1)?,
+            },
+            20 => Self::Links,
+            21 => Self::List { mask: a(0)? },
+            22 => Self::ListX { mask: a(0)? },
+            23 => Self::Lusers,
+            24 => Self::Message {
+                target: a(0)?,
+                text: a(1)?,
+            },
+            25 => {
+                let target = a(0)?;
+                let modes = a(1);
+                let args = modes.as_ref().and(a(2));
+                Self::Mode {
+                    target,
+                    modes,
+                    args,
+                }
+            }
+            26 => Self::Motd,
+            27 => Self::Names { channel: a(0)? },
+            28 => Self::Nick { nick: a(0)? },
+            29 => Self::Notice {
+                target: a(0)?,
+                text: a(1)?,
+            },
+            30 => Self::Oper {
+                name: a(0)?,
+                password: a(1)?,
+            },
+            31 => Self::Part {
+                channel: a(0)?,
+                reason: a(1)?,
+            },
+            32 => Self::Pass { password: a(0)? },
+            33 => Self::Ping { token: a(0)? },
+            34 => Self::Pong,
+            35 => Self::Privmsg {
+                target: a(0)?,
+                text: a(1)?,
+            },
+            36 => Self::Prop {
+                target: a(0)?,
+                property: a(1)?,
+                value: a(2),
+            },
+            37 => Self::Quit { reason: a(0) },
+            38 => Self::Reply {
+                target: a(0)?,
+                tag: a(1)?,
+                text: a(2)?,
+            },
+            39 => Self::Request {
+                target: a(0)?,
+                tag: a(1)?,
+                text: a(2)?,
+            },
+            40 => Self::Silence { mask: a(0)? },
+            41 => Self::Time,
+            42 => Self::Topic {
+                channel: a(0)?,
+                topic: a(1),
+            },
+            43 => Self::User {
+                user: a(0)?,
+                mode: a(1)?,
+                unused: a(2)?,
+                realname: a(3)?,
+            },
+            44 => Self::Userhost { nick: a(0)? },
+            45 => Self::Version,
+            46 => Self::Wallops { text: a(0)? },
+            47 => Self::Wallusers { text: a(0)? },
+            48 => Self::Whisper {
+                channel: a(0)?,
+                nick: a(1)?,
+                text: a(2)?,
+            },
+            49 => Self::Who {
+                mask: a(0)?,
+                flags: a(1),
+            },
+            50 => Self::Whois {
+                target: a(0)?,
+                nick: a(1),
+            },
+            _ => return None,
+        };
+        Some(cmd)
+    }
+
+    /// Text of a message-carrying command (EPRIVMSG, MESSAGE, NOTICE, PRIVMSG, WHISPER).
+    pub fn message_text(&self) -> Option<&str> {
+        match self {
+            Self::EPrivmsg { text, .. }
+            | Self::Message { text, .. }
+            | Self::Notice { text, .. }
+            | Self::Privmsg { text, .. }
+            | Self::Whisper { text, .. } => Some(text),
+            _ => None,
+        }
+    }
+
+    /// A copy of a message-carrying command with its text replaced. Other commands are
+    /// returned unchanged.
+    pub fn with_message_text(&self, new_text: String) -> Self {
+        let mut command = self.clone();
+        match &mut command {
+            Self::EPrivmsg { text, .. }
+            | Self::Message { text, .. }
+            | Self::Notice { text, .. }
+            | Self::Privmsg { text, .. }
+            | Self::Whisper { text, .. } => *text = new_text,
+            _ => {}
+        }
+        command
+    }
+
+    /// The string arguments the OCX passes for this command, in the order `from_ocx` reads
+    /// them.
+    pub fn ocx_args(&self) -> Vec<Option<&str>> {
+        fn s(v: &str) -> Option<&str> {
+            Some(v)
+        }
+        fn o(v: &Option<String>) -> Option<&str> {
+            v.as_deref()
+        }
+        match self {
+            Self::AccessAdd {
+                object,
+                level,
+                mask,
+                timeout,
+                reason,
+            }
+            | Self::AccessDelete {
+                object,
+                level,
+                mask,
+                timeout,
+                reason,
+            } => vec![s(object), s(level), s(mask), o(timeout), o(reason)],
+            Self::AccessClear { object } | Self::AccessList { object } => vec![s(object)],
+            Self::Auth {
+                mechanism,
+                sequence,
+                data,
+            } => vec![s(mechanism), s(sequence), o(data)],
+            Self::Away { message } => vec![o(message)],
+            Self::Data {
+                target,
+                tag,
+                message: text,
+            }
+            | Self::Reply { target, tag, text }
+            | Self::Request { target, tag, text } => vec![s(target), s(tag), s(text)],
+            Self::EventAdd { event, mask } | Self::EventDelete { event, mask } => {
+                vec![s(event), o(mask)]
+            }
+            Self::EventList { event } => vec![s(event)],
+            Self::EPrivmsg { target, text }
+            | Self::Goto { target, text }
+            | Self::Message { target, text }
+            | Self::Notice { target, text }
+            | Self::Privmsg { target, text } => vec![s(target), s(text)],
+            Self::ESubmit { channel, text } => vec![s(channel), s(text)],
+            Self::EQuestion {
+                channel,
+                nick,
+                target,
+                text,
+            } => vec![s(channel), s(nick), s(target), s(text)],
+            Self::Info
+            | Self::Links
+            | Self::Lusers
+            | Self::Motd
+            | Self::Pong
+            | Self::Time
+            | Self::Version => Vec::new(),
+            Self::Invite { target } => vec![s(target)],
+            Self::IrcVers {
+                version,
+                client,
+                locale,
+                text,
+            } => vec![s(version), o(client), o(locale), o(text)],
+            Self::Join { channel, key } => vec![s(channel), o(key)],
+            Self::Kick {
+                channel,
+                nick,
+                reason,
+            } => vec![s(channel), s(nick), o(reason)],
+            Self::Kill { nick, reason } => vec![s(nick), s(reason)],
+            Self::List { mask } | Self::ListX { mask } | Self::Silence { mask } => vec![s(mask)],
+            Self::Mode {
+                target,
+                modes,
+                args,
+            } => vec![s(target), o(modes), o(args)],
+            Self::Names { channel } => vec![s(channel)],
+            Self::Nick { nick } | Self::Userhost { nick } => vec![s(nick)],
+            Self::Oper { name, password } => vec![s(name), s(password)],
+            Self::Part { channel, reason } => vec![s(channel), s(reason)],
+            Self::Pass { password } => vec![s(password)],
+            Self::Ping { token } => vec![s(token)],
+            Self::Prop {
+                target,
+                property,
+                value,
+            } => vec![s(target), s(property), o(value)],
+            Self::Quit { reason } => vec![o(reason)],
+            Self::Topic { channel, topic } => vec![s(channel), o(topic)],
+            Self::User {
+                user,
+                mode,
+                unused,
+                realname,
+            } => vec![s(user), s(mode), s(unused), s(realname)],
+            Self::Wallops { text } | Self::Wallusers { text } => vec![s(text)],
+            Self::Whisper {
+                channel,
+                nick,
+                text,
+            } => vec![s(channel), s(nick), s(text)],
+            Self::Who { mask, flags } => vec![s(mask), o(flags)],
+            Self::Whois { target, nick } => vec![s(target), o(nick)],
+        }
+    }
+
+    /// The OCX id this command is sent with.
+    pub fn id(&self) -> usize {
+        match self {
+            Self::AccessAdd { .. } => 0,
+            Self::AccessDelete { .. } => 1,
+            Self::AccessClear { .. } => 2,
+            Self::AccessList { .. } => 3,
+            Self::Auth { .. } => 4,
+            Self::Away { .. } => 5,
+            Self::Data { .. } => 6,
+            Self::EventAdd { .. } => 7,
+            Self::EventDelete { .. } => 8,
+            Self::EventList { .. } => 9,
+            Self::EPrivmsg { .. } => 10,
+            Self::EQuestion { .. } => 11,
+            Self::ESubmit { .. } => 12,
+            Self::Goto { .. } => 13,
+            Self::Info => 14,
+            Self::Invite { .. } => 15,
+            Self::IrcVers { .. } => 16,
+            Self::Join { .. } => 17,
+            Self::Kick { .. } => 18,
+            Self::Kill { .. } => 19,
+            Self::Links => 20,
+            Self::List { .. } => 21,
+            Self::ListX { .. } => 22,
+            Self::Lusers => 23,
+            Self::Message { .. } => 24,
+            Self::Mode { .. } => 25,
+            Self::Motd => 26,
+            Self::Names { .. } => 27,
+            Self::Nick { .. } => 28,
+            Self::Notice { .. } => 29,
+            Self::Oper { .. } => 30,
+            Self::Part { .. } => 31,
+            Self::Pass { .. } => 32,
+            Self::Ping { .. } => 33,
+            Self::Pong => 34,
+            Self::Privmsg { .. } => 35,
+            Self::Prop { .. } => 36,
+            Self::Quit { .. } => 37,
+            Self::Reply { .. } => 38,
+            Self::Request { .. } => 39,
+            Self::Silence { .. } => 40,
+            Self::Time => 41,
+            Self::Topic { .. } => 42,
+            Self::User { .. } => 43,
+            Self::Userhost { .. } => 44,
+            Self::Version => 45,
+            Self::Wallops { .. } => 46,
+            Self::Wallusers { .. } => 47,
+            Self::Whisper { .. } => 48,
+            Self::Who { .. } => 49,
+            Self::Whois { .. } => 50,
+        }
+    }
+
+    /// Parses a wire line (without needing its CRLF) in the form `Display` produces.
+    pub fn parse(line: &str) -> Option<Self> {
+        let (command, mut p) = Params::split(line);
+        let cmd = match command.as_str() {
+            "ACCESS" => {
+                let object = p.word()?;
+                match p.word()?.to_ascii_uppercase().as_str() {
+                    sub @ ("ADD" | "DELETE") => {
+                        let (level, mask) = (p.word()?, p.word()?);
+                        let timeout = p.word();
+                        let reason = if timeout.is_some() {
+                            p.trailing()
+                        } else {
+                            None
+                        };
+                        if sub == "ADD" {
+                            Self::AccessAdd {
+                                object,
+                                level,
+                                mask,
+                                timeout,
+                                reason,
+                            }
+                        } else {
+                            Self::AccessDelete {
+                                object,
+                                level,
+                                mask,
+                                timeout,
+                                reason,
+                            }
+                        }
+                    }
+                    "CLEAR" => Self::AccessClear { object },
+                    "LIST" => Self::AccessList { object },
+                    _ => return None,
+                }
+            }
+            "AUTH" => Self::Auth {
+                mechanism: p.word()?,
+                sequence: p.word()?,
+                data: p.remainder(),
+            },
+            "AWAY" => Self::Away { message: p.text() },
+            "DATA" => Self::Data {
+                target: p.word()?,
+                tag: p.word()?,
+                message: p.text()?,
+            },
+            "EVENT" => match p.word()?.to_ascii_uppercase().as_str() {
+                "ADD" => Self::EventAdd {
+                    event: p.word()?,
+                    mask: p.remainder(),
+                },
+                "DELETE" => Self::EventDelete {
+                    event: p.word()?,
+                    mask: p.remainder(),
+                },
+                "LIST" => Self::EventList {
+                    event: p.remainder()?,
+                },
+                _ => return None,
+            },
+            "EPRIVMSG" => Self::EPrivmsg {
+                target: p.word()?,
+                text: p.text()?,
+            },
+            "EQUESTION" => Self::EQuestion {
+                channel: p.word()?,
+                nick: p.word()?,
+                target: p.word()?,
+                text: p.text()?,
+            },
+            "ESUBMIT" => Self::ESubmit {
+                channel: p.word()?,
+                text: p.text()?,
+            },
+            "GOTO" => Self::Goto {
+                target: p.word()?,
+                text: p.text()?,
+            },
+            "INFO" => Self::Info,
+            "INVITE" => Self::Invite {
+                target: p.remainder()?,
+            },
+            "IRCVERS" => {
+                let version = p.word()?;
+                let client = p.word();
+                let locale = if client.is_some() { p.word() } else { None };
+                let text = if locale.is_some() { p.trailing() } else { None };
+                Self::IrcVers {
+                    version,
+                    client,
+                    locale,
+                    text,
+                }
+            }
+            "JOIN" => Self::Join {
+                channel: p.word()?,
+                key: p.remainder(),
+            },
+            "KICK" => Self::Kick {
+                channel: p.word()?,
+                nick: p.word()?,
+                reason: p.trailing(),
+            },
+            "KILL" => Self::Kill {
+                nick: p.word()?,
+                reason: p.text()?,
+            },
+            "LINKS" => Self::Links,
+            "LIST" => Self::List {
+                mask: p.remainder()?,
+            },
+            "LISTX" => Self::ListX {
+                mask: p.remainder()?,
+            },
+            "LUSERS" => Self::Lusers,
+            "MESSAGE" => Self::Message {
+                target: p.word()?,
+                text: p.text()?,
+            },
+            "MODE" => {
+                let target = p.word()?;
+                let modes = p.word();
+                let args = if modes.is_some() { p.remainder() } else { None };
+                Self::Mode {
+                    target,
+                    modes,
+                    args,
+                }
+            }
+            "MOTD" => Self::Motd,
+            "NAMES" => Self::Names {
+                channel: p.remainder()?,
+            },
+            "NICK" => Self::Nick {
+                nick: p.remainder()?,
+            },
+            "NOTICE" => Self::Notice {
+                target: p.word()?,
+                text: p.text()?,
+            },
+            "OPER" => Self::Oper {
+                name: p.word()?,
+                password: p.text()?,
+            },
+            "PART" => Self::Part {
+                channel: p.word()?,
+                reason: p.text()?,
+            },
+            "PASS" => Self::Pass {
+                password: p.remainder()?,
+            },
+            "PING" => Self::Ping {
+                token: p.remainder()?,
+            },
+            "PONG" => Self::Pong,
+            "PRIVMSG" => Self::Privmsg {
+                target: p.word()?,
+                text: p.text()?,
+            },
+            "PROP" => Self::Prop {
+                target: p.word()?,
+                property: p.word()?,
+                value: p.trailing(),
+            },
+            "QUIT" => Self::Quit { reason: p.text() },
+            "REPLY" => Self::Reply {
+                target: p.word()?,
+                tag: p.word()?,
+                text: p.text()?,
+            },
+            "REQUEST" => Self::Request {
+                target: p.word()?,
+                tag: p.word()?,
+                text: p.text()?,
+            },
+            "SILENCE" => Self::Silence {
+                mask: p.remainder()?,
+            },
+            "TIME" => Self::Time,
+            "TOPIC" => Self::Topic {
+                channel: p.word()?,
+                topic: p.remainder(),
+            },
+            "USER" => Self::User {
+                user: p.word()?,
+                mode: p.word()?,
+                unused: p.word()?,
+                realname: p.text()?,
+            },
+            "USERHOST" => Self::Userhost {
+                nick: p.remainder()?,
+            },
+            "VERSION" => Self::Version,
+            "WALLOPS" => Self::Wallops {
+                text: p.remainder()?,
+            },
+            "WALLUSERS" => Self::Wallusers {
+                text: p.remainder()?,
+            },
+            "WHISPER" => Self::Whisper {
+                channel: p.word()?,
+                nick: p.word()?,
+                text: p.text()?,
+            },
+            "WHO" => Self::Who {
+                mask: p.word()?,
+                flags: p.remainder(),
+            },
+            "WHOIS" => Self::Whois {
+                target: p.word()?,
+                nick: p.remainder(),
+            },
+            _ => return None,
+        };
+        Some(cmd)
+    }
+}
+
+impl fmt::Display for ChannelCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AccessAdd {
+                object,
+                level,
+                mask,
+                timeout,
+                reason,
+            }
+            | Self::AccessDelete {
+                object,
+                level,
+                mask,
+                timeout,
+                reason,
+            } => {
+                let sub = if matches!(self, Self::AccessAdd { .. }) {
+                    "ADD"
+                } else {
+                    "DELETE"
+                };
+                write!(f, "ACCESS {} {} {} {}", object, sub, level, mask)?;
+                if let Some(timeout) = timeout {
+                    write!(f, " {}", timeout)?;
+                    if let Some(reason) = reason {
+                        write!(f, " :{}", reason)?;
+                    }
+                }
+                Ok(())
+            }
+            Self::AccessClear { object } => write!(f, "ACCESS {} CLEAR", object),
+            Self::AccessList { object } => write!(f, "ACCESS {} LIST", object),
+            Self::Auth {
+                mechanism,
+                sequence,
+                data,
+            } => {
+                write!(f, "AUTH {} {}", mechanism, sequence)?;
+                if let Some(data) = data {
+                    write!(f, " {}", data)?;
+                }
+                Ok(())
+            }
+            Self::Away { message: Some(m) } => write!(f, "AWAY :{}", m),
+            Self::Away { message: None } => f.write_str("AWAY"),
+            Self::Data {
+                target,
+                tag,
+                message,
+            } => write!(f, "DATA {} {} :{}", target, tag, message),
+            Self::EventAdd { event, mask } => {
+                write!(f, "EVENT ADD {}", event)?;
+                if let Some(mask) = mask {
+                    write!(f, " {}", mask)?;
+                }
+                Ok(())
+            }
+            Self::EventDelete { event, mask } => {
+                write!(f, "EVENT DELETE {}", event)?;
+                if let Some(mask) = mask {
+                    write!(f, " {}", mask)?;
+                }
+                Ok(())
+            }
+            Self::EventList { event } => write!(f, "EVENT LIST {}", event),
+            Self::EPrivmsg { target, text } => write!(f, "EPRIVMSG {} :{}", target, text),
+            Self::EQuestion {
+                channel,
+                nick,
+                target,
+                text,
+            } => write!(f, "EQUESTION {} {} {} :{}", channel, nick, target, text),
+            Self::ESubmit { channel, text } => write!(f, "ESUBMIT {} :{}", channel, text),
+            Self::Goto { target, text } => write!(f, "GOTO {} :{}", target, text),
+            Self::Info => f.write_str("INFO"),
+            Self::Invite { target } => write!(f, "INVITE {}", target),
+            Self::IrcVers {
+                version,
+                client,
+                locale,
+                text,
+            } => {
+                write!(f, "IRCVERS {}", version)?;
+                if let Some(client) = client {
+                    write!(f, " {}", client)?;
+                    if let Some(locale) = locale {
+                        write!(f, " {}", locale)?;
+                        if let Some(text) = text {
+                            write!(f, " :{}", text)?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            Self::Join { channel, key } => {
+                write!(f, "JOIN {}", channel)?;
+                if let Some(key) = key {
+                    write!(f, " {}", key)?;
+                }
+                Ok(())
+            }
+            Self::Kick {
+                channel,
+                nick,
+                reason,
+            } => {
+                write!(f, "KICK {} {}", channel, nick)?;
+                if let Some(reason) = reason {
+                    write!(f, " :{}", reason)?;
+                }
+                Ok(())
+            }
+            Self::Kill { nick, reason } => write!(f, "KILL {} :{}", nick, reason),
+            Self::Links => f.write_str("LINKS"),
+            Self::List { mask } => write!(f, "LIST {}", mask),
+            Self::ListX { mask } => write!(f, "LISTX {}", mask),
+            Self::Lusers => f.write_str("LUSERS"),
+            Self::Message { target, text } => write!(f, "MESSAGE {} :{}", target, text),
+            Self::Mode {
+                target,
+                modes,
+                args,
+            } => {
+                write!(f, "MODE {}", target)?;
+                if let Some(modes) = modes {
+                    write!(f, " {}", modes)?;
+                    if let Some(args) = args {
+                        write!(f, " {}", args)?;
+                    }
+                }
+                Ok(())
+            }
+            Self::Motd => f.write_str("MOTD"),
+            Self::Names { channel } => write!(f, "NAMES {}", channel),
+            Self::Nick { nick } => write!(f, "NICK {}", nick),
+            Self::Notice { target, text } => write!(f, "NOTICE {} :{}", target, text),
+            Self::Oper { name, password } => write!(f, "OPER {} :{}", name, password),
+            Self::Part { channel, reason } => write!(f, "PART {} :{}", channel, reason),
+            Self::Pass { password } => write!(f, "PASS {}", password),
+            Self::Ping { token } => write!(f, "PING {}", token),
+            // The OCX sends PONG with an empty argument.
+            Self::Pong => f.write_str("PONG "),
+            Self::Privmsg { target, text } => write!(f, "PRIVMSG {} :{}", target, text),
+            Self::Prop {
+                target,
+                property,
+                value,
+            } => {
+                write!(f, "PROP {} {}", target, property)?;
+                if let Some(value) = value {
+                    write!(f, " :{}", value)?;
+                }
+                Ok(())
+            }
+            Self::Quit { reason: Some(r) } => write!(f, "QUIT :{}", r),
+            Self::Quit { reason: None } => f.write_str("QUIT"),
+            Self::Reply { target, tag, text } => write!(f, "REPLY {} {} :{}", target, tag, text),
+            Self::Request { target, tag, text } => {
+                write!(f, "REQUEST {} {} :{}", target, tag, text)
+            }
+            Self::Silence { mask } => write!(f, "SILENCE {}", mask),
+            Self::Time => f.write_str("TIME"),
+            Self::Topic { channel, topic } => {
+                write!(f, "TOPIC {}", channel)?;
+                if let Some(topic) = topic {
+                    write!(f, " {}", topic)?;
+                }
+                Ok(())
+            }
+            Self::User {
+                user,
+                mode,
+                unused,
+                realname,
+            } => write!(f, "USER {} {} {} :{}", user, mode, unused, realname),
+            Self::Userhost { nick } => write!(f, "USERHOST {}", nick),
+            Self::Version => f.write_str("VERSION"),
+            Self::Wallops { text } => write!(f, "WALLOPS {}", text),
+            Self::Wallusers { text } => write!(f, "WALLUSERS {}", text),
+            Self::Whisper {
+                channel,
+                nick,
+                text,
+            } => write!(f, "WHISPER {} {} :{}", channel, nick, text),
+            Self::Who { mask, flags } => {
+                write!(f, "WHO {}", mask)?;
+                if let Some(flags) = flags {
+                    write!(f, " {}", flags)?;
+                }
+                Ok(())
+            }
+            Self::Whois { target, nick } => {
+                write!(f, "WHOIS {}", target)?;
+                if let Some(nick) = nick {
+                    write!(f, " {}", nick)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::to_cstrings;
+    use std::collections::HashSet;
+    use std::mem::discriminant;
+
+    /// Highest channel command id.
+    const LAST_ID: usize = 50;
+    const ARGS: [&str; 5] = ["#room", "nick", "+o", "text", "extra"];
+
+    /// Every command that `from_ocx` builds for `id` from the first `n` arguments, for each
+    /// `n`, so that optional arguments are covered both present and absent.
+    fn commands(id: usize) -> Vec<ChannelCommand> {
+        (0..=ARGS.len())
+            .filter_map(|n| {
+                let args: Vec<Option<&str>> = (0..ARGS.len())
+                    .map(|i| (i < n).then_some(ARGS[i]))
+                    .collect();
+                ChannelCommand::from_ocx(id, &args)
+            })
+            .collect()
+    }
+
+    fn round_trip(command: &ChannelCommand) -> Option<ChannelCommand> {
+        let args = to_cstrings(&command.ocx_args()).unwrap();
+        let args: Vec<Option<&str>> = args
+            .iter()
+            .map(|a| a.as_ref().map(|a| a.to_str().unwrap()))
+            .collect();
+        ChannelCommand::from_ocx(command.id(), &args)
+    }
+
+    #[test]
+    fn every_variant_round_trips_through_ocx_args() {
+        let mut variants = HashSet::new();
+        for id in 0..=LAST_ID {
+            let commands = commands(id);
+            assert!(!commands.is_empty(), "id {} builds no command", id);
+            for command in commands {
+                assert_eq!(command.id(), id, "{:?}", command);
+                assert_eq!(round_trip(&command).as_ref(), Some(&command));
+                variants.insert(discriminant(&command));
+            }
+        }
+        assert_eq!(variants.len(), LAST_ID + 1);
+        assert!(commands(LAST_ID + 1).is_empty());
+    }
+
+    #[test]
+    fn every_variant_round_trips_through_the_wire_form() {
+        for id in 0..=LAST_ID {
+            for command in commands(id) {
+                let line = command.to_string();
+                assert_eq!(ChannelCommand::parse(&line), Some(command), "{:?}", line);
+            }
+        }
+    }
+}
@@ -0,0 +1,432 @@
+//! Commands sent through the directory connection (`sub_372321ae`).
+
+use std::fmt;
+
+use super::{OcxArgs, Params, arg};
+
+/// A command the OCX sends to a directory server. Variants are listed in OCX id order.
+///
+/// There are 24 commands for 25 ids: the jump table in `sub_372321ae` has slots 0-24, but
+/// slot 13 points at the same default branch as an out-of-range id, which sends nothing.
+/// Id 13 therefore has no variant and `from_ocx` returns `None` for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DirectoryCommand {
+    Auth {
+        mechanism: String,
+        sequence: String,
+        data: Option<String>,
+    },
+    /// `CREATE` takes eight fixed parameters and an optional ninth; they are kept as sent.
+    Create {
+        params: Vec<String>,
+    },
+    Credits,
+    FindS {
+        channel: String,
+    },
+    FindU {
+        nick: String,
+    },
+    IrcVers {
+        version: String,
+        client: Option<String>,
+    },
+    LinksX,
+    List {
+        mask: String,
+        filter: Option<String>,
+    },
+    ListC,
+    ListR {
+        mask: String,
+    },
+    ListU,
+    ListX {
+        mask: String,
+        filter: Option<String>,
+    },
+    ListZ {
+        mask: String,
+        filter: Option<String>,
+    },
+    Nick {
+        nick: String,
+    },
+    Move {
+        channel: String,
+        target: String,
+    },
+    Pass {
+        password: String,
+    },
+    Stats,
+    StatsD,
+    StatsG,
+    StatsGD,
+    Uptime,
+    User {
+        user: String,
+        mode: String,
+        unused: String,
+        realname: String,
+    },
+    Version,
+    Prop {
+        target: String,
+        property: String,
+        value: Option<String>,
+    },
+}
+
+/// Parameters `CREATE` requires before its optional last one.
+const CREATE_REQUIRED: usize = 8;
+
+impl DirectoryCommand {
+    /// Builds the command for OCX id `id` from its string arguments. Returns `None` for an
+    /// unknown id or when a required argument is missing.
+    pub fn from_ocx(id: usize, args: &OcxArgs) -> Option<Self> {
+        let a = |i| arg(args, i);
+        let cmd = match id {
+            0 => Self::Auth {
+                mechanism: a(0)?,
+                sequence: a(1)?,
+                data: a(2),
+            },
+            1 => {
+                let mut params = (0..CREATE_REQUIRED)
+                    .map(a)
+                    .collect::<Option<Vec<String>>>()?;
+                params.extend(a(CREATE_REQUIRED));
+                Self::Create { params }
+            }
+            2 => Self::Credits,
+            3 => Self::FindS { channel: a(0)? },
+            4 => Self::FindU { nick: a(0)? },
+            5 => Self::IrcVers {
+                version: a(0)?,
+                client: a(1),
+            },
+            6 => Self::LinksX,
+            7 => Self::List {
+                mask: a(0)?,
+                filter: a(1),
+            },
+            8 => Self::ListC,
+            9 => Self::ListR { mask: a(0)? },
+            10 => Self::ListU,
+            11 => Self::ListX {
+                mask: a(0)?,
+                filter: a(1),
+            },
+            12 => Self::ListZ {
+                mask: a(0)?,
+                filter: a(1),
+            },
+            14 => Self::Nick { nick: a(0)? },
+            15 => Self::Move {
+                channel: a(0)?,
+                target: a(1)?,
+            },
+            16 => Self::Pass { password: a(0)? },
+            17 => Self::Stats,
+            18 => Self::StatsD,
+            19 => Self::StatsG,
+            20 => Self::StatsGD,
+            21 => Self::Uptime,
+            22 => Self::User {
+                user: a(0)?,
+                mode: a(1)?,
+                unused: a(2)?,
+                realname: a(3)?,
+            },
+            23 => Self::Version,
+            24 => Self::Prop {
+                target: a(0)?,
+                property: a(1)?,
+                value: a(2),
+            },
+            _ => return None,
+        };
+        Some(cmd)
+    }
+
+    /// The string arguments the OCX passes for this command, in the order `from_ocx` reads
+    /// them.
+    pub fn ocx_args(&self) -> Vec<Option<&str>> {
+        fn s(v: &str) -> Option<&str> {
+            Some(v)
+        }
+        fn o(v: &Option<String>) -> Option<&str> {
+            v.as_deref()
+        }
+        match self {
+            Self::Auth {
+                mechanism,
+                sequence,
+                data,
+            } => vec![s(mechanism), s(sequence), o(data)],
+            Self::Create { params } => params.iter().map(|p| s(p)).collect(),
+            Self::Credits
+            | Self::LinksX
+            | Self::ListC
+            | Self::ListU
+            | Self::Stats
+            | Self::StatsD
+            | Self::StatsG
+            | Self::StatsGD
+            | Self::Uptime
+            | Self::Version => Vec::new(),
+            Self::FindS { channel } => vec![s(channel)],
+            Self::FindU { nick } | Self::Nick { nick } => vec![s(nick)],
+            Self::IrcVers { version, client } => vec![s(version), o(client)],
+            Self::List { mask, filter }
+            | Self::ListX { mask, filter }
+            | Self::ListZ { mask, filter } => vec![s(mask), o(filter)],
+            Self::ListR { mask } => vec![s(mask)],
+            Self::Move { channel, target } => vec![s(channel), s(target)],
+            Self::Pass { password } => vec![s(password)],
+            Self::User {
+                user,
+                mode,
+                unused,
+                realname,
+            } => vec![s(user), s(mode), s(unused), s(realname)],
+            Self::Prop {
+                target,
+                property,
+                value,
+            } => vec![s(target), s(property), o(value)],
+        }
+    }
+
+    /// The OCX id this command is sent with.
+    pub fn id(&self) -> usize {
+        match self {
+            Self::Auth { .. } => 0,
+            Self::Create { .. } => 1,
+            Self::Credits => 2,
+            Self::FindS { .. } => 3,
+            Self::FindU { .. } => 4,
+            Self::IrcVers { .. } => 5,
+            Self::LinksX => 6,
+            Self::List { .. } => 7,
+            Self::ListC => 8,
+            Self::ListR { .. } => 9,
+            Self::ListU => 10,
+            Self::ListX { .. } => 11,
+            Self::ListZ { .. } => 12,
+            Self::Nick { .. } => 14,
+            Self::Move { .. } => 15,
+            Self::Pass { .. } => 16,
+            Self::Stats => 17,
+            Self::StatsD => 18,
+            Self::StatsG => 19,
+            Self::StatsGD => 20,
+            Self::Uptime => 21,
+            Self::User { .. } => 22,
+            Self::Version => 23,
+            Self::Prop { .. } => 24,
+        }
+    }
+
+    /// Parses a wire line (without needing its CRLF) in the form `Display` produces.
+    pub fn parse(line: &str) -> Option<Self> {
+        let (command, mut p) = Params::split(line);
+        let cmd = match command.as_str() {
+            "AUTH" => Self::Auth {
+                mechanism: p.word()?,
+                sequence: p.word()?,
+                data: p.remainder(),
+            },
+            "CREATE" => {
+                let mut params = (0..CREATE_REQUIRED)
+                    .map(|_| p.word())
+                    .collect::<Option<Vec<String>>>()?;
+                params.extend(p.remainder());
+                Self::Create { params }
+            }
+            "CREDITS" => Self::Credits,
+            "FINDS" => Self::FindS {
+                channel: p.remainder()?,
+            },
+            "FINDU" => Self::FindU {
+                nick: p.remainder()?,
+            },
+            "IRCVERS" => Self::IrcVers {
+                version: p.word()?,
+                client: p.remainder(),
+            },
+            "LINKSX" => Self::LinksX,
+            "LIST" => Self::List {
+                mask: p.word()?,
+                filter: p.remainder(),
+            },
+            "LISTC" => Self::ListC,
+            "LISTR" => Self::ListR {
+                mask: p.remainder()?,
+            },
+            "LISTU" => Self::ListU,
+            "LISTX" => Self::ListX {
+                mask: p.word()?,
+                filter: p.remainder(),
+            },
+            "LISTZ" => Self::ListZ {
+                mask: p.word()?,
+                filter: p.remainder(),
+            },
+            "NICK" => Self::Nick {
+                nick: p.remainder()?,
+            },
+            "MOVE" => Self::Move {
+                channel: p.word()?,
+                target: p.remainder()?,
+            },
+            "PASS" => Self::Pass {
+                password: p.remainder()?,
+            },
+            "STATS" => Self::Stats,
+            "STATSD" => Self::StatsD,
+            "STATSG" => Self::StatsG,
+            "STATSGD" => Self::StatsGD,
+            "UPTIME" => Self::Uptime,
+            "USER" => Self::User {
+                user: p.word()?,
+                mode: p.word()?,
+                unused: p.word()?,
+                realname: p.remainder()?,
+            },
+            "VERSION" => Self::Version,
+            "PROP" => Self::Prop {
+                target: p.word()?,
+                property: p.word()?,
+                value: p.trailing(),
+            },
+            _ => return None,
+        };
+        Some(cmd)
+    }
+}
+
+impl fmt::Display for DirectoryCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let with_optional = |f: &mut fmt::Formatter<'_>, head: String, tail: &Option<String>| {
+            f.write_str(&head)?;
+            if let Some(tail) = tail {
+                write!(f, " {}", tail)?;
+            }
+            Ok(())
+        };
+        match self {
+            Self::Auth {
+                mechanism,
+                sequence,
+                data,
+            } => with_optional(f, format!("AUTH {} {}", mechanism, sequence), data),
+            Self::Create { params } => write!(f, "CREATE {}", params.join(" ")),
+            Self::Credits => f.write_str("CREDITS"),
+            Self::FindS { channel } => write!(f, "FINDS {}", channel),
+            Self::FindU { nick } => write!(f, "FINDU {}", nick),
+            Self::IrcVers { version, client } => {
+                with_optional(f, format!("IRCVERS {}", version), client)
+            }
+            Self::LinksX => f.write_str("LINKSX"),
+            Self::List { mask, filter } => with_optional(f, format!("LIST {}", mask), filter),
+            Self::ListC => f.write_str("LISTC"),
+            Self::ListR { mask } => write!(f, "LISTR {}", mask),
+            Self::ListU => f.write_str("LISTU"),
+            Self::ListX { mask, filter } => with_optional(f, format!("LISTX {}", mask), filter),
+            Self::ListZ { mask, filter } => with_optional(f, format!("LISTZ {}", mask), filter),
+            Self::Nick { nick } => write!(f, "NICK {}", nick),
+            Self::Move { channel, target } => write!(f, "MOVE {} {}", channel, target),
+            Self::Pass { password } => write!(f, "PASS {}", password),
+            Self::Stats => f.write_str("STATS"),
+            Self::StatsD => f.write_str("STATSD"),
+            Self::StatsG => f.write_str("STATSG"),
+            Self::StatsGD => f.write_str("STATSGD"),
+            Self::Uptime => f.write_str("UPTIME"),
+            Self::User {
+                user,
+                mode,
+                unused,
+                realname,
+            } => write!(f, "USER {} {} {} {}", user, mode, unused, realname),
+            Self::Version => f.write_str("VERSION"),
+            Self::Prop {
+                target,
+                property,
+                value,
+            } => {
+                write!(f, "PROP {} {}", target, property)?;
+                if let Some(value) = value {
+                    write!(f, " :{}", value)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::to_cstrings;
+    use std::collections::HashSet;
+    use std::mem::discriminant;
+
+    /// Highest directory command id.
+    const LAST_ID: usize = 24;
+    /// The id the OCX's dispatch table leaves unused.
+    const UNUSED_ID: usize = 13;
+    const ARGS: [&str; 9] = [
+        "%#Room", "GN", "EN-US", "1", "EN-US", "1", "topic", "1", "key",
+    ];
+
+    /// Every command that `from_ocx` builds for `id` from the first `n` arguments, for each
+    /// `n`, so that optional arguments are covered both present and absent.
+    fn commands(id: usize) -> Vec<DirectoryCommand> {
+        (0..=ARGS.len())
+            .filter_map(|n| {
+                let args: Vec<Option<&str>> = (0..ARGS.len())
+                    .map(|i| (i < n).then_some(ARGS[i]))
+                    .collect();
+                DirectoryCommand::from_ocx(id, &args)
+            })
+            .collect()
+    }
+
+    fn round_trip(command: &DirectoryCommand) -> Option<DirectoryCommand> {
+        let args = to_cstrings(&command.ocx_args()).unwrap();
+        let args: Vec<Option<&str>> = args
+            .iter()
+            .map(|a| a.as_ref().map(|a| a.to_str().unwrap()))
+            .collect();
+        DirectoryCommand::from_ocx(command.id(), &args)
+    }
+
+    #[test]
+    fn every_variant_round_trips_through_ocx_args() {
+        let mut variants = HashSet::new();
+        for id in (0..=LAST_ID).filter(|&id| id != UNUSED_ID) {
+            let commands = commands(id);
+            assert!(!commands.is_empty(), "id {} builds no command", id);
+            for command in commands {
+                assert_eq!(command.id(), id, "{:?}", command);
+                assert_eq!(round_trip(&command).as_ref(), Some(&command));
+                variants.insert(discriminant(&command));
+            }
+        }
+        assert_eq!(variants.len(), LAST_ID);
+        assert!(commands(UNUSED_ID).is_empty());
+        assert!(commands(LAST_ID + 1).is_empty());
+    }
+
+    #[test]
+    fn every_variant_round_trips_through_the_wire_form() {
+        for id in (0..=LAST_ID).filter(|&id| id != UNUSED_ID) {
+            for command in commands(id) {
+                let line = command.to_string();
+                assert_eq!(DirectoryCommand::parse(&line), Some(command), "{:?}", line);
+            }
+        }
+    }
+}
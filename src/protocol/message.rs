@@ -0,0 +1,547 @@
+//! Inbound IRCX lines as typed messages, with subscribers fed from the recv hooks.
+//!
+//! A line is split into its optional prefix (`nick!user@host` or a server name), the
+//! command or three-digit numeric, the middle parameters and the trailing parameter. IRCX
+//! commands (WHISPER, PROP, DATA, ACCESS, EVENT, ...) get their own [`Command`] variants and
+//! the IRCX 8xx replies and 9xx errors are named by [`numeric_name`].
+
+use std::fmt;
+use std::sync::Arc;
+
+use crate::subscribers::Subscribers;
+
+/// Where a message came from: `name` is a server, or `nick!user@host` a user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prefix {
+    /// Nickname, or the server name for server prefixes.
+    pub name: String,
+    pub user: Option<String>,
+    pub host: Option<String>,
+}
+
+impl Prefix {
+    pub fn parse(prefix: &str) -> Self {
+        let (rest, host) = match prefix.split_once('@') {
+            Some((rest, host)) => (rest, Some(host.to_string())),
+            None => (prefix, None),
+        };
+        let (name, user) = match rest.split_once('!') {
+            Some((name, user)) => (name, Some(user.to_string())),
+            None => (rest, None),
+        };
+        Prefix {
+            name: name.to_string(),
+            user,
+            host,
+        }
+    }
+
+    /// True for a `nick!user@host` prefix rather than a server name.
+    pub fn is_user(&self) -> bool {
+        self.user.is_some() || self.host.is_some()
+    }
+}
+
+impl fmt::Display for Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)?;
+        if let Some(user) = &self.user {
+            write!(f, "!{}", user)?;
+        }
+        if let Some(host) = &self.host {
+            write!(f, "@{}", host)?;
+        }
+        Ok(())
+    }
+}
+
+/// The command word of a message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Access,
+    Auth,
+    Data,
+    Error,
+    Event,
+    Invite,
+    Join,
+    Kick,
+    Knock,
+    Mode,
+    Nick,
+    Notice,
+    Part,
+    Ping,
+    Pong,
+    Privmsg,
+    Prop,
+    Quit,
+    Reply,
+    Request,
+    Topic,
+    Whisper,
+    /// A three-digit numeric reply.
+    Numeric(u16),
+    /// Any other command, upper-cased.
+    Other(String),
+}
+
+impl Command {
+    pub fn parse(word: &str) -> Self {
+        if word.len() == 3
+            && word.bytes().all(|b| b.is_ascii_digit())
+            && let Ok(n) = word.parse()
+        {
+            return Command::Numeric(n);
+        }
+        match word.to_ascii_uppercase().as_str() {
+            "ACCESS" => Command::Access,
+            "AUTH" => Command::Auth,
+            "DATA" => Command::Data,
+            "ERROR" => Command::Error,
+            "EVENT" => Command::Event,
+            "INVITE" => Command::Invite,
+            "JOIN" => Command::Join,
+            "KICK" => Command::Kick,
+            "KNOCK" => Command::Knock,
+            "MODE" => Command::Mode,
+            "NICK" => Command::Nick,
+            "NOTICE" => Command::Notice,
+            "PART" => Command::Part,
+            "PING" => Command::Ping,
+            "PONG" => Command::Pong,
+            "PRIVMSG" => Command::Privmsg,
+            "PROP" => Command::Prop,
+            "QUIT" => Command::Quit,
+            "REPLY" => Command::Reply,
+            "REQUEST" => Command::Request,
+            "TOPIC" => Command::Topic,
+            "WHISPER" => Command::Whisper,
+            other => Command::Other(other.to_string()),
+        }
+    }
+
+    /// True for the IRCX 800-899 replies.
+    pub fn is_ircx_reply(&self) -> bool {
+        matches!(self, Command::Numeric(800..=899))
+    }
+
+    /// True for the IRCX 900-999 errors.
+    pub fn is_ircx_error(&self) -> bool {
+        matches!(self, Command::Numeric(900..=999))
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Command::Access => "ACCESS",
+            Command::Auth => "AUTH",
+            Command::Data => "DATA",
+            Command::Error => "ERROR",
+            Command::Event => "EVENT",
+            Command::Invite => "INVITE",
+            Command::Join => "JOIN",
+            Command::Kick => "KICK",
+            Command::Knock => "KNOCK",
+            Command::Mode => "MODE",
+            Command::Nick => "NICK",
+            Command::Notice => "NOTICE",
+            Command::Part => "PART",
+            Command::Ping => "PING",
+            Command::Pong => "PONG",
+            Command::Privmsg => "PRIVMSG",
+            Command::Prop => "PROP",
+            Command::Quit => "QUIT",
+            Command::Reply => "REPLY",
+            Command::Request => "REQUEST",
+            Command::Topic => "TOPIC",
+            Command::Whisper => "WHISPER",
+            Command::Numeric(n) => return write!(f, "{:03}", n),
+            Command::Other(other) => other,
+        };
+        f.write_str(name)
+    }
+}
+
+/// Symbolic name of an IRCX numeric (800-999), as in the IRCX draft.
+pub fn numeric_name(numeric: u16) -> Option<&'static str> {
+    let name = match numeric {
+        800 => "IRCRPL_IRCX",
+        801 => "IRCRPL_ACCESSADD",
+        802 => "IRCRPL_ACCESSDELETE",
+        803 => "IRCRPL_ACCESSSTART",
+        804 => "IRCRPL_ACCESSLIST",
+        805 => "IRCRPL_ACCESSEND",
+        806 => "IRCRPL_EVENTADD",
+        807 => "IRCRPL_EVENTDEL",
+        808 => "IRCRPL_EVENTSTART",
+        809 => "IRCRPL_EVENTLIST",
+        810 => "IRCRPL_EVENTEND",
+        811 => "IRCRPL_LISTXSTART",
+        812 => "IRCRPL_LISTXLIST",
+        813 => "IRCRPL_LISTXPICS",
+        816 => "IRCRPL_LISTXTRUNC",
+        817 => "IRCRPL_LISTXEND",
+        818 => "IRCRPL_PROPLIST",
+        819 => "IRCRPL_PROPEND",
+        820 => "IRCRPL_ACCESSCLEAR",
+        900 => "IRCERR_BADCOMMAND",
+        901 => "IRCERR_TOOMANYARGUMENTS",
+        902 => "IRCERR_BADFUNCTION",
+        903 => "IRCERR_BADLEVEL",
+        904 => "IRCERR_BADTAG",
+        905 => "IRCERR_BADPROPERTY",
+        906 => "IRCERR_BADVALUE",
+        907 => "IRCERR_RESOURCE",
+        908 => "IRCERR_SECURITY",
+        909 => "IRCERR_ALREADYAUTHENTICATED",
+        910 => "IRCERR_AUTHENTICATIONFAILED",
+        911 => "IRCERR_AUTHENTICATIONSUSPENDED",
+        912 => "IRCERR_UNKNOWNPACKAGE",
+        913 => "IRCERR_NOACCESS",
+        914 => "IRCERR_DUPACCESS",
+        915 => "IRCERR_MISACCESS",
+        916 => "IRCERR_TOOMANYACCESSES",
+        918 => "IRCERR_EVENTDUP",
+        919 => "IRCERR_EVENTMIS",
+        920 => "IRCERR_NOSUCHEVENT",
+        921 => "IRCERR_TOOMANYEVENTS",
+        923 => "IRCERR_NOWHISPER",
+        924 => "IRCERR_NOSUCHOBJECT",
+        925 => "IRCERR_NOTSUPPORTED",
+        926 => "IRCERR_CHANNELEXIST",
+        927 => "IRCERR_ALREADYONCHANNEL",
+        999 => "IRCERR_UNKNOWNERROR",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// One parsed line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub prefix: Option<Prefix>,
+    pub command: Command,
+    /// Parameters before the trailing one.
+    pub params: Vec<String>,
+    /// The `:`-introduced last parameter, without the `:`.
+    pub trailing: Option<String>,
+}
+
+impl Message {
+    /// Parses one line, with or without its CRLF. Returns `None` for a blank line.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut rest = line.trim_end_matches(['\r', '\n']).trim_start_matches(' ');
+
+        let prefix = match rest.strip_prefix(':') {
+            Some(stripped) => {
+                let (prefix, after) = stripped.split_once(' ')?;
+                rest = after.trim_start_matches(' ');
+                Some(Prefix::parse(prefix))
+            }
+            None => None,
+        };
+
+        let (word, after) = rest.split_once(' ').unwrap_or((rest, ""));
+        if word.is_empty() {
+            return None;
+        }
+        let command = Command::parse(word);
+        rest = after;
+
+        let mut params = Vec::new();
+        let mut trailing = None;
+        while !rest.is_empty() {
+            if let Some(text) = rest.strip_prefix(':') {
+                trailing = Some(text.to_string());
+                break;
+            }
+            let (param, after) = rest.split_once(' ').unwrap_or((rest, ""));
+            if !param.is_empty() {
+                params.push(param.to_string());
+            }
+            rest = after;
+        }
+
+        Some(Message {
+            prefix,
+            command,
+            params,
+            trailing,
+        })
+    }
+
+    /// Sender's nickname (or server name).
+    pub fn source(&self) -> Option<&str> {
+        self.prefix.as_ref().map(|p| p.name.as_str())
+    }
+
+    /// Parameter `i`, counting the trailing parameter as the last one.
+    pub fn arg(&self, i: usize) -> Option<&str> {
+        match self.params.get(i) {
+            Some(param) => Some(param),
+            None if i == self.params.len() => self.trailing.as_deref(),
+            None => None,
+        }
+    }
+
+    /// The last parameter, which for messages is their text.
+    pub fn text(&self) -> Option<&str> {
+        self.trailing
+            .as_deref()
+            .or_else(|| self.params.last().map(String::as_str))
+    }
+}
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(prefix) = &self.prefix {
+            write!(f, ":{} ", prefix)?;
+        }
+        write!(f, "{}", self.command)?;
+        for param in &self.params {
+            write!(f, " {}", param)?;
+        }
+        if let Some(trailing) = &self.trailing {
+            write!(f, " :{}", trailing)?;
+        }
+        Ok(())
+    }
+}
+
+/// Which connection a message arrived on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Channel,
+    Directory,
+}
+
+pub type MessageSubscriber = dyn Fn(Source, &Message) + Send + Sync;
+
+static SUBSCRIBERS: Subscribers<MessageSubscriber> = Subscribers::new();
+
+/// Registers `subscriber` for every line the OCX receives. Returns an ID for
+/// [`unsubscribe`].
+///
+/// Subscribers run on the UI thread inside the recv hooks, before the OCX sees the line,
+/// and must not block.
+pub fn subscribe(subscriber: impl Fn(Source, &Message) + Send + Sync + 'static) -> u64 {
+    SUBSCRIBERS.add(Arc::new(subscriber))
+}
+
+/// Removes a subscriber registered with [`subscribe`].
+pub fn unsubscribe(id: u64) {
+    SUBSCRIBERS.remove(id);
+}
+
+/// Parses `line` and hands it to every subscriber. Lines that do not parse are skipped.
+// Called from the recv hooks, which only exist on 32-bit Windows.
+#[cfg_attr(not(all(windows, target_arch = "x86")), allow(dead_code))]
+pub(crate) fn publish(source: Source, line: &str) {
+    let subscribers = SUBSCRIBERS.snapshot();
+    if subscribers.is_empty() {
+        return;
+    }
+    let Some(message) = Message::parse(line) else {
+        return;
+    };
+    for subscriber in &subscribers {
+        subscriber(source, &message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefix(name: &str, user: Option<&str>, host: Option<&str>) -> Option<Prefix> {
+        Some(Prefix {
+            name: name.to_string(),
+            user: user.map(str::to_string),
+            host: host.map(str::to_string),
+        })
+    }
+
+    #[test]
+    fn lines_are_split_into_their_parts() {
+        for (line, expected_prefix, command, params, trailing) in [
+            (
+                ":nick!user@host PRIVMSG #room :hello there",
+                prefix("nick", Some("user"), Some("host")),
+                Command::Privmsg,
+                &["#room"][..],
+                Some("hello there"),
+            ),
+            (
+                ":irc7.example 001 me :Welcome",
+                prefix("irc7.example", None, None),
+                Command::Numeric(1),
+                &["me"],
+                Some("Welcome"),
+            ),
+            (
+                ":nick@host NOTICE me :hi",
+                prefix("nick", None, Some("host")),
+                Command::Notice,
+                &["me"],
+                Some("hi"),
+            ),
+            (
+                ":a!b@c WHISPER %#room them :psst\r\n",
+                prefix("a", Some("b"), Some("c")),
+                Command::Whisper,
+                &["%#room", "them"],
+                Some("psst"),
+            ),
+            (
+                "PROP %#room ONJOIN :Welcome in",
+                None,
+                Command::Prop,
+                &["%#room", "ONJOIN"],
+                Some("Welcome in"),
+            ),
+            (
+                "DATA %#room them TAG :payload",
+                None,
+                Command::Data,
+                &["%#room", "them", "TAG"],
+                Some("payload"),
+            ),
+            (
+                "ACCESS %#room ADD OWNER *!*@* 0 :reason",
+                None,
+                Command::Access,
+                &["%#room", "ADD", "OWNER", "*!*@*", "0"],
+                Some("reason"),
+            ),
+            (
+                "event add channel",
+                None,
+                Command::Event,
+                &["add", "channel"],
+                None,
+            ),
+            (
+                ":srv 818 me %#room OWNERKEY :secret",
+                prefix("srv", None, None),
+                Command::Numeric(818),
+                &["me", "%#room", "OWNERKEY"],
+                Some("secret"),
+            ),
+            (
+                ":srv 913 me %#room :No access",
+                prefix("srv", None, None),
+                Command::Numeric(913),
+                &["me", "%#room"],
+                Some("No access"),
+            ),
+            // An empty trailing parameter is still there.
+            ("TOPIC #room :", None, Command::Topic, &["#room"], Some("")),
+            // Repeated spaces separate, they do not make empty parameters.
+            (
+                "  :srv   MODE  #room   +o   nick  ",
+                prefix("srv", None, None),
+                Command::Mode,
+                &["#room", "+o", "nick"],
+                None,
+            ),
+            (
+                "PING :irc7 :with colon",
+                None,
+                Command::Ping,
+                &[],
+                Some("irc7 :with colon"),
+            ),
+            ("KNOCK #room", None, Command::Knock, &["#room"], None),
+            (
+                "ircvers IRC8 MSN-OCX",
+                None,
+                Command::Other("IRCVERS".to_string()),
+                &["IRC8", "MSN-OCX"],
+                None,
+            ),
+            // Not three digits: just another command.
+            (
+                "1234 x",
+                None,
+                Command::Other("1234".to_string()),
+                &["x"],
+                None,
+            ),
+        ] {
+            let message = Message::parse(line).unwrap_or_else(|| panic!("{:?}", line));
+            assert_eq!(message.prefix, expected_prefix, "{:?}", line);
+            assert_eq!(message.command, command, "{:?}", line);
+            assert_eq!(message.params, params, "{:?}", line);
+            assert_eq!(message.trailing.as_deref(), trailing, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn lines_without_a_command_do_not_parse() {
+        for line in ["", "\r\n", "   ", ":prefix-only", ":srv ", ":srv    \r\n"] {
+            assert_eq!(Message::parse(line), None, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn prefixes_and_args() {
+        let message = Message::parse(":nick!user@host KICK #room them :bye").unwrap();
+        assert_eq!(message.source(), Some("nick"));
+        assert!(message.prefix.as_ref().unwrap().is_user());
+        assert_eq!(message.arg(0), Some("#room"));
+        assert_eq!(message.arg(1), Some("them"));
+        assert_eq!(message.arg(2), Some("bye"));
+        assert_eq!(message.arg(3), None);
+        assert_eq!(message.text(), Some("bye"));
+
+        let message = Message::parse(":irc7.example JOIN %#room").unwrap();
+        assert!(!message.prefix.as_ref().unwrap().is_user());
+        assert_eq!(message.text(), Some("%#room"));
+        assert_eq!(Message::parse("QUIT").unwrap().text(), None);
+    }
+
+    #[test]
+    fn numerics_are_named_and_classified() {
+        for (numeric, name) in [
+            (800, Some("IRCRPL_IRCX")),
+            (818, Some("IRCRPL_PROPLIST")),
+            (820, Some("IRCRPL_ACCESSCLEAR")),
+            (900, Some("IRCERR_BADCOMMAND")),
+            (913, Some("IRCERR_NOACCESS")),
+            (999, Some("IRCERR_UNKNOWNERROR")),
+            (814, None),
+            (1, None),
+            (433, None),
+        ] {
+            assert_eq!(numeric_name(numeric), name, "{}", numeric);
+        }
+        assert!(Command::Numeric(818).is_ircx_reply());
+        assert!(!Command::Numeric(818).is_ircx_error());
+        assert!(Command::Numeric(913).is_ircx_error());
+        assert!(!Command::Numeric(433).is_ircx_reply());
+        assert!(!Command::Numeric(433).is_ircx_error());
+        assert_eq!(Command::Numeric(1).to_string(), "001");
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for (line, displayed) in [
+            (
+                ":nick!user@host PRIVMSG #room :hello there",
+                ":nick!user@host PRIVMSG #room :hello there",
+            ),
+            (":srv 001 me :Welcome\r\n", ":srv 001 me :Welcome"),
+            ("whisper %#room them :hi", "WHISPER %#room them :hi"),
+            ("TOPIC #room :", "TOPIC #room :"),
+            ("  MODE   #room  +o  nick ", "MODE #room +o nick"),
+            ("ircvers IRC8 MSN-OCX", "IRCVERS IRC8 MSN-OCX"),
+            (":a@h NOTICE me :x", ":a@h NOTICE me :x"),
+        ] {
+            let message = Message::parse(line).unwrap();
+            assert_eq!(message.to_string(), displayed, "{:?}", line);
+            assert_eq!(Message::parse(displayed), Some(message), "{:?}", line);
+        }
+    }
+}
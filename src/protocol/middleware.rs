@@ -0,0 +1,293 @@
+//! Middlewares that see every command the OCX sends before it is forwarded.
+//!
+//! The channel and directory send hooks run each typed command through the registered
+//! middlewares in ascending `order`. A middleware returns the commands to pass on: the one
+//! it was given (to inspect it), a changed one (to rewrite it), none (to block it) or
+//! several (to expand it). Each command it returns goes through the remaining middlewares.
+//!
+//! Middlewares are registered at startup by [`init`] and can be switched off by name with
+//! `[middleware] disabled = ["..."]` in `config.toml`.
+
+use std::sync::{Arc, RwLock};
+
+use super::split;
+use super::{ChannelCommand, DirectoryCommand};
+use crate::config::MiddlewareConfig;
+
+/// Order of the built-in splitter: after anything that rewrites message text.
+pub const ORDER_SPLIT: i32 = 900;
+
+pub trait Middleware: Send + Sync {
+    /// Name used in `[middleware] disabled` and in logs.
+    fn name(&self) -> &str;
+
+    /// Called for each command on the channel connection.
+    fn on_channel(&self, command: ChannelCommand) -> Vec<ChannelCommand> {
+        vec![command]
+    }
+
+    /// Called for each command on the directory connection.
+    fn on_directory(&self, command: DirectoryCommand) -> Vec<DirectoryCommand> {
+        vec![command]
+    }
+}
+
+struct Entry {
+    order: i32,
+    middleware: Arc<dyn Middleware>,
+}
+
+struct Pipeline {
+    entries: Vec<Entry>,
+    disabled: Vec<String>,
+}
+
+static PIPELINE: RwLock<Pipeline> = RwLock::new(Pipeline {
+    entries: Vec::new(),
+    disabled: Vec::new(),
+});
+
+/// Applies `[middleware]` and registers the built-in middlewares.
+pub fn init(config: &MiddlewareConfig) {
+    configure(config);
+    register(ORDER_SPLIT, SplitLongMessages);
+}
+
+/// Applies `[middleware]`.
+pub fn configure(config: &MiddlewareConfig) {
+    if let Ok(mut pipeline) = PIPELINE.write() {
+        pipeline.disabled = config.disabled.clone();
+    }
+}
+
+/// Adds `middleware` to the pipeline. Middlewares with a lower `order` run first; equal
+/// orders run in registration order.
+pub fn register(order: i32, middleware: impl Middleware + 'static) {
+    let Ok(mut pipeline) = PIPELINE.write() else {
+        return;
+    };
+    log::info!(
+        "Registered outbound middleware {} (order {})",
+        middleware.name(),
+        order
+    );
+    pipeline.insert(order, Arc::new(middleware));
+}
+
+impl Pipeline {
+    fn insert(&mut self, order: i32, middleware: Arc<dyn Middleware>) {
+        let at = self.entries.partition_point(|e| e.order <= order);
+        self.entries.insert(at, Entry { order, middleware });
+    }
+
+    /// The enabled middlewares, in order.
+    fn active(&self) -> Vec<Arc<dyn Middleware>> {
+        self.entries
+            .iter()
+            .filter(|e| {
+                !self
+                    .disabled
+                    .iter()
+                    .any(|name| name.eq_ignore_ascii_case(e.middleware.name()))
+            })
+            .map(|e| e.middleware.clone())
+            .collect()
+    }
+}
+
+/// The enabled middlewares, in order. Cloned so a middleware can register others.
+fn active() -> Vec<Arc<dyn Middleware>> {
+    PIPELINE
+        .read()
+        .map(|pipeline| pipeline.active())
+        .unwrap_or_default()
+}
+
+/// Runs a channel command through the pipeline and returns what should be sent.
+pub fn run_channel(command: ChannelCommand) -> Vec<ChannelCommand> {
+    run_through(&active(), command, |m, c| m.on_channel(c))
+}
+
+/// Runs a directory command through the pipeline and returns what should be sent.
+pub fn run_directory(command: DirectoryCommand) -> Vec<DirectoryCommand> {
+    run_through(&active(), command, |m, c| m.on_directory(c))
+}
+
+fn run_through<C>(
+    middlewares: &[Arc<dyn Middleware>],
+    command: C,
+    step: impl Fn(&dyn Middleware, C) -> Vec<C>,
+) -> Vec<C> {
+    let mut commands = vec![command];
+    for middleware in middlewares {
+        commands = commands
+            .into_iter()
+            .flat_map(|c| step(middleware.as_ref(), c))
+            .collect();
+    }
+    commands
+}
+
+/// Sends each of `commands` in turn, stopping at the first that fails. True if none did,
+/// including when a middleware blocked the command and there is nothing to send.
+pub fn send_all<C>(commands: Vec<C>, send: impl FnMut(C) -> bool) -> bool {
+    commands.into_iter().all(send)
+}
+
+/// Sends over-long channel messages as several, using [`split::split_outgoing`].
+struct SplitLongMessages;
+
+impl Middleware for SplitLongMessages {
+    fn name(&self) -> &str {
+        "split"
+    }
+
+    fn on_channel(&self, command: ChannelCommand) -> Vec<ChannelCommand> {
+        let Some(pieces) = split::split_outgoing(&command) else {
+            return vec![command];
+        };
+        log::info!(
+            "Splitting {} bytes of outgoing text into {} message(s)",
+            command.message_text().map_or(0, str::len),
+            pieces.len()
+        );
+        pieces
+            .into_iter()
+            .map(|piece| command.with_message_text(piece))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Appends its name to the text of every command.
+    struct Tag(&'static str);
+
+    impl Middleware for Tag {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn on_channel(&self, command: ChannelCommand) -> Vec<ChannelCommand> {
+            let text = format!("{}{}", command.message_text().unwrap_or(""), self.0);
+            vec![command.with_message_text(text)]
+        }
+    }
+
+    /// Drops every command.
+    struct Block;
+
+    impl Middleware for Block {
+        fn name(&self) -> &str {
+            "block"
+        }
+
+        fn on_channel(&self, _: ChannelCommand) -> Vec<ChannelCommand> {
+            Vec::new()
+        }
+    }
+
+    /// Sends every command twice.
+    struct Double;
+
+    impl Middleware for Double {
+        fn name(&self) -> &str {
+            "double"
+        }
+
+        fn on_channel(&self, command: ChannelCommand) -> Vec<ChannelCommand> {
+            vec![command.clone(), command]
+        }
+    }
+
+    fn pipeline(middlewares: Vec<(i32, Arc<dyn Middleware>)>) -> Pipeline {
+        let mut pipeline = Pipeline {
+            entries: Vec::new(),
+            disabled: Vec::new(),
+        };
+        for (order, middleware) in middlewares {
+            pipeline.insert(order, middleware);
+        }
+        pipeline
+    }
+
+    fn privmsg(text: &str) -> ChannelCommand {
+        ChannelCommand::from_ocx(35, &[Some("#test"), Some(text)]).unwrap()
+    }
+
+    fn run(pipeline: &Pipeline, command: ChannelCommand) -> Vec<ChannelCommand> {
+        run_through(&pipeline.active(), command, |m, c| m.on_channel(c))
+    }
+
+    fn texts(commands: &[ChannelCommand]) -> Vec<&str> {
+        commands.iter().filter_map(|c| c.message_text()).collect()
+    }
+
+    #[test]
+    fn middlewares_run_by_order_then_registration() {
+        let pipeline = pipeline(vec![
+            (20, Arc::new(Tag("c"))),
+            (10, Arc::new(Tag("a"))),
+            (10, Arc::new(Tag("b"))),
+        ]);
+        assert_eq!(texts(&run(&pipeline, privmsg(">"))), [">abc"]);
+    }
+
+    #[test]
+    fn a_blocked_command_is_not_sent_and_reports_success() {
+        let pipeline = pipeline(vec![(10, Arc::new(Block)), (20, Arc::new(Tag("x")))]);
+        let commands = run(&pipeline, privmsg("hi"));
+        assert!(commands.is_empty());
+        assert!(send_all(commands, |_| panic!("a blocked command was sent")));
+
+        let mut sent = Vec::new();
+        let all = send_all(vec![1, 2, 3], |n| {
+            sent.push(n);
+            n != 2
+        });
+        assert!(!all);
+        assert_eq!(sent, [1, 2]);
+    }
+
+    #[test]
+    fn an_expanded_command_goes_through_the_rest_of_the_pipeline() {
+        let pipeline = pipeline(vec![
+            (10, Arc::new(Double)),
+            (20, Arc::new(Double)),
+            (30, Arc::new(Tag("!"))),
+        ]);
+        assert_eq!(texts(&run(&pipeline, privmsg("hi"))), ["hi!"; 4]);
+    }
+
+    #[test]
+    fn disabled_middlewares_are_skipped_by_name() {
+        let mut pipeline = pipeline(vec![
+            (10, Arc::new(Tag("a"))),
+            (20, Arc::new(Block)),
+            (30, Arc::new(Tag("b"))),
+        ]);
+        pipeline.disabled = vec!["BLOCK".to_string(), "a".to_string()];
+        assert_eq!(texts(&run(&pipeline, privmsg(">"))), [">b"]);
+    }
+
+    #[test]
+    fn long_messages_are_split_after_the_middlewares_that_rewrite_them() {
+        let pipeline = pipeline(vec![
+            (ORDER_SPLIT, Arc::new(SplitLongMessages)),
+            (ORDER_SPLIT + 1, Arc::new(Tag("|"))),
+            (100, Arc::new(Tag(" end"))),
+        ]);
+        let commands = run(&pipeline, privmsg(&"word ".repeat(200)));
+        assert!(commands.len() > 1);
+        let pieces = texts(&commands);
+        // The text was split after " end" was appended, then every piece was tagged.
+        assert!(pieces.last().unwrap().ends_with(" end|"));
+        for (command, piece) in commands.iter().zip(&pieces) {
+            assert!(piece.ends_with('|'));
+            assert!(command.to_string().len() <= split::MAX_LINE_LEN);
+        }
+        assert_eq!(texts(&run(&pipeline, privmsg("hi"))), ["hi end|"]);
+    }
+}
@@ -0,0 +1,110 @@
+//! Typed IRCX traffic: commands as the OCX sends them and messages as it receives them.
+//!
+//! The channel and directory send hooks receive a command id plus up to nine string
+//! arguments. The two id spaces differ (NICK is 28 on the channel side and 14 on the
+//! directory side), so each has its own enum. Both convert from the OCX's `(id, args)`
+//! tuple, serialize to the wire form the OCX would write (`Display`), and parse that wire
+//! form back. Inbound lines are parsed into [`message::Message`]s for Rust subscribers, and
+//! [`split`] breaks message text that is too long for one line. Outgoing commands pass
+//! through the [`middleware`] pipeline before the send hooks forward them.
+
+pub mod channel;
+pub mod directory;
+pub mod message;
+pub mod middleware;
+pub mod split;
+
+pub use channel::ChannelCommand;
+pub use directory::DirectoryCommand;
+pub use message::Message;
+
+use std::ffi::{CString, NulError};
+
+/// The OCX's string arguments, in order, with null pointers as `None`.
+pub type OcxArgs<'a> = [Option<&'a str>];
+
+/// Looks up argument `i`, treating a missing slot like a null pointer.
+pub(crate) fn arg(args: &OcxArgs, i: usize) -> Option<String> {
+    args.get(i).copied().flatten().map(str::to_string)
+}
+
+/// Owned, NUL-terminated copies of a command's arguments for passing back to the OCX.
+/// Fails if an argument contains a NUL byte, which the OCX would take as its end.
+#[cfg_attr(not(all(windows, target_arch = "x86")), allow(dead_code))]
+pub(crate) fn to_cstrings(args: &OcxArgs) -> Result<Vec<Option<CString>>, NulError> {
+    args.iter()
+        .map(|arg| arg.map(CString::new).transpose())
+        .collect()
+}
+
+/// Pointer to argument `i` of `args`, or null for a missing one.
+#[cfg_attr(not(all(windows, target_arch = "x86")), allow(dead_code))]
+pub(crate) fn cstring_arg(args: &[Option<CString>], i: usize) -> *const u8 {
+    match args.get(i) {
+        Some(Some(arg)) => arg.as_ptr() as *const u8,
+        _ => std::ptr::null(),
+    }
+}
+
+/// Cursor over the parameters of a wire line, after the command word.
+pub(crate) struct Params<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Params<'a> {
+    /// Splits `line` into its upper-cased command word and the parameters that follow.
+    pub(crate) fn split(line: &'a str) -> (String, Self) {
+        let line = line.trim_end_matches(['\r', '\n']);
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        (command.to_ascii_uppercase(), Params { rest })
+    }
+
+    /// Next space-delimited parameter, or `None` at the end of the line or at a trailing
+    /// (`:`-prefixed) parameter.
+    pub(crate) fn word(&mut self) -> Option<String> {
+        if self.rest.is_empty() || self.rest.starts_with(':') {
+            return None;
+        }
+        let (word, rest) = self.rest.split_once(' ').unwrap_or((self.rest, ""));
+        self.rest = rest;
+        Some(word.to_string())
+    }
+
+    /// The trailing parameter, or the rest of the line if it lacks the `:`.
+    pub(crate) fn text(&mut self) -> Option<String> {
+        self.trailing().or_else(|| self.remainder())
+    }
+
+    /// The trailing parameter with its `:` removed, or `None` if there is none.
+    pub(crate) fn trailing(&mut self) -> Option<String> {
+        let text = self.rest.strip_prefix(':')?;
+        self.rest = "";
+        Some(text.to_string())
+    }
+
+    /// Everything left on the line, verbatim, or `None` if nothing is left.
+    pub(crate) fn remainder(&mut self) -> Option<String> {
+        if self.rest.is_empty() {
+            return None;
+        }
+        Some(std::mem::take(&mut self.rest).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_cstrings_keeps_missing_args_and_rejects_nul() {
+        let args = to_cstrings(&[Some("#room"), None, Some("hi")]).unwrap();
+        assert_eq!(args[0].as_deref(), Some(c"#room"));
+        assert!(args[1].is_none());
+        assert!(cstring_arg(&args, 1).is_null());
+        assert!(cstring_arg(&args, 3).is_null());
+        assert!(!cstring_arg(&args, 2).is_null());
+
+        let err = to_cstrings(&[Some("#room"), Some("a\0b")]).unwrap_err();
+        assert_eq!(err.nul_position(), 1);
+    }
+}
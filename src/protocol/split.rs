@@ -0,0 +1,287 @@
+//! Splitting message text that does not fit in one IRC line.
+//!
+//! IRC lines are limited to 512 bytes including CRLF, and the OCX drops the connection when
+//! it receives a longer one (IRC7 issue #199). Long PRIVMSG, NOTICE and WHISPER text is cut
+//! into pieces that each fit in a line of their own, on UTF-8 character boundaries and,
+//! where possible, at spaces. CTCP-wrapped text (`\x01TAG ...\x01`) keeps its wrapper on
+//! every piece; for MSN styled text (`\x01S <font> ...\x01`) the font token is kept too.
+//!
+//! Inbound lines are split in the channel recv hook; outgoing messages are split in the
+//! channel send hook, leaving room for the prefix the server adds when relaying them.
+
+use super::ChannelCommand;
+use super::message::{Command, Message};
+
+/// Longest line, in bytes, excluding CRLF.
+pub const MAX_LINE_LEN: usize = 510;
+
+/// Below this many bytes per piece a split would be unreadable, so the line is summarised.
+const MIN_PIECE_LEN: usize = 64;
+
+/// Longest sender name kept in the line that replaces a message too large to show.
+const MAX_SOURCE_LEN: usize = 64;
+
+/// Bytes kept free in outgoing lines for the `:nick!user@host ` prefix the server adds
+/// when it relays them.
+const RELAY_PREFIX_LEN: usize = 100;
+
+const CTCP_DELIM: char = '\x01';
+
+/// Splits `text` into pieces of at most `max_bytes` bytes. Cuts fall at the last space that
+/// fits (the space itself is dropped), or at the last character boundary when a single word
+/// is longer than `max_bytes`. A character longer than `max_bytes` makes a piece of its own.
+pub fn split_text(text: &str, max_bytes: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = text;
+    while rest.len() > max_bytes {
+        let mut cut = max_bytes;
+        while cut > 0 && !rest.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        if cut == 0 {
+            // `max_bytes` is shorter than the first character: it goes in a piece of its own.
+            cut = rest.chars().next().map_or(rest.len(), char::len_utf8);
+            pieces.push(&rest[..cut]);
+            rest = &rest[cut..];
+            continue;
+        }
+        match rest[..cut].rfind(' ').filter(|&space| space > 0) {
+            Some(space) => {
+                pieces.push(&rest[..space]);
+                rest = &rest[space + 1..];
+            }
+            None => {
+                pieces.push(&rest[..cut]);
+                rest = &rest[cut..];
+            }
+        }
+    }
+    if !rest.is_empty() || pieces.is_empty() {
+        pieces.push(rest);
+    }
+    pieces
+}
+
+/// Splits message text so each piece, re-wrapped, is at most `max_bytes` bytes. Returns
+/// `None` if the CTCP header leaves less than [`MIN_PIECE_LEN`] bytes for the text itself.
+pub fn split_payload(text: &str, max_bytes: usize) -> Option<Vec<String>> {
+    let Some(inner) = text.strip_prefix(CTCP_DELIM) else {
+        if max_bytes < MIN_PIECE_LEN {
+            return None;
+        }
+        return Some(
+            split_text(text, max_bytes)
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+        );
+    };
+
+    let inner = inner.strip_suffix(CTCP_DELIM).unwrap_or(inner);
+    let header_len = ctcp_header_len(inner);
+    let (header, body) = inner.split_at(header_len);
+    // Leading and closing delimiters around every piece.
+    let room = max_bytes.checked_sub(header.len() + 2)?;
+    if room < MIN_PIECE_LEN {
+        return None;
+    }
+    Some(
+        split_text(body, room)
+            .into_iter()
+            .map(|piece| format!("{0}{1}{2}{0}", CTCP_DELIM, header, piece))
+            .collect(),
+    )
+}
+
+/// Length of the part of a CTCP body that has to be repeated on every piece: the tag and its
+/// trailing space, plus the font token for MSN styled text.
+fn ctcp_header_len(inner: &str) -> usize {
+    let Some(tag_end) = inner.find(' ') else {
+        return inner.len();
+    };
+    let header_end = tag_end + 1;
+    if &inner[..tag_end] != "S" {
+        return header_end;
+    }
+    match inner[header_end..].find(' ') {
+        Some(font_end) => header_end + font_end + 1,
+        None => inner.len(),
+    }
+}
+
+/// True for the commands whose text may be split across several lines.
+pub fn is_splittable(command: &Command) -> bool {
+    matches!(
+        command,
+        Command::Privmsg | Command::Notice | Command::Whisper
+    )
+}
+
+/// Rewrites an inbound line longer than [`MAX_LINE_LEN`] as lines that fit. Returns `None`
+/// if the line is short enough or is not a PRIVMSG, NOTICE or WHISPER with text.
+///
+/// Each new line keeps the original prefix, command and target. When the text cannot be
+/// split sensibly (the prefix and target take up nearly the whole line), a single line
+/// saying how much was left out takes its place, and when not even that fits, the fixed
+/// line built by [`too_large_line`].
+pub fn split_inbound(line: &str) -> Option<Vec<String>> {
+    if line.len() <= MAX_LINE_LEN {
+        return None;
+    }
+    let mut message = Message::parse(line)?;
+    if !is_splittable(&message.command) {
+        return None;
+    }
+    let text = message.trailing.take()?;
+    let head = message.to_string();
+    // " :" between the head and the text.
+    let room = MAX_LINE_LEN.saturating_sub(head.len() + 2);
+
+    let pieces = split_payload(&text, room)
+        .unwrap_or_else(|| vec![format!("[{} byte message too long to display]", text.len())]);
+    if pieces.iter().any(|piece| piece.len() > room) {
+        return Some(vec![too_large_line(&message, line.len())]);
+    }
+    Some(
+        pieces
+            .into_iter()
+            .map(|piece| format!("{} :{}", head, piece))
+            .collect(),
+    )
+}
+
+/// A short line standing in for a message of `len` bytes whose prefix and target leave no
+/// room for its text: `:<nick> <COMMAND> <target> :message from <nick> too large (<len>
+/// bytes)`. The prefix is cut down to the nick, and `*` replaces a target that is itself
+/// too long.
+fn too_large_line(message: &Message, len: usize) -> String {
+    let mut source = message.source().unwrap_or("server");
+    if source.len() > MAX_SOURCE_LEN {
+        let mut cut = MAX_SOURCE_LEN;
+        while !source.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        source = &source[..cut];
+    }
+    let text = format!("message from {} too large ({} bytes)", source, len);
+    let target = message.params.first().map_or("*", String::as_str);
+    let line = format!(":{} {} {} :{}", source, message.command, target, text);
+    if line.len() <= MAX_LINE_LEN {
+        line
+    } else {
+        format!(":{} {} * :{}", source, message.command, text)
+    }
+}
+
+/// Splits the text of an outgoing message that would be too long once the server relays it
+/// with the sender's prefix. Returns `None` if `command` carries no text, fits as it is, or
+/// cannot be split.
+pub fn split_outgoing(command: &ChannelCommand) -> Option<Vec<String>> {
+    let text = command.message_text()?;
+    let limit = MAX_LINE_LEN - RELAY_PREFIX_LEN;
+    let line_len = command.to_string().len();
+    if line_len <= limit {
+        return None;
+    }
+    let room = limit.checked_sub(line_len - text.len())?;
+    split_payload(text, room)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_inbound_fits_every_piece() {
+        let line = format!(":nick!user@host PRIVMSG #room :{}", "word ".repeat(300));
+        let lines = split_inbound(&line).unwrap();
+        assert!(lines.len() > 1);
+        for piece in &lines {
+            assert!(piece.len() <= MAX_LINE_LEN);
+            assert!(piece.starts_with(":nick!user@host PRIVMSG #room :"));
+        }
+    }
+
+    #[test]
+    fn split_inbound_replaces_a_message_with_no_room_for_text() {
+        let target = format!("#{}", "r".repeat(600));
+        let line = format!(":nick!user@host PRIVMSG {} :hello", target);
+        let lines = split_inbound(&line).unwrap();
+        assert_eq!(
+            lines,
+            [format!(
+                ":nick PRIVMSG * :message from nick too large ({} bytes)",
+                line.len()
+            )]
+        );
+    }
+
+    #[test]
+    fn split_text_cuts_at_spaces_and_character_boundaries() {
+        assert_eq!(split_text("one two three", 8), ["one two", "three"]);
+        assert_eq!(split_text("abcdefghij", 4), ["abcd", "efgh", "ij"]);
+        // "é" is two bytes: no piece ends half way through one.
+        assert_eq!(split_text("ééééé", 5), ["éé", "éé", "é"]);
+        // A leading space is not a place to cut.
+        assert_eq!(split_text(" abcdef", 4), [" abc", "def"]);
+        assert_eq!(split_text("", 4), [""]);
+    }
+
+    #[test]
+    fn split_text_hard_splits_characters_longer_than_a_piece() {
+        assert_eq!(split_text("€€a", 2), ["€", "€", "a"]);
+        assert_eq!(split_text("a€", 1), ["a", "€"]);
+    }
+
+    #[test]
+    fn split_payload_keeps_the_ctcp_and_font_headers_on_every_piece() {
+        let body = "word ".repeat(60);
+        let text = format!("\x01S Tahoma;0 {}\x01", body.trim_end());
+        let pieces = split_payload(&text, 100).unwrap();
+        assert!(pieces.len() > 1);
+        for piece in &pieces {
+            assert!(piece.len() <= 100);
+            assert!(piece.starts_with("\x01S Tahoma;0 "));
+            assert!(piece.ends_with('\x01'));
+        }
+
+        let text = format!("\x01ACTION {}\x01", body.trim_end());
+        let pieces = split_payload(&text, 100).unwrap();
+        assert!(pieces.len() > 1);
+        for piece in &pieces {
+            assert!(piece.len() <= 100);
+            assert!(piece.starts_with("\x01ACTION "));
+            assert!(piece.ends_with('\x01'));
+        }
+        assert_eq!(split_payload("\x01ACTION hi\x01", 60), None);
+    }
+
+    #[test]
+    fn split_outgoing_fits_each_message_command_under_the_relay_limit() {
+        let limit = MAX_LINE_LEN - RELAY_PREFIX_LEN;
+        let words: Vec<String> = (0..200).map(|i| format!("w{i}é")).collect();
+        let text = words.join(" ");
+        for (id, args) in [
+            (10, vec![Some("#room"), Some(text.as_str())]),
+            (24, vec![Some("#room"), Some(text.as_str())]),
+            (29, vec![Some("#room"), Some(text.as_str())]),
+            (35, vec![Some("#room"), Some(text.as_str())]),
+            (48, vec![Some("#room"), Some("nick"), Some(text.as_str())]),
+        ] {
+            let command = ChannelCommand::from_ocx(id, &args).unwrap();
+            let pieces = split_outgoing(&command).unwrap();
+            assert!(pieces.len() > 1, "command {id} was not split");
+            for piece in &pieces {
+                let line = command.with_message_text(piece.clone()).to_string();
+                assert!(line.len() <= limit, "command {id}: {} bytes", line.len());
+            }
+            // Only the spaces that were cut at are gone, and the pieces stay in order.
+            assert_eq!(pieces.join(" "), text, "command {id}");
+        }
+
+        let short = ChannelCommand::from_ocx(35, &[Some("#room"), Some("hi")]).unwrap();
+        assert_eq!(split_outgoing(&short), None);
+        let no_text = ChannelCommand::from_ocx(28, &[Some("nick")]).unwrap();
+        assert_eq!(split_outgoing(&no_text), None);
+    }
+}
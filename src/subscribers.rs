@@ -0,0 +1,51 @@
+//! Registry of callbacks, shared by the line subscribers in `network::framing` and the
+//! message subscribers in `protocol::message`.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// A list of subscribers of type `F` (usually a `dyn Fn`), each with the ID it was given.
+pub struct Subscribers<F: ?Sized> {
+    list: RwLock<Vec<(u64, Arc<F>)>>,
+    next_id: AtomicU64,
+}
+
+impl<F: ?Sized> Subscribers<F> {
+    pub const fn new() -> Self {
+        Self {
+            list: RwLock::new(Vec::new()),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Adds `subscriber` and returns its ID for [`remove`](Self::remove).
+    pub fn add(&self, subscriber: Arc<F>) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        if let Ok(mut list) = self.list.write() {
+            list.push((id, subscriber));
+        }
+        id
+    }
+
+    /// Removes the subscriber with ID `id`, if it is still registered.
+    pub fn remove(&self, id: u64) {
+        if let Ok(mut list) = self.list.write() {
+            list.retain(|(sid, _)| *sid != id);
+        }
+    }
+
+    /// The current subscribers, cloned so they can (un)subscribe while being called without
+    /// deadlocking.
+    pub fn snapshot(&self) -> Vec<Arc<F>> {
+        self.list
+            .read()
+            .map(|list| list.iter().map(|(_, s)| s.clone()).collect())
+            .unwrap_or_default()
+    }
+}
+
+impl<F: ?Sized> Default for Subscribers<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}